@@ -0,0 +1,292 @@
+use std::borrow::Cow;
+
+/// Physical meaning of a FITS axis deduced from its CTYPE
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AxisKind {
+    Longitude,
+    Latitude,
+    Spectral,
+    Stokes,
+    Time,
+    Other,
+}
+
+impl AxisKind {
+    pub fn from_ctype(ctype: Option<&str>) -> Self {
+        // the axis type is given by the first letters of CTYPE, e.g. RA---SIN, VRAD, STOKES
+        let name = ctype
+            .and_then(|c| c.trim().split('-').next())
+            .unwrap_or("")
+            .to_uppercase();
+
+        match name.as_str() {
+            "RA" => AxisKind::Longitude,
+            "DEC" => AxisKind::Latitude,
+            "FREQ" | "ENER" | "WAVN" | "VRAD" | "WAVE" | "VOPT" | "ZOPT" | "AWAV" | "VELO" | "BETA" | "FELO" | "VELOCITY" => {
+                AxisKind::Spectral
+            }
+            "STOKES" => AxisKind::Stokes,
+            "TIME" | "UTC" | "TAI" | "TT" | "TDB" | "TCB" | "TCG" | "GPS" | "MJD" | "JD" | "BJD" => AxisKind::Time,
+            // e.g. GLON/GLAT, ELON/ELAT, HPLN/HPLT
+            n if n.ends_with("LON") || (n.len() == 4 && n.ends_with("LN")) => AxisKind::Longitude,
+            n if n.ends_with("LAT") || (n.len() == 4 && n.ends_with("LT")) => AxisKind::Latitude,
+            _ => AxisKind::Other,
+        }
+    }
+}
+
+/// Selection of the three FITS axes rendered as the volume
+///
+/// The remaining axes are fixed at a given index so that a 3D hyperplane
+/// can be extracted from N-dimensional cubes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AxisMapping {
+    /// FITS axes (0-based) mapped onto the x, y and z axes of the volume
+    pub volume: [usize; 3],
+    /// Index of the hyperplane along each FITS axis. Ignored for the axes of the volume
+    pub indices: Vec<u64>,
+}
+
+impl AxisMapping {
+    /// Default mapping of a cube
+    ///
+    /// The celestial and spectral axes are rendered when they are found.
+    /// Otherwise the first non-degenerated axes are chosen.
+    pub fn new(naxis: &[u64], kinds: &[AxisKind]) -> Self {
+        let mut volume = vec![];
+
+        for kind in [AxisKind::Longitude, AxisKind::Latitude, AxisKind::Spectral] {
+            if let Some(axis) = kinds.iter().position(|k| *k == kind) {
+                if axis < naxis.len() {
+                    volume.push(axis);
+                }
+            }
+        }
+
+        let unused = |axis: &usize| !volume.contains(axis);
+        let non_degenerated = (0..naxis.len()).filter(|a| naxis[*a] > 1 && unused(a));
+        let degenerated = (0..naxis.len()).filter(|a| naxis[*a] <= 1 && unused(a));
+        let completion = non_degenerated.chain(degenerated).collect::<Vec<_>>();
+        volume.extend(completion);
+
+        Self {
+            volume: [volume[0], volume[1], volume[2]],
+            indices: vec![0; naxis.len()],
+        }
+    }
+
+    /// Tell whether the mapping can be applied to a HDU of the given dimensions
+    pub fn is_valid(&self, naxis: &[u64]) -> bool {
+        let [x, y, z] = self.volume;
+
+        naxis.len() >= 3
+            && self.indices.len() == naxis.len()
+            && x != y && y != z && x != z
+            && self.volume.iter().all(|a| *a < naxis.len())
+            && self.indices.iter().zip(naxis).all(|(i, n)| i < n)
+    }
+
+    /// Map the FITS axis onto a slot (x, y or z) of the volume
+    ///
+    /// The axis previously in the slot is swapped with the given one if
+    /// the latter was already rendered.
+    pub fn set_volume_axis(&mut self, slot: usize, axis: usize) {
+        if let Some(other) = self.volume.iter().position(|a| *a == axis) {
+            self.volume[other] = self.volume[slot];
+        }
+        self.volume[slot] = axis;
+    }
+
+    /// Dimensions of the volume
+    pub fn dim(&self, naxis: &[u64]) -> (u32, u32, u32) {
+        let [x, y, z] = self.volume;
+        (naxis[x] as u32, naxis[y] as u32, naxis[z] as u32)
+    }
+
+    fn strides(naxis: &[u64]) -> Vec<usize> {
+        naxis
+            .iter()
+            .scan(1, |stride, n| {
+                let s = *stride;
+                *stride *= *n as usize;
+                Some(s)
+            })
+            .collect()
+    }
+
+    /// Offset (in pixels) of the first voxel of the hyperplane
+    fn base_offset(&self, strides: &[usize]) -> usize {
+        self.indices
+            .iter()
+            .enumerate()
+            .filter(|(axis, _)| !self.volume.contains(axis))
+            .map(|(axis, idx)| (*idx as usize) * strides[axis])
+            .sum()
+    }
+
+    /// Offset of the hyperplane if it is stored contiguously in the data unit
+    fn contiguous_offset(&self, naxis: &[u64]) -> Option<usize> {
+        if self.volume == [0, 1, 2] {
+            Some(self.base_offset(&Self::strides(naxis)))
+        } else {
+            None
+        }
+    }
+
+    /// Offsets (in pixels) in the data unit of the voxels of a box of the volume, x varying first
+    ///
+    /// The box is made of `size` voxels along each axis taken every `step` voxels.
+    fn offsets(&self, naxis: &[u64], origin: [u32; 3], size: [u32; 3], step: u32) -> impl Iterator<Item = usize> {
+        let strides = Self::strides(naxis);
+        let base = self.base_offset(&strides);
+
+        let [x, y, z] = self.volume;
+        let (sx, sy, sz) = (strides[x], strides[y], strides[z]);
+        let [ox, oy, oz] = origin.map(|o| o as usize);
+        let [nx, ny, nz] = size.map(|n| n as usize);
+        let base = base + ox * sx + oy * sy + oz * sz;
+        let (sx, sy, sz) = (sx * step as usize, sy * step as usize, sz * step as usize);
+
+        (0..nz).flat_map(move |k| {
+            (0..ny).flat_map(move |j| (0..nx).map(move |i| base + i * sx + j * sy + k * sz))
+        })
+    }
+
+    /// Predicate telling whether the pixel of the data unit at an offset is a voxel of the volume
+    pub fn in_volume(&self, naxis: &[u64]) -> impl Fn(usize) -> bool {
+        // the axes outside of the volume with their stride and the index of the hyperplane
+        let fixed = Self::strides(naxis)
+            .into_iter()
+            .zip(naxis.iter().zip(&self.indices))
+            .enumerate()
+            .filter(|(axis, _)| !self.volume.contains(axis))
+            .map(|(_, (stride, (n, idx)))| (stride, *n as usize, *idx as usize))
+            .collect::<Vec<_>>();
+
+        move |offset| fixed.iter().all(|(stride, n, idx)| (offset / stride) % n == *idx)
+    }
+
+    /// Offset (in pixels) in the data unit of a voxel of the volume
+    pub fn offset(&self, naxis: &[u64], voxel: [u32; 3]) -> usize {
+        self.offsets(naxis, voxel, [1, 1, 1], 1).next().unwrap_or(0)
+    }
+
+    fn whole(&self, naxis: &[u64]) -> [u32; 3] {
+        let (nx, ny, nz) = self.dim(naxis);
+        [nx, ny, nz]
+    }
+
+    /// Extract the volume from the values of a whole data unit
+    pub fn extract<'a, T: Clone>(&self, naxis: &[u64], values: &'a [T]) -> Cow<'a, [T]> {
+        let (nx, ny, nz) = self.dim(naxis);
+        let num_voxels = (nx as usize) * (ny as usize) * (nz as usize);
+
+        if let Some(offset) = self.contiguous_offset(naxis) {
+            Cow::Borrowed(&values[offset..(offset + num_voxels)])
+        } else {
+            Cow::Owned(self.offsets(naxis, [0; 3], self.whole(naxis), 1).map(|o| values[o].clone()).collect())
+        }
+    }
+
+    /// Extract the volume from the raw bytes of a whole data unit
+    pub fn extract_bytes<'a>(&self, naxis: &[u64], bytes: &'a [u8], num_bytes_per_pixel: usize) -> Cow<'a, [u8]> {
+        let (nx, ny, nz) = self.dim(naxis);
+        let num_bytes = (nx as usize) * (ny as usize) * (nz as usize) * num_bytes_per_pixel;
+
+        if let Some(offset) = self.contiguous_offset(naxis) {
+            let start = offset * num_bytes_per_pixel;
+            Cow::Borrowed(&bytes[start..(start + num_bytes)])
+        } else {
+            Cow::Owned(self.extract_box_bytes(naxis, bytes, num_bytes_per_pixel, [0; 3], self.whole(naxis), 1))
+        }
+    }
+
+    /// Extract a box of the volume from the raw bytes of a whole data unit
+    ///
+    /// The box has `size` voxels along each axis, taken every `step` voxels from `origin`.
+    pub fn extract_box_bytes(
+        &self,
+        naxis: &[u64],
+        bytes: &[u8],
+        num_bytes_per_pixel: usize,
+        origin: [u32; 3],
+        size: [u32; 3],
+        step: u32,
+    ) -> Vec<u8> {
+        let num_voxels = size.iter().map(|n| *n as usize).product::<usize>();
+
+        let mut volume = Vec::with_capacity(num_voxels * num_bytes_per_pixel);
+        for o in self.offsets(naxis, origin, size, step) {
+            let start = o * num_bytes_per_pixel;
+            volume.extend_from_slice(&bytes[start..(start + num_bytes_per_pixel)]);
+        }
+
+        volume
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_the_celestial_and_spectral_axes() {
+        let kinds = ["STOKES", "RA---SIN", "DEC--SIN", "FREQ"].map(|ctype| AxisKind::from_ctype(Some(ctype)));
+        assert_eq!(kinds, [AxisKind::Stokes, AxisKind::Longitude, AxisKind::Latitude, AxisKind::Spectral]);
+
+        let mapping = AxisMapping::new(&[1, 3, 4, 5], &kinds);
+        assert_eq!(mapping.volume, [1, 2, 3]);
+        assert_eq!(mapping.dim(&[1, 3, 4, 5]), (3, 4, 5));
+
+        // without known axes, the degenerated ones come last
+        let mapping = AxisMapping::new(&[1, 3, 4, 5], &[AxisKind::Other; 4]);
+        assert_eq!(mapping.volume, [1, 2, 3]);
+        let mapping = AxisMapping::new(&[3, 1, 4, 5], &[AxisKind::Other; 4]);
+        assert_eq!(mapping.volume, [0, 2, 3]);
+    }
+
+    #[test]
+    fn extract_a_hyperplane() {
+        // the value of each pixel is its offset in the data unit
+        let naxis = [2, 3, 2, 2];
+        let values = (0..24).collect::<Vec<u32>>();
+
+        // the z axis of the volume is the first FITS axis, in the second plane of the last axis
+        let mapping = AxisMapping {
+            volume: [1, 2, 0],
+            indices: vec![0, 0, 0, 1],
+        };
+        assert!(mapping.is_valid(&naxis));
+        let volume = mapping.extract(&naxis, &values);
+        assert_eq!(volume[..], [12, 14, 16, 18, 20, 22, 13, 15, 17, 19, 21, 23]);
+        assert_eq!(mapping.offset(&naxis, [2, 0, 1]), 17);
+
+        let in_volume = mapping.in_volume(&naxis);
+        assert_eq!(values.iter().filter(|v| in_volume(**v as usize)).count(), 12);
+        assert!(in_volume(12) && !in_volume(11));
+
+        let to_bytes = |values: &[u32]| values.iter().flat_map(|v| v.to_be_bytes()).collect::<Vec<_>>();
+        let bytes = to_bytes(&values);
+        let column = mapping.extract_box_bytes(&naxis, &bytes, 4, [1, 0, 0], [1, 1, 2], 1);
+        assert_eq!(column, to_bytes(&[14, 15]));
+        // every other voxel along x
+        let strided = mapping.extract_box_bytes(&naxis, &bytes, 4, [0, 0, 0], [2, 1, 1], 2);
+        assert_eq!(strided, to_bytes(&[12, 16]));
+    }
+
+    #[test]
+    fn reject_invalid_mappings() {
+        let naxis = [2, 3, 4];
+        let mut mapping = AxisMapping::new(&naxis, &[AxisKind::Other; 3]);
+        assert!(mapping.is_valid(&naxis));
+
+        // the axes are swapped to stay distinct
+        mapping.set_volume_axis(0, 2);
+        assert_eq!(mapping.volume, [2, 1, 0]);
+        assert!(mapping.is_valid(&naxis));
+
+        assert!(!AxisMapping { volume: [0, 0, 2], indices: vec![0; 3] }.is_valid(&naxis));
+        assert!(!AxisMapping { volume: [0, 1, 3], indices: vec![0; 4] }.is_valid(&naxis));
+        assert!(!mapping.is_valid(&[2, 3]));
+    }
+}
@@ -0,0 +1,270 @@
+use fitsrs::hdu::header::ValueMap;
+use fitsrs::{ImgXY, LonLat, WCS};
+
+use crate::get_float;
+use crate::table::Table;
+
+/// Restoring beam of a radio cube, an elliptical gaussian
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Beam {
+    /// FWHM of the major axis, in degrees
+    pub major: f64,
+    /// FWHM of the minor axis, in degrees
+    pub minor: f64,
+    /// Angle of the major axis from the north through the east, in degrees
+    pub position_angle: f64,
+}
+
+/// Number of degrees in an angle unit of a table column
+fn angle_scale(unit: &str) -> Option<f64> {
+    match unit.trim().to_lowercase().as_str() {
+        "deg" | "degree" | "degrees" => Some(1.0),
+        "arcmin" => Some(1.0 / 60.0),
+        "arcsec" => Some(1.0 / 3600.0),
+        "mas" => Some(1.0 / 3_600_000.0),
+        "rad" => Some(180.0 / std::f64::consts::PI),
+        _ => None,
+    }
+}
+
+/// Unit vector pointing toward a position on the sky
+fn to_xyz(lonlat: &LonLat) -> [f64; 3] {
+    let (lon, lat) = (lonlat.lon(), lonlat.lat());
+    [lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin()]
+}
+
+/// Position at an angular distance from another one, along a bearing from the north through the east
+///
+/// All the angles are in radians.
+fn offset(lonlat: &LonLat, distance: f64, bearing: f64) -> LonLat {
+    let (lon, lat) = (lonlat.lon(), lonlat.lat());
+    let lat2 = (lat.sin() * distance.cos() + lat.cos() * distance.sin() * bearing.cos()).asin();
+    let lon2 = lon + (bearing.sin() * distance.sin() * lat.cos()).atan2(distance.cos() - lat.sin() * lat2.sin());
+
+    LonLat::new(lon2, lat2)
+}
+
+/// Solid angle of the pixel of the celestial plane centered on a position, in steradians
+pub fn pixel_solid_angle(wcs: &WCS, center: (f64, f64)) -> Option<f64> {
+    let (x, y) = center;
+    let xyz = |x: f64, y: f64| wcs.unproj(&ImgXY::new(x, y)).map(|lonlat| to_xyz(&lonlat));
+
+    let (left, right) = (xyz(x - 0.5, y)?, xyz(x + 0.5, y)?);
+    let (bottom, top) = (xyz(x, y - 0.5)?, xyz(x, y + 0.5)?);
+    let a = [0, 1, 2].map(|i| right[i] - left[i]);
+    let b = [0, 1, 2].map(|i| top[i] - bottom[i]);
+    let cross = [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ];
+
+    Some(cross.iter().map(|c| c * c).sum::<f64>().sqrt())
+}
+
+impl Beam {
+    /// Beam given by the BMAJ, BMIN and BPA keywords, in degrees
+    pub fn from_header(header: &ValueMap) -> Option<Self> {
+        let major = get_float(header, "BMAJ").filter(|b| *b > 0.0)?;
+
+        Some(Self {
+            major,
+            minor: get_float(header, "BMIN").filter(|b| *b > 0.0).unwrap_or(major),
+            position_angle: get_float(header, "BPA").unwrap_or(0.0),
+        })
+    }
+
+    /// Solid angle of the gaussian, in steradians
+    pub fn solid_angle(&self) -> f64 {
+        std::f64::consts::PI / (4.0 * std::f64::consts::LN_2) * self.major.to_radians() * self.minor.to_radians()
+    }
+
+    /// Number of pixels in the beam, given the solid angle of a pixel in steradians
+    pub fn num_pixels(&self, pixel_solid_angle: f64) -> f64 {
+        self.solid_angle() / pixel_solid_angle
+    }
+
+    /// Half major and minor axes of the beam centered on a pixel, in pixel offsets along the celestial axes
+    ///
+    /// The axes follow the orientation of the sky given by the WCS, e.g. the
+    /// east being on the left of images with a negative CDELT1.
+    pub fn pixel_axes(&self, wcs: &WCS, center: (f64, f64)) -> Option<[(f64, f64); 2]> {
+        let pixel = ImgXY::new(center.0, center.1);
+        let lonlat = wcs.unproj(&pixel)?;
+
+        let pa = self.position_angle.to_radians();
+        let axis = |fwhm: f64, bearing: f64| {
+            let end = wcs.proj(&offset(&lonlat, 0.5 * fwhm.to_radians(), bearing))?;
+            Some((end.x() - pixel.x(), end.y() - pixel.y()))
+        };
+
+        Some([axis(self.major, pa)?, axis(self.minor, pa + std::f64::consts::FRAC_PI_2)?])
+    }
+}
+
+/// Restoring beams of the channels of a cube
+#[derive(Debug, Clone, PartialEq)]
+pub enum Beams {
+    /// Same beam for all the channels
+    Common(Beam),
+    /// Beam of each channel along the z axis of the volume
+    PerChannel(Vec<Beam>),
+}
+
+impl Beams {
+    /// Beams read from a CASA BEAMS table with one beam per channel and polarization
+    ///
+    /// `channel` is the channel of the hyperplane rendered when the spectral
+    /// axis is not the z axis of the volume, the beams of all the channels
+    /// are kept otherwise. `None` if the file has no BEAMS table, if the
+    /// beams of some channels are missing or if a CHAN is not the index of a row.
+    pub fn from_tables(tables: &[Table], channel: Option<u64>, polarization: u64) -> Option<Self> {
+        let table = tables.iter().find(|table| table.extname().as_deref() == Some("BEAMS"))?;
+
+        // CASA writes the axes in arcsec and the position angles in degrees
+        let scale = |name: &str, default: f64| match table.unit(name) {
+            Some(unit) => angle_scale(&unit),
+            None => Some(default),
+        };
+        let major = table.column("BMAJ")?;
+        let minor = table.column("BMIN")?;
+        let pa = table.column("BPA")?;
+        let (major_scale, minor_scale, pa_scale) = (scale("BMAJ", 1.0 / 3600.0)?, scale("BMIN", 1.0 / 3600.0)?, scale("BPA", 1.0)?);
+
+        let num_rows = major.len();
+        if minor.len() != num_rows || pa.len() != num_rows {
+            return None;
+        }
+        let chans = table.column("CHAN").unwrap_or_else(|| (0..num_rows).map(|c| c as f64).collect());
+        let pols = table.column("POL").unwrap_or_else(|| vec![0.0; num_rows]);
+
+        let mut beams: Vec<Option<Beam>> = vec![];
+        for row in 0..num_rows {
+            let (chan, pol) = (*chans.get(row)?, *pols.get(row)? as u64);
+            if pol != polarization {
+                continue;
+            }
+            // there are at most as many channels as rows
+            if !(chan >= 0.0 && chan < num_rows as f64 && chan.fract() == 0.0) {
+                return None;
+            }
+            let chan = chan as usize;
+
+            if chan >= beams.len() {
+                beams.resize(chan + 1, None);
+            }
+            beams[chan] = Some(Beam {
+                major: major[row] * major_scale,
+                minor: minor[row] * minor_scale,
+                position_angle: pa[row] * pa_scale,
+            });
+        }
+
+        match channel {
+            Some(channel) => beams.get(channel as usize).copied().flatten().map(Beams::Common),
+            None => beams.into_iter().collect::<Option<Vec<_>>>().filter(|b| !b.is_empty()).map(Beams::PerChannel),
+        }
+    }
+
+    /// Beam of a channel of the volume
+    pub fn beam(&self, channel: u32) -> Option<&Beam> {
+        match self {
+            Beams::Common(beam) => Some(beam),
+            Beams::PerChannel(beams) => beams.get(channel as usize),
+        }
+    }
+
+    /// Tell whether the beam varies along the z axis of the volume
+    pub fn is_per_channel(&self) -> bool {
+        matches!(self, Beams::PerChannel(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{bintable, close, extension_header, header, wcs};
+
+    const ARCSEC: f64 = 1.0 / 3600.0;
+
+    /// Tangent projection centered on the pixel (50, 50) with 1 arcsec pixels, the east on the left
+    fn arcsec_pixels() -> WCS {
+        wcs(&[
+            ("CTYPE1", "'RA---TAN'"),
+            ("CTYPE2", "'DEC--TAN'"),
+            ("CRPIX1", "50.0"),
+            ("CRPIX2", "50.0"),
+            ("CRVAL1", "30.0"),
+            ("CRVAL2", "-20.0"),
+            ("CDELT1", "-2.77777777777778E-4"),
+            ("CDELT2", "2.77777777777778E-4"),
+        ])
+    }
+
+    #[test]
+    fn count_the_pixels_in_the_beam() {
+        let beam = Beam::from_header(&header(&[("BMAJ", "2.77777777777778E-4"), ("BPA", "45.0")])).unwrap();
+        assert!(close(beam.major, ARCSEC, 1e-12) && beam.minor == beam.major && beam.position_angle == 45.0);
+        // a circular gaussian of 1 arcsec covers 1.1331 square arcsec
+        let arcsec2 = ARCSEC.to_radians().powi(2);
+        assert!(close(beam.solid_angle() / arcsec2, 1.133_09, 1e-5));
+
+        let pixel = pixel_solid_angle(&arcsec_pixels(), (50.0, 50.0)).unwrap();
+        assert!(close(pixel, arcsec2, 1e-6));
+        assert!(close(beam.num_pixels(pixel), 1.133_09, 1e-5));
+
+        assert_eq!(Beam::from_header(&header(&[("BMIN", "1.0")])), None);
+    }
+
+    #[test]
+    fn orient_the_beam_on_the_sky() {
+        let beam = Beam {
+            major: 4.0 * ARCSEC,
+            minor: 2.0 * ARCSEC,
+            position_angle: 0.0,
+        };
+        let [(mx, my), (nx, ny)] = beam.pixel_axes(&arcsec_pixels(), (50.0, 50.0)).unwrap();
+
+        // the major axis points to the north, the minor one to the east
+        assert!(mx.abs() < 1e-6 && close(my, 2.0, 1e-6), "{} {}", mx, my);
+        assert!(close(nx, -1.0, 1e-6) && ny.abs() < 1e-6, "{} {}", nx, ny);
+    }
+
+    #[test]
+    fn read_the_beams_of_the_channels() {
+        let columns = [("'BMAJ'", "'E'"), ("'BMIN'", "'E'"), ("'BPA'", "'E'"), ("'CHAN'", "'J'"), ("'POL'", "'J'")];
+        let units = [("TUNIT1", "'arcsec'"), ("TUNIT2", "'arcsec'"), ("TUNIT3", "'deg'"), ("EXTNAME", "'BEAMS'")];
+        let cards = bintable(&columns, 4, &units);
+        // two channels of two polarizations, the second channel first
+        let rows = [(3.0_f32, 2.0, 10.0, 1, 0), (3.5, 2.5, 20.0, 1, 1), (1.0, 0.5, 30.0, 0, 0), (1.5, 1.0, 40.0, 0, 1)];
+        let mut data = vec![];
+        for (major, minor, pa, chan, pol) in rows {
+            [major, minor, pa].iter().for_each(|v| data.extend_from_slice(&v.to_be_bytes()));
+            [chan, pol].iter().for_each(|v: &i32| data.extend_from_slice(&v.to_be_bytes()));
+        }
+        let header = extension_header(&cards);
+        let tables = [Table::new(&header, &data)];
+
+        let beams = Beams::from_tables(&tables, None, 1).unwrap();
+        assert!(beams.is_per_channel());
+        let first = beams.beam(0).unwrap();
+        assert!(close(first.major, 1.5 * ARCSEC, 1e-12) && close(first.minor, ARCSEC, 1e-12));
+        assert_eq!(first.position_angle, 40.0);
+        assert_eq!(beams.beam(1).unwrap().position_angle, 20.0);
+        assert_eq!(beams.beam(2), None);
+
+        // the beam of the channel of the hyperplane rendered
+        assert_eq!(Beams::from_tables(&tables, Some(1), 0).unwrap().beam(7).unwrap().position_angle, 10.0);
+        assert_eq!(Beams::from_tables(&tables, Some(2), 0), None);
+        assert_eq!(Beams::from_tables(&tables, None, 2), None);
+
+        // the channels are indices of rows
+        for chan in [4_i32, -1, 1 << 30] {
+            let mut data = data.clone();
+            data[12..16].copy_from_slice(&chan.to_be_bytes());
+            assert_eq!(Beams::from_tables(&[Table::new(&header, &data)], None, 0), None, "{}", chan);
+        }
+        let scaled = extension_header(&bintable(&columns, 4, &[("TSCAL4", "0.5"), ("EXTNAME", "'BEAMS'")]));
+        assert_eq!(Beams::from_tables(&[Table::new(&scaled, &data)], None, 0), None);
+    }
+}
@@ -0,0 +1,550 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use crate::axes::AxisMapping;
+use crate::decode;
+use crate::error::Error;
+use crate::lod;
+use crate::progress::Progress;
+use crate::stats::Statistics;
+use rayon::prelude::*;
+use crate::texture::Texture;
+
+/// Side of a brick in voxels
+pub const BRICK_SIZE: u32 = 32;
+/// Maximum number of voxels of the brick atlas living on the GPU
+const ATLAS_MAX_VOXELS: u64 = 32 << 20;
+/// Maximum number of voxels of a cube uploaded at once on the GPU
+const DIRECT_MAX_VOXELS: u64 = 256 << 20;
+/// Maximum number of bricks uploaded per frame
+const UPLOADS_PER_FRAME: usize = 16;
+
+/// Tell whether a cube is too big to be uploaded at once on the GPU
+pub fn needs_bricking(dim: (u32, u32, u32), max_texture_dimension: u32) -> bool {
+    dim.0 > max_texture_dimension
+        || dim.1 > max_texture_dimension
+        || dim.2 > max_texture_dimension
+        || (dim.0 as u64) * (dim.1 as u64) * (dim.2 as u64) > DIRECT_MAX_VOXELS
+}
+
+/// Volume decoded on demand from the data unit of an image HDU
+///
+/// The data unit is not copied, bricks are read from the bytes of the
+/// file (e.g. memory mapped) when they are uploaded.
+#[derive(Debug, Clone)]
+pub struct RawVolume {
+    /// Byte offset of the data unit in the file
+    pub offset: usize,
+    pub naxis: Vec<u64>,
+    pub axes: AxisMapping,
+    pub bitpix: i64,
+    pub scale: decode::Scale,
+    pub blank: Option<i64>,
+    pub encoding: decode::Encoding,
+}
+
+impl RawVolume {
+    fn num_bytes_per_pixel(&self) -> usize {
+        // the BITPIX has been checked when parsing the HDU
+        decode::num_bytes_per_pixel(self.bitpix).unwrap_or(4)
+    }
+
+    /// Decode the voxels of a box of the volume, taken every `step` voxels
+    pub fn read(&self, file: &[u8], origin: [u32; 3], size: [u32; 3], step: u32) -> (Vec<f32>, Vec<u8>) {
+        let bytes = self.axes.extract_box_bytes(
+            &self.naxis,
+            &file[self.offset..],
+            self.num_bytes_per_pixel(),
+            origin,
+            size,
+            step,
+        );
+
+        decode::decode_encoded(&bytes, self.bitpix, &self.scale, self.blank, &self.encoding)
+            .unwrap_or_default()
+    }
+
+    /// Statistics of the whole volume
+    ///
+    /// The volume is read slice after slice so that only one of them per
+    /// thread is copied in memory at a time. `progress` is advanced by the
+    /// number of bytes read.
+    pub fn statistics(&self, file: &[u8], progress: &Progress) -> Statistics {
+        let (nx, ny, nz) = self.axes.dim(&self.naxis);
+        let num_bytes_per_pixel = self.num_bytes_per_pixel();
+
+        (0..nz)
+            .into_par_iter()
+            .fold(Statistics::default, |mut stats, z| {
+                if progress.is_cancelled() {
+                    return stats;
+                }
+                let bytes = self.axes.extract_box_bytes(
+                    &self.naxis,
+                    &file[self.offset..],
+                    num_bytes_per_pixel,
+                    [0, 0, z],
+                    [nx, ny, 1],
+                    1,
+                );
+
+                let _ = decode::for_each_value(&bytes, self.bitpix, &self.scale, self.blank, |v| stats.add(v));
+                progress.advance(bytes.len() as u64);
+                stats
+            })
+            .reduce(Statistics::default, |mut stats, other| {
+                stats.merge(&other);
+                stats
+            })
+    }
+
+    /// Raw bytes of evenly spaced voxels of the volume
+    ///
+    /// Used to estimate the cuts without decoding the whole cube.
+    pub fn sample_bytes(&self, file: &[u8], max_samples: usize) -> Vec<u8> {
+        let (nx, ny, nz) = self.axes.dim(&self.naxis);
+        let num_voxels = (nx as usize) * (ny as usize) * (nz as usize);
+        let step = (num_voxels / max_samples.max(1)).max(1);
+
+        let num_bytes_per_pixel = self.num_bytes_per_pixel();
+        let data = &file[self.offset..];
+
+        let mut bytes = Vec::with_capacity((num_voxels / step + 1) * num_bytes_per_pixel);
+        for idx in (0..num_voxels).step_by(step) {
+            let x = idx % (nx as usize);
+            let y = (idx / (nx as usize)) % (ny as usize);
+            let z = idx / ((nx as usize) * (ny as usize));
+
+            let start = self.axes.offset(&self.naxis, [x as u32, y as u32, z as u32]) * num_bytes_per_pixel;
+            bytes.extend_from_slice(&data[start..(start + num_bytes_per_pixel)]);
+        }
+
+        bytes
+    }
+}
+
+/// Voxels of a cube
+pub enum Voxels {
+    /// Values and validity mask decoded in memory
+    Decoded {
+        data: Vec<f32>,
+        mask: Vec<u8>,
+    },
+    /// Values decoded on demand from the file
+    Raw(RawVolume),
+}
+
+impl Voxels {
+    /// Values and validity mask of the voxels of a box of the cube, x varying first
+    ///
+    /// The box has `size` voxels along each axis, taken every `step` voxels from `origin`.
+    fn read(&self, file: &[u8], dim: [u32; 3], origin: [u32; 3], size: [u32; 3], step: u32) -> (Vec<f32>, Vec<u8>) {
+        match self {
+            Voxels::Decoded { data, mask } => {
+                let num_voxels = size.iter().map(|n| *n as usize).product::<usize>();
+                let mut values = Vec::with_capacity(num_voxels);
+                let mut valid = Vec::with_capacity(num_voxels);
+
+                for k in 0..size[2] {
+                    for j in 0..size[1] {
+                        let (y, z) = (origin[1] + j * step, origin[2] + k * step);
+                        let start = (origin[0] + dim[0] * (y + dim[1] * z)) as usize;
+
+                        if step == 1 {
+                            let end = start + size[0] as usize;
+                            values.extend_from_slice(&data[start..end]);
+                            valid.extend_from_slice(&mask[start..end]);
+                        } else {
+                            let row = (0..size[0] as usize).map(|i| start + i * step as usize);
+                            values.extend(row.clone().map(|idx| data[idx]));
+                            valid.extend(row.map(|idx| mask[idx]));
+                        }
+                    }
+                }
+
+                (values, valid)
+            }
+            Voxels::Raw(volume) => volume.read(file, origin, size, step),
+        }
+    }
+}
+
+/// Brick of a level of the pyramid of a cube
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct BrickId {
+    level: u32,
+    // position in the grid of bricks of the level
+    brick: [u32; 3],
+}
+
+/// GPU cache of the bricks of a cube too big to be uploaded at once
+///
+/// The cube is split into bricks of [BRICK_SIZE] voxels. The resident ones
+/// are stored in slots of a 3D atlas texture, the mask has its own atlas
+/// sharing the same layout. An indirection table with one texel per brick
+/// of the full resolution gives the slot of the brick covering it (rgb) and
+/// its level plus one, 0 if none is resident (a), to the ray marchers.
+///
+/// Regions that do not fit in the atlas at full resolution are streamed at
+/// the finest level of the pyramid fitting in it. The bricks of the level L
+/// sample the cube every 2^L voxels, each one covering 2^L bricks of the full
+/// resolution along each axis.
+pub struct BrickCache {
+    dim: [u32; 3],
+    // number of bricks of the full resolution along each axis
+    grid: [u32; 3],
+    // number of slots along each axis of the atlas
+    slots: [u32; 3],
+    // number of levels, the coarsest one fitting entirely in the atlas
+    levels: u32,
+
+    atlas: Texture,
+    mask: Texture,
+    table: Texture,
+
+    // slot of each resident brick
+    resident: HashMap<BrickId, u32>,
+    // brick stored in each slot with the last time it has been requested
+    owners: Vec<Option<(BrickId, u64)>>,
+    // bricks to upload, the most important one at the end
+    pending: Vec<BrickId>,
+    // bricks covering the region explored
+    wanted: HashSet<BrickId>,
+    // level and bricks of the region explored
+    region: Option<(u32, [Range<u32>; 3])>,
+    epoch: u64,
+}
+
+impl BrickCache {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, dim: (u32, u32, u32)) -> Result<Self, Error> {
+        let mut levels = lod::num_levels(dim);
+        let dim = [dim.0, dim.1, dim.2];
+        let grid = dim.map(|d| d.div_ceil(BRICK_SIZE));
+
+        // fill the atlas axis after axis within the limits of the device
+        let max_slots_per_axis = (device.limits().max_texture_dimension_3d / BRICK_SIZE).max(1);
+        let mut num_slots = (ATLAS_MAX_VOXELS / (BRICK_SIZE as u64).pow(3)).max(1);
+        let mut slots = [1; 3];
+        for axis in 0..3 {
+            slots[axis] = (num_slots.min(max_slots_per_axis as u64) as u32).min(grid[axis]).max(1);
+            num_slots /= slots[axis] as u64;
+        }
+
+        let atlas_dim = (slots[0] * BRICK_SIZE, slots[1] * BRICK_SIZE, slots[2] * BRICK_SIZE);
+        let atlas = Texture::from_raw_bytes::<f32>(device, queue, None, atlas_dim, 4, "brick atlas")?;
+        let mask = Texture::from_raw_bytes::<u8>(device, queue, None, atlas_dim, 1, "brick mask atlas")?;
+        // all the bricks are initially non resident
+        let table = Texture::from_raw_bytes::<[f32; 4]>(device, queue, None, (grid[0], grid[1], grid[2]), 16, "brick table")?;
+
+        // levels are added until the whole cube fits in the atlas
+        let num_slots = slots.iter().product::<u32>() as usize;
+        while num_bricks(&level_bricks(dim, &[0..dim[0], 0..dim[1], 0..dim[2]], levels - 1)) > num_slots {
+            levels += 1;
+        }
+
+        Ok(Self {
+            dim,
+            grid,
+            slots,
+            levels,
+            atlas,
+            mask,
+            table,
+            resident: HashMap::new(),
+            owners: vec![None; num_slots],
+            pending: vec![],
+            wanted: HashSet::new(),
+            region: None,
+            epoch: 0,
+        })
+    }
+
+    pub fn atlas(&self) -> &Texture {
+        &self.atlas
+    }
+
+    pub fn mask(&self) -> &Texture {
+        &self.mask
+    }
+
+    pub fn table(&self) -> &Texture {
+        &self.table
+    }
+
+    /// Content of the bricking uniform: the brick size and the atlas size in voxels
+    pub fn bricking(&self) -> [f32; 4] {
+        [
+            BRICK_SIZE as f32,
+            (self.slots[0] * BRICK_SIZE) as f32,
+            (self.slots[1] * BRICK_SIZE) as f32,
+            (self.slots[2] * BRICK_SIZE) as f32,
+        ]
+    }
+
+    /// Tell whether some requested bricks are still to be uploaded
+    pub fn is_loading(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Level the region explored is streamed at
+    pub fn level(&self) -> u32 {
+        self.region.as_ref().map_or(0, |(level, _)| *level)
+    }
+
+    fn slot_coo(&self, slot: u32) -> [u32; 3] {
+        [
+            slot % self.slots[0],
+            (slot / self.slots[0]) % self.slots[1],
+            slot / (self.slots[0] * self.slots[1]),
+        ]
+    }
+
+    /// Ask for the bricks covering a region of the cube (in voxels)
+    ///
+    /// The region is streamed at the finest level from `min_level` whose
+    /// bricks fit in the atlas. The bricks closest to the center of the
+    /// region are uploaded first.
+    pub fn request(&mut self, region: [Range<u32>; 3], min_level: u32) {
+        let num_slots = self.owners.len();
+        let (level, bricks) = (min_level.min(self.levels - 1)..self.levels)
+            .map(|level| (level, level_bricks(self.dim, &region, level)))
+            .find(|(_, bricks)| num_bricks(bricks) <= num_slots)
+            // not reached, the whole cube fits in the atlas at the coarsest level
+            .unwrap_or_else(|| (self.levels - 1, level_bricks(self.dim, &region, self.levels - 1)));
+        if self.region.as_ref() == Some(&(level, bricks.clone())) {
+            return;
+        }
+
+        let center = [0, 1, 2].map(|axis| (bricks[axis].start + bricks[axis].end) as f32 * 0.5);
+        let mut wanted = vec![];
+        for z in bricks[2].clone() {
+            for y in bricks[1].clone() {
+                for x in bricks[0].clone() {
+                    let d = [x, y, z]
+                        .iter()
+                        .zip(center.iter())
+                        .map(|(b, c)| (*b as f32 + 0.5 - c).powi(2))
+                        .sum::<f32>();
+
+                    wanted.push((d, BrickId { level, brick: [x, y, z] }));
+                }
+            }
+        }
+        wanted.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        self.epoch += 1;
+        self.wanted = wanted.iter().map(|(_, id)| *id).collect();
+        for (id, last) in self.owners.iter_mut().flatten() {
+            if self.wanted.contains(id) {
+                *last = self.epoch;
+            }
+        }
+
+        self.pending = wanted
+            .into_iter()
+            .rev()
+            .map(|(_, id)| id)
+            .filter(|id| !self.resident.contains_key(id))
+            .collect();
+        self.region = Some((level, bricks));
+    }
+
+    /// Slot receiving a new brick: a free one or the one of the least
+    /// recently requested brick that is not wanted anymore
+    fn find_slot(&self) -> Option<u32> {
+        if let Some(slot) = self.owners.iter().position(|o| o.is_none()) {
+            return Some(slot as u32);
+        }
+
+        self.owners
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, o)| o.map(|(id, last)| (slot, id, last)))
+            .filter(|(_, id, _)| !self.wanted.contains(id))
+            .min_by_key(|(_, _, last)| *last)
+            .map(|(slot, _, _)| slot as u32)
+    }
+
+    /// Entry of the table for a brick of the full resolution: the finest resident brick covering it
+    fn entry(&self, brick: [u32; 3]) -> [f32; 4] {
+        (0..self.levels)
+            .find_map(|level| {
+                let grid = level_grid(self.dim, level);
+                // the last voxels of an odd axis are pooled in the last brick of the coarser levels
+                let id = BrickId {
+                    level,
+                    brick: [0, 1, 2].map(|axis| (brick[axis] >> level).min(grid[axis] - 1)),
+                };
+                let [sx, sy, sz] = self.slot_coo(*self.resident.get(&id)?);
+
+                Some([sx as f32, sy as f32, sz as f32, (level + 1) as f32])
+            })
+            .unwrap_or([0.0; 4])
+    }
+
+    /// Write the entries of the table covered by a brick
+    fn write_entries(&self, queue: &wgpu::Queue, id: BrickId) {
+        let grid = level_grid(self.dim, id.level);
+        let range = [0, 1, 2].map(|axis| {
+            let start = id.brick[axis] << id.level;
+            let end = if id.brick[axis] == grid[axis] - 1 {
+                self.grid[axis]
+            } else {
+                ((id.brick[axis] + 1) << id.level).min(self.grid[axis])
+            };
+            start..end
+        });
+
+        let mut entries = Vec::with_capacity(num_bricks(&range));
+        for z in range[2].clone() {
+            for y in range[1].clone() {
+                for x in range[0].clone() {
+                    entries.push(self.entry([x, y, z]));
+                }
+            }
+        }
+
+        let [rx, ry, rz] = range.clone().map(|r| r.len() as u32);
+        self.table.write_data(
+            queue,
+            (range[0].start, range[1].start, range[2].start),
+            bytemuck::cast_slice(&entries),
+            (rx, ry, rz),
+        );
+    }
+
+    /// Upload some of the requested bricks
+    pub fn update(&mut self, queue: &wgpu::Queue, voxels: &Voxels, file: &[u8]) {
+        for _ in 0..UPLOADS_PER_FRAME {
+            let id = match self.pending.pop() {
+                Some(id) => id,
+                None => break,
+            };
+
+            let slot = match self.find_slot() {
+                Some(slot) => slot,
+                None => {
+                    // the atlas is full of wanted bricks
+                    self.pending.clear();
+                    break;
+                }
+            };
+
+            // evict the previous brick of the slot, a coarser one is shown in its place if resident
+            if let Some((evicted, _)) = self.owners[slot as usize].take() {
+                self.resident.remove(&evicted);
+                self.write_entries(queue, evicted);
+            }
+
+            // the bricks of a coarse level sample the full resolution every `step` voxels
+            let step = 1 << id.level;
+            let (lx, ly, lz) = lod::level_dim((self.dim[0], self.dim[1], self.dim[2]), id.level);
+            let level_dim = [lx, ly, lz];
+            let size = [0, 1, 2].map(|axis| BRICK_SIZE.min(level_dim[axis] - id.brick[axis] * BRICK_SIZE));
+            let origin = id.brick.map(|b| b * BRICK_SIZE * step);
+            let (values, valid) = voxels.read(file, self.dim, origin, size, step);
+            if values.len() != size.iter().map(|n| *n as usize).product::<usize>() {
+                continue;
+            }
+
+            let [sx, sy, sz] = self.slot_coo(slot);
+            let slot_origin = (sx * BRICK_SIZE, sy * BRICK_SIZE, sz * BRICK_SIZE);
+            let size = (size[0], size[1], size[2]);
+            self.atlas.write_data(queue, slot_origin, bytemuck::cast_slice(&values), size);
+            self.mask.write_data(queue, slot_origin, &valid, size);
+
+            self.owners[slot as usize] = Some((id, self.epoch));
+            self.resident.insert(id, slot);
+            self.write_entries(queue, id);
+        }
+    }
+}
+
+/// Number of bricks along each axis of a level
+fn level_grid(dim: [u32; 3], level: u32) -> [u32; 3] {
+    let (x, y, z) = lod::level_dim((dim[0], dim[1], dim[2]), level);
+    [x, y, z].map(|d| d.div_ceil(BRICK_SIZE))
+}
+
+/// Bricks of a level covering a region of the cube given in voxels of the full resolution
+fn level_bricks(dim: [u32; 3], region: &[Range<u32>; 3], level: u32) -> [Range<u32>; 3] {
+    let (x, y, z) = lod::level_dim((dim[0], dim[1], dim[2]), level);
+    let level_dim = [x, y, z];
+
+    [0, 1, 2].map(|axis| {
+        let r = &region[axis];
+        let start = (r.start >> level).min(level_dim[axis] - 1);
+        let end = r.end.div_ceil(1 << level).min(level_dim[axis]).max(start + 1);
+        (start / BRICK_SIZE)..end.div_ceil(BRICK_SIZE)
+    })
+}
+
+fn num_bricks(bricks: &[Range<u32>; 3]) -> usize {
+    bricks.iter().map(|r| r.len()).product()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::axes::AxisKind;
+
+    /// 4x4x4 BITPIX = -32 cube whose values are the index of the voxels
+    fn cube() -> (Vec<u8>, RawVolume) {
+        let file = (0..64).flat_map(|i| (i as f32).to_be_bytes()).collect();
+        let naxis = vec![4, 4, 4];
+        let volume = RawVolume {
+            offset: 0,
+            axes: AxisMapping::new(&naxis, &[AxisKind::Other; 3]),
+            naxis,
+            bitpix: -32,
+            scale: decode::Scale::default(),
+            blank: None,
+            encoding: decode::Encoding::default(),
+        };
+
+        (file, volume)
+    }
+
+    #[test]
+    fn brick_big_cubes() {
+        assert!(!needs_bricking((256, 256, 256), 2048));
+        assert!(needs_bricking((100, 100, 100), 64));
+        assert!(needs_bricking((2048, 2048, 100), 2048));
+    }
+
+    #[test]
+    fn read_boxes_every_step_voxels() {
+        let (file, volume) = cube();
+        let expected = [1.0, 3.0, 9.0, 11.0, 33.0, 35.0, 41.0, 43.0];
+
+        let (values, valid) = volume.read(&file, [1, 0, 0], [2, 2, 2], 2);
+        assert_eq!(values, expected);
+        assert_eq!(valid, [decode::VALID; 8]);
+
+        let decoded = Voxels::Decoded {
+            data: (0..64).map(|i| i as f32).collect(),
+            mask: vec![decode::VALID; 64],
+        };
+        assert_eq!(decoded.read(&file, [4; 3], [1, 0, 0], [2, 2, 2], 2).0, expected);
+        assert_eq!(decoded.read(&file, [4; 3], [2, 3, 1], [2, 1, 1], 1).0, [30.0, 31.0]);
+
+        let stats = volume.statistics(&file, &Progress::default());
+        assert_eq!((stats.count, stats.min, stats.max, stats.mean), (64, 0.0, 63.0, 31.5));
+    }
+
+    #[test]
+    fn cover_regions_with_coarser_bricks() {
+        let dim = [100, 64, 10];
+        let whole = [0..100, 0..64, 0..10];
+
+        assert_eq!(level_bricks(dim, &whole, 0), [0..4, 0..2, 0..1]);
+        assert_eq!(num_bricks(&level_bricks(dim, &whole, 0)), 8);
+        // the level 1 is 50x32x5 voxels
+        assert_eq!(level_bricks(dim, &whole, 1), [0..2, 0..1, 0..1]);
+        assert_eq!(level_grid(dim, 1), [2, 1, 1]);
+
+        let region = [40..70, 0..10, 3..4];
+        assert_eq!(level_bricks(dim, &region, 0), [1..3, 0..1, 0..1]);
+        assert_eq!(level_bricks(dim, &region, 1), [0..2, 0..1, 0..1]);
+    }
+}
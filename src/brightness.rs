@@ -0,0 +1,228 @@
+use crate::beam::Beams;
+use crate::spectral::C;
+
+/// Boltzmann constant, in J/K
+const K_B: f64 = 1.380_649e-23;
+/// One jansky, in W/m²/Hz
+const JY: f64 = 1e-26;
+
+/// Units of brightness of radio cubes the values can be displayed in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BrightnessUnit {
+    JyPerBeam,
+    JyPerPixel,
+    /// Brightness temperature in the Rayleigh-Jeans approximation
+    Kelvin,
+}
+
+impl BrightnessUnit {
+    pub fn all() -> [BrightnessUnit; 3] {
+        [BrightnessUnit::JyPerBeam, BrightnessUnit::JyPerPixel, BrightnessUnit::Kelvin]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BrightnessUnit::JyPerBeam => "Jy/beam",
+            BrightnessUnit::JyPerPixel => "Jy/pixel",
+            BrightnessUnit::Kelvin => "K",
+        }
+    }
+
+    /// Unit of a BUNIT value with the factor converting the values into it, e.g. 1e-3 for mJy/beam
+    pub fn from_bunit(bunit: &str) -> Option<(Self, f64)> {
+        // kelvins are matched exactly, a "k" suffix would also match units like km/s
+        match bunit.trim() {
+            "K" | "K (Tb)" => return Some((BrightnessUnit::Kelvin, 1.0)),
+            "mK" => return Some((BrightnessUnit::Kelvin, 1e-3)),
+            _ => (),
+        }
+
+        let bunit = bunit.trim().to_lowercase().replace(' ', "");
+        let (suffix, unit) = [
+            ("jy/beam", BrightnessUnit::JyPerBeam),
+            ("jybeam-1", BrightnessUnit::JyPerBeam),
+            ("jy/pixel", BrightnessUnit::JyPerPixel),
+            ("jy/pix", BrightnessUnit::JyPerPixel),
+            ("jypixel-1", BrightnessUnit::JyPerPixel),
+            ("kelvin", BrightnessUnit::Kelvin),
+        ]
+        .iter()
+        .copied()
+        .find(|(suffix, _)| bunit.ends_with(suffix))?;
+
+        let scale = match &bunit[..(bunit.len() - suffix.len())] {
+            "" => 1.0,
+            "m" => 1e-3,
+            "u" | "µ" => 1e-6,
+            _ => return None,
+        };
+
+        Some((unit, scale))
+    }
+}
+
+/// Conversion of the values of a cube between the brightness units
+///
+/// The conversions to and from Jy/beam use the beam of each channel and
+/// the Rayleigh-Jeans conversion to K uses the frequency of each channel.
+#[derive(Debug, Clone)]
+pub struct Brightness {
+    // unit of the values given by BUNIT
+    unit: BrightnessUnit,
+    // factor converting the values into `unit`
+    scale: f64,
+    // solid angle of the beam of each channel, in steradians
+    beams: Option<Vec<f64>>,
+    // solid angle of a pixel, in steradians
+    pixel: Option<f64>,
+    // frequency of the center of each channel, in Hz
+    frequencies: Option<Vec<f64>>,
+    num_channels: u32,
+}
+
+impl Brightness {
+    /// Conversion of the values of a cube of `num_channels` channels
+    ///
+    /// `None` if BUNIT is not a brightness unit. The units needing a beam,
+    /// the solid angle of the pixels or the frequencies of the channels are
+    /// only available when those are known.
+    pub fn new(
+        bunit: &str,
+        beams: Option<&Beams>,
+        pixel_solid_angle: Option<f64>,
+        num_channels: u32,
+        frequency: impl Fn(u32) -> Option<f64>,
+    ) -> Option<Self> {
+        let (unit, scale) = BrightnessUnit::from_bunit(bunit)?;
+
+        let beams = beams.and_then(|beams| {
+            (0..num_channels)
+                .map(|channel| beams.beam(channel).map(|beam| beam.solid_angle()))
+                .collect::<Option<Vec<_>>>()
+        });
+        let frequencies = (0..num_channels)
+            .map(|channel| frequency(channel).filter(|f| *f > 0.0))
+            .collect::<Option<Vec<_>>>();
+
+        Some(Self {
+            unit,
+            scale,
+            beams,
+            pixel: pixel_solid_angle.filter(|p| *p > 0.0),
+            frequencies,
+            num_channels,
+        })
+    }
+
+    /// Specific intensity in Jy/sr of a brightness of 1 in a unit, at a channel
+    fn intensity(&self, unit: BrightnessUnit, channel: u32) -> Option<f64> {
+        match unit {
+            BrightnessUnit::JyPerBeam => Some(1.0 / self.beams.as_ref()?.get(channel as usize)?),
+            BrightnessUnit::JyPerPixel => Some(1.0 / self.pixel?),
+            BrightnessUnit::Kelvin => {
+                let frequency = self.frequencies.as_ref()?.get(channel as usize)?;
+                Some(2.0 * K_B * frequency * frequency / (C * C) / JY)
+            }
+        }
+    }
+
+    /// Factor converting the values of a channel into a unit
+    pub fn factor(&self, to: BrightnessUnit, channel: u32) -> Option<f64> {
+        if to == self.unit {
+            return Some(self.scale);
+        }
+
+        Some(self.scale * self.intensity(self.unit, channel)? / self.intensity(to, channel)?)
+    }
+
+    /// Whether the factor converting the values into a unit depends on the channel
+    pub fn is_per_channel(&self, to: BrightnessUnit) -> bool {
+        let first = self.factor(to, 0);
+        (1..self.num_channels).any(|channel| self.factor(to, channel) != first)
+    }
+
+    /// Units the values of a channel can be converted into
+    pub fn units(&self, channel: u32) -> Vec<BrightnessUnit> {
+        BrightnessUnit::all()
+            .iter()
+            .copied()
+            .filter(|unit| self.factor(*unit, channel).is_some())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beam::Beam;
+    use crate::testing::close;
+
+    const HI: f64 = 1.420_405_752e9;
+
+    /// Circular beam of 1 arcsec
+    fn arcsec_beam() -> Beams {
+        let arcsec = 1.0 / 3600.0;
+        Beams::Common(Beam {
+            major: arcsec,
+            minor: arcsec,
+            position_angle: 0.0,
+        })
+    }
+
+    /// Conversion of a cube of two channels at 1 and 2 times the HI line, with 0.5 arcsec pixels
+    fn brightness(bunit: &str) -> Brightness {
+        let pixel = (0.5_f64 / 3600.0).to_radians().powi(2);
+        Brightness::new(bunit, Some(&arcsec_beam()), Some(pixel), 2, |channel| Some(HI * (channel + 1) as f64)).unwrap()
+    }
+
+    #[test]
+    fn read_the_bunit() {
+        assert_eq!(BrightnessUnit::from_bunit("Jy/beam"), Some((BrightnessUnit::JyPerBeam, 1.0)));
+        assert_eq!(BrightnessUnit::from_bunit(" mJy/beam "), Some((BrightnessUnit::JyPerBeam, 1e-3)));
+        assert_eq!(BrightnessUnit::from_bunit("uJy beam-1"), Some((BrightnessUnit::JyPerBeam, 1e-6)));
+        assert_eq!(BrightnessUnit::from_bunit("JY/PIXEL"), Some((BrightnessUnit::JyPerPixel, 1.0)));
+        assert_eq!(BrightnessUnit::from_bunit("K (Tb)"), Some((BrightnessUnit::Kelvin, 1.0)));
+        assert_eq!(BrightnessUnit::from_bunit("mK"), Some((BrightnessUnit::Kelvin, 1e-3)));
+        for bunit in ["km/s", "Jy", "MJy/sr", "kJy/beam"] {
+            assert_eq!(BrightnessUnit::from_bunit(bunit), None, "{}", bunit);
+        }
+    }
+
+    #[test]
+    fn convert_jansky_per_beam_to_kelvins() {
+        let jy = brightness("Jy/beam");
+        // 1.222e6 / (ν[GHz]² θmaj["] θmin["]) K for 1 Jy/beam
+        let kelvins = jy.factor(BrightnessUnit::Kelvin, 0).unwrap();
+        assert!(close(kelvins, 605_744.0, 1e-6), "{}", kelvins);
+        assert!(close(kelvins, 1.222e6 / (HI * 1e-9).powi(2), 1e-3));
+        assert!(close(jy.factor(BrightnessUnit::Kelvin, 1).unwrap(), kelvins / 4.0, 1e-12));
+        assert!(jy.is_per_channel(BrightnessUnit::Kelvin));
+
+        // a beam of 1.1331 square arcsec covers 4.5324 pixels
+        let per_pixel = jy.factor(BrightnessUnit::JyPerPixel, 1).unwrap();
+        assert!(close(1.0 / per_pixel, 4.532_36, 1e-5), "{}", per_pixel);
+        assert!(!jy.is_per_channel(BrightnessUnit::JyPerPixel));
+        assert_eq!(jy.factor(BrightnessUnit::JyPerBeam, 1), Some(1.0));
+
+        // back from the kelvins
+        let mk = brightness("mK");
+        for channel in 0..2 {
+            let round_trip = jy.factor(BrightnessUnit::Kelvin, channel).unwrap() * mk.factor(BrightnessUnit::JyPerBeam, channel).unwrap();
+            assert!(close(round_trip, 1e-3, 1e-12), "{}", round_trip);
+        }
+    }
+
+    #[test]
+    fn offer_the_units_that_can_be_converted() {
+        let all = BrightnessUnit::all().to_vec();
+        assert_eq!(brightness("Jy/beam").units(0), all);
+
+        let no_beam = Brightness::new("K", None, None, 2, |_| Some(HI)).unwrap();
+        assert_eq!(no_beam.units(0), vec![BrightnessUnit::Kelvin]);
+        // the frequencies of all the channels are needed
+        let some_frequencies = Brightness::new("Jy/beam", Some(&arcsec_beam()), None, 2, |channel| Some(HI).filter(|_| channel == 0)).unwrap();
+        assert_eq!(some_frequencies.units(0), vec![BrightnessUnit::JyPerBeam]);
+
+        assert!(Brightness::new("km/s", None, None, 2, |_| Some(HI)).is_none());
+    }
+}
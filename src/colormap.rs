@@ -0,0 +1,416 @@
+use crate::error::Error;
+use serde_json::Value;
+
+/// Number of entries of the colormap texture
+pub const COLORMAP_SIZE: usize = 256;
+
+/// Colormap of the maximum intensity projection, sampled in sRGB
+#[derive(Debug, Clone, PartialEq)]
+pub struct Colormap {
+    pub name: String,
+    /// Colors from the low to the high cut
+    colors: Vec<[f32; 3]>,
+}
+
+fn jet(t: f32) -> [f32; 3] {
+    let r = if t < 0.7 { 4.0 * t - 1.5 } else { -4.0 * t + 4.5 };
+    let g = if t < 0.5 { 4.0 * t - 0.5 } else { -4.0 * t + 3.5 };
+    let b = if t < 0.3 { 4.0 * t + 0.5 } else { -4.0 * t + 2.5 };
+    [r, g, b]
+}
+
+/// Polynomial fit of the matplotlib viridis colormap
+fn viridis(t: f32) -> [f32; 3] {
+    const C: [[f32; 3]; 7] = [
+        [0.277_727_33, 0.005_407_344_5, 0.334_099_8],
+        [0.105_093_04, 1.404_613_5, 1.384_590_2],
+        [-0.330_861_83, 0.214_847_56, 0.095_095_16],
+        [-4.634_230_5, -5.799_101, -19.332_441],
+        [6.228_27, 14.179_933, 56.690_55],
+        [4.776_385, -13.745_145, -65.353_03],
+        [-5.435_456, 4.645_852_6, 26.312_435],
+    ];
+    [0, 1, 2].map(|i| C.iter().rev().fold(0.0, |acc, c| acc * t + c[i]))
+}
+
+/// Polynomial fit of the Google turbo colormap
+fn turbo(t: f32) -> [f32; 3] {
+    const C: [[f32; 6]; 3] = [
+        [0.135_721_38, 4.615_392_6, -42.660_324, 132.131_08, -152.942_4, 59.286_38],
+        [0.091_402_61, 2.194_188_4, 4.842_966_6, -14.185_033, 4.277_298_6, 2.829_566],
+        [0.106_673_3, 12.641_946, -60.582_05, 110.362_77, -89.903_11, 27.348_25],
+    ];
+    C.map(|c| c.iter().rev().fold(0.0, |acc, c| acc * t + c))
+}
+
+/// Green's cubehelix with its default parameters, monotonic in luminance
+fn cubehelix(t: f32) -> [f32; 3] {
+    let (start, rotations, hue) = (0.5, -1.5, 1.0);
+    let phi = 2.0 * std::f32::consts::PI * (start / 3.0 + rotations * t);
+    let a = hue * t * (1.0 - t) / 2.0;
+    let (cos, sin) = (phi.cos(), phi.sin());
+
+    [
+        t + a * (-0.148_61 * cos + 1.782_77 * sin),
+        t + a * (-0.292_27 * cos - 0.906_49 * sin),
+        t + a * (1.972_94 * cos),
+    ]
+}
+
+/// Colors evenly spaced from 0 to 1 linearly interpolated
+fn stops(colors: &[[f32; 3]], t: f32) -> [f32; 3] {
+    let x = t * (colors.len() - 1) as f32;
+    let i = (x.floor() as usize).min(colors.len() - 2);
+    let f = x - i as f32;
+    [0, 1, 2].map(|c| colors[i][c] + (colors[i + 1][c] - colors[i][c]) * f)
+}
+
+fn hex(rgb: u32) -> [f32; 3] {
+    [16, 8, 0].map(|shift| ((rgb >> shift) & 0xff) as f32 / 255.0)
+}
+
+/// Diverging red to blue colormap of ColorBrewer
+fn rdbu(t: f32) -> [f32; 3] {
+    let colors = [
+        0x67001f, 0xb2182b, 0xd6604d, 0xf4a582, 0xfddbc7, 0xf7f7f7, 0xd1e5f0, 0x92c5de, 0x4393c3, 0x2166ac, 0x053061,
+    ]
+    .map(hex);
+    stops(&colors, t)
+}
+
+/// Moreland's cool to warm diverging colormap, interpolated in the Msh color space
+///
+/// Designed to stay readable by people with color vision deficiencies.
+fn cool_warm(t: f32) -> [f32; 3] {
+    let cool = msh::from_srgb([59.0 / 255.0, 76.0 / 255.0, 192.0 / 255.0]);
+    let warm = msh::from_srgb([180.0 / 255.0, 4.0 / 255.0, 38.0 / 255.0]);
+
+    msh::to_srgb(msh::interpolate(cool, warm, t as f64)).map(|c| c as f32)
+}
+
+/// Msh color space of Moreland, the polar coordinates of CIELAB
+mod msh {
+    use std::f64::consts::PI;
+
+    // D65 reference white
+    const WHITE: [f64; 3] = [0.950_47, 1.0, 1.088_83];
+
+    fn to_linear(c: f64) -> f64 {
+        if c <= 0.040_45 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    }
+
+    fn from_linear(c: f64) -> f64 {
+        if c <= 0.003_130_8 { 12.92 * c } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+    }
+
+    fn f(t: f64) -> f64 {
+        if t > 0.008_856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 }
+    }
+
+    fn f_inv(t: f64) -> f64 {
+        if t > 0.206_893 { t * t * t } else { (t - 16.0 / 116.0) / 7.787 }
+    }
+
+    pub fn from_srgb(rgb: [f32; 3]) -> [f64; 3] {
+        let [r, g, b] = rgb.map(|c| to_linear(c as f64));
+        let xyz = [
+            0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b,
+            0.212_672_9 * r + 0.715_152_2 * g + 0.072_175 * b,
+            0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b,
+        ];
+        let [fx, fy, fz] = [0, 1, 2].map(|i| f(xyz[i] / WHITE[i]));
+        let (l, a, b) = (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz));
+
+        let m = (l * l + a * a + b * b).sqrt();
+        [m, (l / m).acos(), b.atan2(a)]
+    }
+
+    pub fn to_srgb([m, s, h]: [f64; 3]) -> [f64; 3] {
+        let (l, a, b) = (m * s.cos(), m * s.sin() * h.cos(), m * s.sin() * h.sin());
+        let fy = (l + 16.0) / 116.0;
+        let [x, y, z] = [fy + a / 500.0, fy, fy - b / 200.0];
+        let [x, y, z] = [f_inv(x) * WHITE[0], f_inv(y) * WHITE[1], f_inv(z) * WHITE[2]];
+
+        [
+            3.240_454_2 * x - 1.537_138_5 * y - 0.498_531_4 * z,
+            -0.969_266 * x + 1.876_010_8 * y + 0.041_556 * z,
+            0.055_643_4 * x - 0.204_025_9 * y + 1.057_225_2 * z,
+        ]
+        .map(|c| from_linear(c.clamp(0.0, 1.0)))
+    }
+
+    /// Hue of an unsaturated color keeping its distance to a saturated one
+    fn adjust_hue([m, s, h]: [f64; 3], unsaturated_m: f64) -> f64 {
+        if m >= unsaturated_m {
+            return h;
+        }
+
+        let spin = s * (unsaturated_m * unsaturated_m - m * m).sqrt() / (m * s.sin());
+        if h > -PI / 3.0 { h + spin } else { h - spin }
+    }
+
+    /// Interpolation through white when the hues of the end colors are far apart
+    pub fn interpolate(mut from: [f64; 3], mut to: [f64; 3], mut t: f64) -> [f64; 3] {
+        if from[1] > 0.05 && to[1] > 0.05 && (from[2] - to[2]).abs() > PI / 3.0 {
+            let white = from[0].max(to[0]).max(88.0);
+            if t < 0.5 {
+                to = [white, 0.0, 0.0];
+                t *= 2.0;
+            } else {
+                from = [white, 0.0, 0.0];
+                t = 2.0 * t - 1.0;
+            }
+        }
+
+        if from[1] < 0.05 && to[1] > 0.05 {
+            from[2] = adjust_hue(to, from[0]);
+        } else if to[1] < 0.05 && from[1] > 0.05 {
+            to[2] = adjust_hue(from, to[0]);
+        }
+
+        [0, 1, 2].map(|i| from[i] + (to[i] - from[i]) * t)
+    }
+}
+
+impl Colormap {
+    fn sampled(name: &str, f: impl Fn(f32) -> [f32; 3]) -> Self {
+        Self {
+            name: name.to_owned(),
+            colors: (0..COLORMAP_SIZE).map(|i| f(i as f32 / (COLORMAP_SIZE - 1) as f32)).collect(),
+        }
+    }
+
+    /// Colormaps available without loading a palette, the first one is the default
+    pub fn builtins() -> Vec<Colormap> {
+        vec![
+            Colormap::sampled("jet", jet),
+            Colormap::sampled("viridis", viridis),
+            Colormap::sampled("turbo", turbo),
+            Colormap::sampled("cubehelix", cubehelix),
+            Colormap::sampled("grey", |t| [t; 3]),
+            Colormap::sampled("RdBu", rdbu),
+            Colormap::sampled("coolwarm", cool_warm),
+        ]
+    }
+
+    /// Color at a position between 0 and 1, linearly interpolated between the colors
+    pub fn color_at(&self, t: f32) -> [f32; 3] {
+        if self.colors.len() == 1 {
+            return self.colors[0];
+        }
+
+        stops(&self.colors, t.clamp(0.0, 1.0))
+    }
+
+    /// Entries of the colormap texture, sRGB
+    pub fn lut(&self, reversed: bool) -> Vec<[u8; 4]> {
+        (0..COLORMAP_SIZE)
+            .map(|i| {
+                let t = i as f32 / (COLORMAP_SIZE - 1) as f32;
+                let [r, g, b] = self.color_at(if reversed { 1.0 - t } else { t });
+                [r, g, b, 1.0].map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+            })
+            .collect()
+    }
+
+    /// Palette read from a CSV or a JSON file
+    ///
+    /// The CSV files have one color per line, given by its r, g, b components
+    /// or `#rrggbb`, like the CET colormaps. The components are between 0 and 1,
+    /// or 0 and 255 if one of them is greater than 1. The JSON files follow
+    /// matplotlib: either a list of colors or the segment data of a
+    /// LinearSegmentedColormap, i.e. `{"red": [[x, y0, y1], ...], "green": ..., "blue": ...}`,
+    /// optionally with a `name`.
+    pub fn from_file(name: &str, text: &str) -> Result<Self, Error> {
+        let invalid = |reason: &str| Error::InvalidColormap { reason: format!("{}: {}", name, reason) };
+
+        let text = text.trim_start_matches('\u{feff}').trim();
+        let (name, colors) = if text.starts_with('{') || text.starts_with('[') {
+            let json = serde_json::from_str::<Value>(text).map_err(|_| invalid("not a valid JSON file"))?;
+            let name = json.get("name").and_then(Value::as_str).unwrap_or(name).to_owned();
+            (name, colors_from_json(&json).ok_or_else(|| invalid("expected a list of colors or red, green and blue segments"))?)
+        } else {
+            (name.to_owned(), colors_from_csv(text).ok_or_else(|| invalid("expected one r, g, b color per line"))?)
+        };
+
+        if colors.is_empty() {
+            return Err(invalid("no color found"));
+        }
+
+        Ok(Self { name, colors })
+    }
+}
+
+/// Color given by `#rrggbb`
+fn parse_hex(text: &str) -> Option<[f32; 3]> {
+    let digits = text.trim().trim_matches('"').strip_prefix('#')?;
+    if digits.len() != 6 {
+        return None;
+    }
+
+    Some(hex(u32::from_str_radix(digits, 16).ok()?))
+}
+
+/// Scale the components to 0-1 if they are given between 0 and 255
+fn normalize(mut colors: Vec<[f32; 3]>) -> Vec<[f32; 3]> {
+    if colors.iter().flatten().any(|c| *c > 1.0) {
+        for c in colors.iter_mut().flatten() {
+            *c /= 255.0;
+        }
+    }
+    colors
+}
+
+fn colors_from_csv(text: &str) -> Option<Vec<[f32; 3]>> {
+    let mut colors = vec![];
+    let mut hexes = vec![];
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') && parse_hex(line).is_none() {
+            continue;
+        }
+        if let Some(rgb) = parse_hex(line) {
+            hexes.push(rgb);
+            continue;
+        }
+
+        let values = line
+            .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+            .filter(|v| !v.is_empty())
+            .map(|v| v.parse::<f32>().ok())
+            .collect::<Option<Vec<_>>>();
+        match values.as_deref() {
+            Some([r, g, b, ..]) => colors.push([*r, *g, *b]),
+            // a header line
+            None if colors.is_empty() && hexes.is_empty() => continue,
+            _ => return None,
+        }
+    }
+
+    // mixing both notations is not expected
+    match (colors.is_empty(), hexes.is_empty()) {
+        (false, true) => Some(normalize(colors)),
+        (true, false) => Some(hexes),
+        (true, true) => Some(vec![]),
+        (false, false) => None,
+    }
+}
+
+fn colors_from_json(json: &Value) -> Option<Vec<[f32; 3]>> {
+    // a list of colors, either [r, g, b(, a)] or "#rrggbb"
+    if let Some(values) = json.as_array().or_else(|| json.get("colors").and_then(Value::as_array)) {
+        let colors = values
+            .iter()
+            .map(|value| match value {
+                Value::String(s) => parse_hex(s),
+                Value::Array(c) if c.len() >= 3 => Some([c[0].as_f64()? as f32, c[1].as_f64()? as f32, c[2].as_f64()? as f32]),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        return Some(normalize(colors));
+    }
+
+    // segment data of a LinearSegmentedColormap, the rows being (x, y below x, y above x)
+    let segments = ["red", "green", "blue"]
+        .iter()
+        .map(|channel| {
+            json.get(channel)?
+                .as_array()?
+                .iter()
+                .map(|row| match row.as_array()?.as_slice() {
+                    [x, y0, y1] => Some([x.as_f64()? as f32, y0.as_f64()? as f32, y1.as_f64()? as f32]),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()
+                .filter(|rows| !rows.is_empty())
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let segment = |rows: &[[f32; 3]], t: f32| {
+        match rows.windows(2).find(|w| t <= w[1][0]) {
+            Some(w) if w[1][0] > w[0][0] => w[0][2] + (w[1][1] - w[0][2]) * (t - w[0][0]) / (w[1][0] - w[0][0]),
+            Some(w) => w[1][1],
+            None => rows[rows.len() - 1][2],
+        }
+    };
+    Some(
+        (0..COLORMAP_SIZE)
+            .map(|i| {
+                let t = i as f32 / (COLORMAP_SIZE - 1) as f32;
+                [0, 1, 2].map(|c| segment(&segments[c], t))
+            })
+            .collect(),
+    )
+}
+
+/// Horizontal bar previewing a colormap
+pub fn preview(ui: &mut egui::Ui, colormap: &Colormap, reversed: bool, height: f32) -> egui::Response {
+    let (rect, response) = ui.allocate_exact_size(egui::vec2(ui.available_width(), height), egui::Sense::hover());
+    let num_columns = rect.width().max(1.0) as usize;
+    for i in 0..num_columns {
+        let t = i as f32 / (num_columns - 1).max(1) as f32;
+        let [r, g, b] = colormap.color_at(if reversed { 1.0 - t } else { t }).map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        let x = rect.left() + i as f32;
+        ui.painter().rect_filled(
+            egui::Rect::from_min_max(egui::pos2(x, rect.top()), egui::pos2(x + 1.0, rect.bottom())),
+            0.0,
+            egui::Color32::from_rgb(r, g, b),
+        );
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builtin(name: &str) -> Colormap {
+        Colormap::builtins().into_iter().find(|colormap| colormap.name == name).unwrap()
+    }
+
+    #[test]
+    fn sample_the_builtin_colormaps() {
+        let jet = builtin("jet").lut(false);
+        assert_eq!(jet.len(), COLORMAP_SIZE);
+        assert_eq!((jet[0], jet[255]), ([0, 0, 128, 255], [128, 0, 0, 255]));
+        assert_eq!(builtin("viridis").lut(false)[0], [71, 1, 85, 255]);
+        assert_eq!(builtin("RdBu").lut(false)[128], [246, 247, 247, 255]);
+        assert_eq!(builtin("grey").lut(false)[100], [100, 100, 100, 255]);
+
+        // the end colors of Moreland survive the trip through the Msh space
+        let cool_warm = builtin("coolwarm").lut(false);
+        assert_eq!((cool_warm[0], cool_warm[255]), ([59, 76, 192, 255], [180, 4, 38, 255]));
+        let reversed = builtin("coolwarm").lut(true);
+        assert_eq!((reversed[0], reversed[255]), (cool_warm[255], cool_warm[0]));
+    }
+
+    #[test]
+    fn load_the_palettes() {
+        let csv = Colormap::from_file("orange", "r,g,b\n0, 0, 0\n255;128;0\n").unwrap();
+        assert_eq!((csv.name.as_str(), csv.lut(false)[255]), ("orange", [255, 128, 0, 255]));
+        assert_eq!(csv.color_at(0.5), [0.5, 64.0 / 255.0, 0.0]);
+        let hexes = Colormap::from_file("hexes", "# CET colormap\n#000000\n#ff8000\n").unwrap();
+        assert_eq!(hexes.lut(false), csv.lut(false));
+
+        let list = Colormap::from_file("list", r##"{"colors": ["#ff0000", [0, 0, 1, 1]]}"##).unwrap();
+        assert_eq!((list.lut(false)[0], list.lut(false)[255]), ([255, 0, 0, 255], [0, 0, 255, 255]));
+
+        let segments = r#"{
+            "name": "magenta",
+            "red": [[0, 0, 0], [1, 1, 1]],
+            "green": [[0, 0, 0], [1, 0, 0]],
+            "blue": [[0, 1, 1], [0.5, 0.2, 0.2], [1, 1, 1]]
+        }"#;
+        let magenta = Colormap::from_file("file", segments).unwrap();
+        assert_eq!(magenta.name, "magenta");
+        assert_eq!(magenta.lut(false)[255], [255, 0, 255, 255]);
+        assert_eq!(magenta.lut(false)[0], [0, 0, 255, 255]);
+
+        for text in ["", "0,0,0\n#ffffff", "0,0,0\nblue", "{\"colors\": [1, 2]", "{\"red\": []}"] {
+            assert!(matches!(Colormap::from_file("bad", text), Err(Error::InvalidColormap { .. })), "{}", text);
+        }
+        // deeply nested documents are rejected rather than overflowing the stack
+        let nested = "[".repeat(100_000);
+        assert!(matches!(Colormap::from_file("nested", &nested), Err(Error::InvalidColormap { .. })));
+    }
+}
@@ -0,0 +1,271 @@
+use std::ops::Range;
+
+use crate::stats::Statistics;
+
+/// Algorithm deriving the initial cuts of a cube from its values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CutStrategy {
+    /// Values at the given percentiles, between 0 and 100
+    Percentiles { low: f32, high: f32 },
+    /// IRAF ZScale algorithm, fitting a line to the sorted values
+    ZScale { contrast: f32 },
+    /// Min and max of the values kept after a sigma-clipping around the median,
+    /// the dispersion being estimated with the median absolute deviation
+    SigmaClip { sigma: f32, iterations: u32 },
+    /// Min and max of the values
+    MinMax,
+}
+
+impl Default for CutStrategy {
+    fn default() -> Self {
+        CutStrategy::Percentiles {
+            low: 1.0,
+            high: 99.0,
+        }
+    }
+}
+
+/// Number of values the ZScale line is fitted on
+const ZSCALE_NUM_SAMPLES: usize = 1000;
+/// Fraction of the values that can be rejected before giving up the fit
+const ZSCALE_MAX_REJECT: f64 = 0.5;
+/// Minimum number of values kept by the fit
+const ZSCALE_MIN_NUM_PIXELS: usize = 5;
+/// Rejection threshold in units of the standard deviation of the residuals
+const ZSCALE_KREJ: f64 = 2.5;
+const ZSCALE_MAX_ITERATIONS: usize = 5;
+
+/// Scale factor from the median absolute deviation to the standard deviation of a normal distribution
+const MAD_TO_SIGMA: f64 = 1.4826;
+
+impl CutStrategy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CutStrategy::Percentiles { .. } => "Percentiles",
+            CutStrategy::ZScale { .. } => "ZScale",
+            CutStrategy::SigmaClip { .. } => "Sigma clipping",
+            CutStrategy::MinMax => "Min/max",
+        }
+    }
+
+    /// Strategies with their default parameters, listed in the options panel
+    pub fn all() -> [CutStrategy; 4] {
+        [
+            CutStrategy::default(),
+            CutStrategy::ZScale { contrast: 0.25 },
+            CutStrategy::SigmaClip {
+                sigma: 3.0,
+                iterations: 5,
+            },
+            CutStrategy::MinMax,
+        ]
+    }
+
+    /// Compute the cuts of a cube
+    ///
+    /// Percentiles and min/max are given by the statistics of the whole cube,
+    /// the other strategies work on a subset of its valid physical values.
+    pub fn compute(&self, stats: &Statistics, samples: &[f64]) -> Range<f64> {
+        if stats.count == 0 {
+            // only blank values
+            return 0.0..1.0;
+        }
+
+        let cuts = match *self {
+            CutStrategy::Percentiles { low, high } => {
+                stats.percentile(low.min(high))..stats.percentile(low.max(high))
+            }
+            CutStrategy::MinMax => stats.min..stats.max,
+            _ if samples.is_empty() => stats.min..stats.max,
+            CutStrategy::ZScale { contrast } => zscale(samples, contrast as f64),
+            CutStrategy::SigmaClip { sigma, iterations } => sigma_clip(samples, sigma as f64, iterations),
+        };
+
+        // the shaders normalize the values by the width of the cuts
+        if cuts.start < cuts.end {
+            cuts
+        } else if stats.min < stats.max {
+            stats.min..stats.max
+        } else {
+            // constant cube
+            (stats.min - 0.5)..(stats.max + 0.5)
+        }
+    }
+}
+
+fn min_max(values: &[f64]) -> Range<f64> {
+    values
+        .iter()
+        .fold(f64::INFINITY..f64::NEG_INFINITY, |r, v| r.start.min(*v)..r.end.max(*v))
+}
+
+/// Median of values, reordering them
+fn median(values: &mut [f64]) -> f64 {
+    let mid = values.len() / 2;
+    let (_, m, _) = values.select_nth_unstable_by(mid, |a, b| a.total_cmp(b));
+    *m
+}
+
+/// Sigma-clipped min and max
+///
+/// Values further than `sigma` times the dispersion from the median are
+/// discarded until none is or the number of iterations is reached. The
+/// dispersion is the scaled median absolute deviation, or the standard
+/// deviation when more than half of the values are equal to the median.
+fn sigma_clip(values: &[f64], sigma: f64, iterations: u32) -> Range<f64> {
+    let mut kept = values.to_vec();
+
+    for _ in 0..iterations {
+        let m = median(&mut kept);
+        let mut deviations = kept.iter().map(|v| (v - m).abs()).collect::<Vec<_>>();
+        let mut dispersion = MAD_TO_SIGMA * median(&mut deviations);
+        if dispersion == 0.0 {
+            // more than half of the values are equal to the median, only the ones
+            // equal to it are kept once the values are constant
+            let mean = kept.iter().sum::<f64>() / kept.len() as f64;
+            dispersion = (kept.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / kept.len() as f64).sqrt();
+        }
+
+        let len = kept.len();
+        kept.retain(|v| (v - m).abs() <= sigma * dispersion);
+        if kept.len() == len || kept.is_empty() {
+            break;
+        }
+    }
+
+    if kept.is_empty() {
+        min_max(values)
+    } else {
+        min_max(&kept)
+    }
+}
+
+/// IRAF ZScale algorithm
+///
+/// A line is fitted to the sorted values with an iterative rejection of the
+/// outliers. The cuts are the values of the line at both ends once its slope
+/// has been divided by the contrast, limited to the range of the values.
+fn zscale(values: &[f64], contrast: f64) -> Range<f64> {
+    let stride = (values.len() / ZSCALE_NUM_SAMPLES).max(1);
+    let mut samples = values.iter().step_by(stride).copied().collect::<Vec<_>>();
+    samples.sort_unstable_by(|a, b| a.total_cmp(b));
+
+    let n = samples.len();
+    let (zmin, zmax) = (samples[0], samples[n - 1]);
+    let min_num_pixels = ZSCALE_MIN_NUM_PIXELS.max((n as f64 * ZSCALE_MAX_REJECT) as usize);
+    // rejected values are grown by a few neighbours
+    let grow = ((n as f64 * 0.01) as usize).max(1);
+
+    let mut rejected = vec![false; n];
+    let mut num_good = n;
+    let mut last_num_good = n + 1;
+    let mut fit = (0.0, 0.0);
+    for _ in 0..ZSCALE_MAX_ITERATIONS {
+        if num_good >= last_num_good || num_good < min_num_pixels {
+            break;
+        }
+
+        fit = fit_line(&samples, &rejected);
+        let (intercept, slope) = fit;
+        let residuals = samples
+            .iter()
+            .enumerate()
+            .map(|(x, v)| v - (intercept + slope * x as f64))
+            .collect::<Vec<_>>();
+
+        // standard deviation of the residuals of the values still kept
+        let kept = residuals.iter().zip(&rejected).filter(|(_, r)| !**r).map(|(d, _)| *d);
+        let mean = kept.clone().sum::<f64>() / num_good as f64;
+        let std = (kept.map(|d| (d - mean) * (d - mean)).sum::<f64>() / num_good as f64).sqrt();
+        let threshold = ZSCALE_KREJ * std;
+
+        let outliers = residuals.iter().map(|d| d.abs() > threshold).collect::<Vec<_>>();
+        for (x, _) in outliers.iter().enumerate().filter(|(_, o)| **o) {
+            let start = x.saturating_sub(grow / 2);
+            let end = (x + grow - grow / 2).min(n);
+            rejected[start..end].iter_mut().for_each(|r| *r = true);
+        }
+
+        last_num_good = num_good;
+        num_good = rejected.iter().filter(|r| !**r).count();
+    }
+
+    if num_good < min_num_pixels {
+        return zmin..zmax;
+    }
+
+    let slope = if contrast > 0.0 { fit.1 / contrast } else { fit.1 };
+    let center = (n - 1) / 2;
+    let m = samples[center];
+
+    zmin.max(m - center as f64 * slope)..zmax.min(m + (n - center - 1) as f64 * slope)
+}
+
+/// Least-squares line (intercept, slope) fitted to the values not rejected
+fn fit_line(values: &[f64], rejected: &[bool]) -> (f64, f64) {
+    let (mut n, mut sx, mut sy, mut sxx, mut sxy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for (x, (y, r)) in values.iter().zip(rejected).enumerate() {
+        if !*r {
+            let x = x as f64;
+            n += 1.0;
+            sx += x;
+            sy += y;
+            sxx += x * x;
+            sxy += x * y;
+        }
+    }
+
+    let det = n * sxx - sx * sx;
+    if det == 0.0 {
+        return (if n > 0.0 { sy / n } else { 0.0 }, 0.0);
+    }
+
+    let slope = (n * sxy - sx * sy) / det;
+    ((sy - slope * sx) / n, slope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statistics(values: &[f64]) -> Statistics {
+        let mut stats = Statistics::default();
+        values.iter().for_each(|v| stats.add(*v));
+        stats
+    }
+
+    #[test]
+    fn clip_the_outliers() {
+        let mut values = (0..100).map(|i| i as f64).collect::<Vec<_>>();
+        values.push(1e6);
+        assert_eq!(sigma_clip(&values, 3.0, 5), 0.0..99.0);
+
+        // a ramp is its own ZScale line
+        let cuts = zscale(&values, 1.0);
+        assert!(cuts.start.abs() < 1.0 && (cuts.end - 99.0).abs() < 2.0, "{:?}", cuts);
+
+        let cuts = CutStrategy::Percentiles { low: 10.0, high: 90.0 }.compute(&statistics(&values), &values);
+        assert!((cuts.start - 10.0).abs() < 0.5 && (cuts.end - 90.0).abs() < 0.5, "{:?}", cuts);
+        assert_eq!(CutStrategy::MinMax.compute(&statistics(&values), &[]), 0.0..1e6);
+    }
+
+    #[test]
+    fn keep_cuts_apart_on_degenerate_cubes() {
+        // the median absolute deviation is null when most values are equal, the outlier
+        // is clipped with the standard deviation
+        let mut values = vec![5.0; 10];
+        values.push(1000.0);
+        assert_eq!(sigma_clip(&values, 3.0, 5), 5.0..5.0);
+
+        for strategy in CutStrategy::all() {
+            let constant = [2.0; 10];
+            assert_eq!(strategy.compute(&statistics(&constant), &constant), 1.5..2.5, "{}", strategy.name());
+
+            // percentiles falling on the same value
+            let cuts = strategy.compute(&statistics(&values), &values);
+            assert!(cuts.start < cuts.end, "{}", strategy.name());
+        }
+
+        assert_eq!(CutStrategy::default().compute(&Statistics::default(), &[]), 0.0..1.0);
+    }
+}
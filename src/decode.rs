@@ -0,0 +1,571 @@
+use crate::error::Error;
+use crate::progress::Progress;
+use crate::stats::Statistics;
+use rayon::prelude::*;
+use std::convert::TryInto;
+
+/// Linear scaling from the stored values to the physical ones
+///
+/// physical = BZERO + BSCALE * stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scale {
+    pub bscale: f64,
+    pub bzero: f64,
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Self {
+            bscale: 1.0,
+            bzero: 0.0,
+        }
+    }
+}
+
+impl Scale {
+    pub fn is_identity(&self) -> bool {
+        self.bscale == 1.0 && self.bzero == 0.0
+    }
+
+    #[inline]
+    pub fn apply(&self, stored: f64) -> f64 {
+        self.bzero + self.bscale * stored
+    }
+}
+
+/// Mapping from the physical values to the f32 ones stored on the GPU
+///
+/// gpu = (physical - offset) / scale
+///
+/// Double precision cubes can have a dynamic range or an offset that f32
+/// cannot hold. Centering and rescaling them before the downconversion
+/// keeps their precision on the GPU.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Encoding {
+    pub offset: f64,
+    pub scale: f64,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Self {
+            offset: 0.0,
+            scale: 1.0,
+        }
+    }
+}
+
+impl Encoding {
+    /// Encoding mapping the range of values given onto [-1, 1]
+    pub fn from_range(min: f64, max: f64) -> Self {
+        let scale = 0.5 * (max - min);
+
+        if scale.is_finite() && scale > 0.0 {
+            Self {
+                offset: 0.5 * (min + max),
+                scale,
+            }
+        } else {
+            Self::default()
+        }
+    }
+
+    #[inline]
+    pub fn to_gpu(self, physical: f64) -> f32 {
+        ((physical - self.offset) / self.scale) as f32
+    }
+}
+
+/// A pixel type that can be found in a FITS data unit
+///
+/// FITS stores its values in big-endian. Each pixel type knows
+/// how to read itself from those bytes and how to convert itself
+/// to the f32 value that will be sent to the GPU.
+pub trait Pixel: Copy {
+    /// Number of bytes of one pixel in the data unit
+    const NUM_BYTES: usize;
+
+    fn from_be_bytes(bytes: &[u8]) -> Self;
+    fn to_f32(self) -> f32;
+    fn to_f64(self) -> f64;
+    /// Tell whether the pixel is undefined, i.e. a NaN for floating point
+    /// types or the BLANK value for integer ones
+    fn is_blank(self, blank: Option<i64>) -> bool;
+}
+
+impl Pixel for u8 {
+    const NUM_BYTES: usize = 1;
+
+    fn from_be_bytes(bytes: &[u8]) -> Self {
+        bytes[0]
+    }
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+    fn is_blank(self, blank: Option<i64>) -> bool {
+        blank == Some(self as i64)
+    }
+}
+
+impl Pixel for i16 {
+    const NUM_BYTES: usize = 2;
+
+    fn from_be_bytes(bytes: &[u8]) -> Self {
+        i16::from_be_bytes(bytes.try_into().unwrap())
+    }
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+    fn is_blank(self, blank: Option<i64>) -> bool {
+        blank == Some(self as i64)
+    }
+}
+
+impl Pixel for i32 {
+    const NUM_BYTES: usize = 4;
+
+    fn from_be_bytes(bytes: &[u8]) -> Self {
+        i32::from_be_bytes(bytes.try_into().unwrap())
+    }
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+    fn is_blank(self, blank: Option<i64>) -> bool {
+        blank == Some(self as i64)
+    }
+}
+
+impl Pixel for i64 {
+    const NUM_BYTES: usize = 8;
+
+    fn from_be_bytes(bytes: &[u8]) -> Self {
+        i64::from_be_bytes(bytes.try_into().unwrap())
+    }
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+    fn is_blank(self, blank: Option<i64>) -> bool {
+        blank == Some(self)
+    }
+}
+
+impl Pixel for f32 {
+    const NUM_BYTES: usize = 4;
+
+    fn from_be_bytes(bytes: &[u8]) -> Self {
+        f32::from_be_bytes(bytes.try_into().unwrap())
+    }
+    fn to_f32(self) -> f32 {
+        self
+    }
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+    fn is_blank(self, _blank: Option<i64>) -> bool {
+        self.is_nan()
+    }
+}
+
+impl Pixel for f64 {
+    const NUM_BYTES: usize = 8;
+
+    fn from_be_bytes(bytes: &[u8]) -> Self {
+        f64::from_be_bytes(bytes.try_into().unwrap())
+    }
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+    fn to_f64(self) -> f64 {
+        self
+    }
+    fn is_blank(self, _blank: Option<i64>) -> bool {
+        self.is_nan()
+    }
+}
+
+/// Number of bytes of a pixel given the BITPIX of the HDU
+pub fn num_bytes_per_pixel(bitpix: i64) -> Result<usize, Error> {
+    match bitpix {
+        8 => Ok(u8::NUM_BYTES),
+        16 => Ok(i16::NUM_BYTES),
+        32 => Ok(i32::NUM_BYTES),
+        64 => Ok(i64::NUM_BYTES),
+        -32 => Ok(f32::NUM_BYTES),
+        -64 => Ok(f64::NUM_BYTES),
+        _ => Err(Error::UnsupportedBitpix { bitpix }),
+    }
+}
+
+/// Value of the mask for a valid voxel
+pub const VALID: u8 = 255;
+/// Value of the mask for a NaN or BLANK voxel
+pub const INVALID: u8 = 0;
+
+/// Number of pixels of the chunks decoded in parallel
+const CHUNK_NUM_PIXELS: usize = 1 << 18;
+
+#[inline]
+fn decode_pixel<T: Pixel>(p: T, scale: &Scale, blank: Option<i64>) -> (f32, u8) {
+    if p.is_blank(blank) {
+        (f32::NAN, INVALID)
+    } else if scale.is_identity() {
+        (p.to_f32(), VALID)
+    } else {
+        // the scaling is done in double precision so that large BZERO
+        // offsets (e.g. unsigned integers) do not eat the f32 mantissa
+        (scale.apply(p.to_f64()) as f32, VALID)
+    }
+}
+
+/// Decode the pixels by chunks in parallel, accumulating the statistics of
+/// their physical values on the way if `statistics` is set
+fn decode_pixels<T: Pixel>(
+    data: &[u8],
+    scale: &Scale,
+    blank: Option<i64>,
+    progress: &Progress,
+    statistics: bool,
+) -> (Vec<f32>, Vec<u8>, Statistics) {
+    let num_pixels = data.len() / T::NUM_BYTES;
+    let mut values = vec![0.0; num_pixels];
+    let mut mask = vec![INVALID; num_pixels];
+
+    let stats = values
+        .par_chunks_mut(CHUNK_NUM_PIXELS)
+        .zip(mask.par_chunks_mut(CHUNK_NUM_PIXELS))
+        .zip(data.par_chunks(CHUNK_NUM_PIXELS * T::NUM_BYTES))
+        .fold(Statistics::default, |mut stats, ((values, mask), bytes)| {
+            if progress.is_cancelled() {
+                return stats;
+            }
+            for ((v, m), b) in values.iter_mut().zip(mask.iter_mut()).zip(bytes.chunks_exact(T::NUM_BYTES)) {
+                let p = T::from_be_bytes(b);
+                (*v, *m) = decode_pixel(p, scale, blank);
+                if statistics && *m == VALID {
+                    stats.add(scale.apply(p.to_f64()));
+                }
+            }
+            progress.advance(bytes.len() as u64);
+            stats
+        })
+        .reduce(Statistics::default, |mut stats, other| {
+            stats.merge(&other);
+            stats
+        });
+
+    (values, mask, stats)
+}
+
+/// Decode pixels that have already been read from the data unit
+///
+/// This is the case of tile-compressed images whose pixels are given by the
+/// decompressor. Scaling and masking follow the same rules as [decode].
+///
+/// The statistics are the ones of the valid pixels whose index is accepted
+/// by `in_volume`, i.e. of the hyperplane displayed.
+pub fn decode_values<T: Pixel>(
+    pixels: impl Iterator<Item = T>,
+    scale: &Scale,
+    blank: Option<i64>,
+    in_volume: impl Fn(usize) -> bool,
+) -> (Vec<f32>, Vec<u8>, Statistics) {
+    let (num_pixels, _) = pixels.size_hint();
+    let mut values = Vec::with_capacity(num_pixels);
+    let mut mask = Vec::with_capacity(num_pixels);
+    let mut stats = Statistics::default();
+
+    for (i, p) in pixels.enumerate() {
+        let (v, m) = decode_pixel(p, scale, blank);
+        if m == VALID && in_volume(i) {
+            stats.add(scale.apply(p.to_f64()));
+        }
+        values.push(v);
+        mask.push(m);
+    }
+
+    (values, mask, stats)
+}
+
+/// Decode the big-endian data unit into a native-endian f32 volume
+///
+/// The BSCALE/BZERO scaling is applied so that the returned values are in
+/// physical units. They are the ones uploaded to the GPU and the ones the cuts
+/// are computed on so that both always agree.
+///
+/// A validity mask is returned along with the values. NaNs and BLANK voxels
+/// are marked as [INVALID] and their value is set to NaN. The statistics of
+/// the physical values of the valid voxels, computed in double precision,
+/// are accumulated while decoding.
+///
+/// The data unit is decoded by chunks in parallel, `progress` being
+/// advanced by the number of bytes decoded. The chunks left are skipped
+/// once the loading is cancelled.
+pub fn decode(
+    data: &[u8],
+    bitpix: i64,
+    scale: &Scale,
+    blank: Option<i64>,
+    progress: &Progress,
+) -> Result<(Vec<f32>, Vec<u8>, Statistics), Error> {
+    decode_bitpix(data, bitpix, scale, blank, progress, true)
+}
+
+fn decode_bitpix(
+    data: &[u8],
+    bitpix: i64,
+    scale: &Scale,
+    blank: Option<i64>,
+    progress: &Progress,
+    statistics: bool,
+) -> Result<(Vec<f32>, Vec<u8>, Statistics), Error> {
+    match bitpix {
+        8 => Ok(decode_pixels::<u8>(data, scale, blank, progress, statistics)),
+        16 => Ok(decode_pixels::<i16>(data, scale, blank, progress, statistics)),
+        32 => Ok(decode_pixels::<i32>(data, scale, blank, progress, statistics)),
+        64 => Ok(decode_pixels::<i64>(data, scale, blank, progress, statistics)),
+        -32 => Ok(decode_pixels::<f32>(data, scale, blank, progress, statistics)),
+        -64 => Ok(decode_pixels::<f64>(data, scale, blank, progress, statistics)),
+        _ => Err(Error::UnsupportedBitpix { bitpix }),
+    }
+}
+
+fn for_each_pixel<T: Pixel>(data: &[u8], scale: &Scale, blank: Option<i64>, f: &mut impl FnMut(f64)) {
+    for p in data.chunks_exact(T::NUM_BYTES).map(T::from_be_bytes) {
+        if !p.is_blank(blank) {
+            f(scale.apply(p.to_f64()));
+        }
+    }
+}
+
+/// Call `f` with the physical value of each valid pixel of a data unit
+///
+/// Nothing is allocated so that statistics can be streamed over the bytes
+/// of the file. Values are given in double precision, before any encoding.
+pub fn for_each_value(
+    data: &[u8],
+    bitpix: i64,
+    scale: &Scale,
+    blank: Option<i64>,
+    mut f: impl FnMut(f64),
+) -> Result<(), Error> {
+    match bitpix {
+        8 => for_each_pixel::<u8>(data, scale, blank, &mut f),
+        16 => for_each_pixel::<i16>(data, scale, blank, &mut f),
+        32 => for_each_pixel::<i32>(data, scale, blank, &mut f),
+        64 => for_each_pixel::<i64>(data, scale, blank, &mut f),
+        -32 => for_each_pixel::<f32>(data, scale, blank, &mut f),
+        -64 => for_each_pixel::<f64>(data, scale, blank, &mut f),
+        _ => return Err(Error::UnsupportedBitpix { bitpix }),
+    }
+
+    Ok(())
+}
+
+/// Decode a BITPIX = -64 data unit keeping the double precision
+///
+/// NaNs are kept in the values and flagged as [INVALID] in the returned mask.
+/// The statistics of the valid values are accumulated while decoding.
+pub fn decode_f64(data: &[u8], scale: &Scale, progress: &Progress) -> (Vec<f64>, Vec<u8>, Statistics) {
+    decode_pixels_f64(data, scale, progress, true)
+}
+
+fn decode_pixels_f64(data: &[u8], scale: &Scale, progress: &Progress, statistics: bool) -> (Vec<f64>, Vec<u8>, Statistics) {
+    let identity = scale.is_identity();
+
+    let num_pixels = data.len() / f64::NUM_BYTES;
+    let mut values = vec![0.0; num_pixels];
+    let mut mask = vec![INVALID; num_pixels];
+
+    let stats = values
+        .par_chunks_mut(CHUNK_NUM_PIXELS)
+        .zip(mask.par_chunks_mut(CHUNK_NUM_PIXELS))
+        .zip(data.par_chunks(CHUNK_NUM_PIXELS * f64::NUM_BYTES))
+        .fold(Statistics::default, |mut stats, ((values, mask), bytes)| {
+            if progress.is_cancelled() {
+                return stats;
+            }
+            for ((v, m), b) in values.iter_mut().zip(mask.iter_mut()).zip(bytes.chunks_exact(f64::NUM_BYTES)) {
+                let p = <f64 as Pixel>::from_be_bytes(b);
+
+                (*v, *m) = if p.is_nan() {
+                    (p, INVALID)
+                } else if identity {
+                    (p, VALID)
+                } else {
+                    (scale.apply(p), VALID)
+                };
+                if statistics && *m == VALID {
+                    stats.add(*v);
+                }
+            }
+            progress.advance(bytes.len() as u64);
+            stats
+        })
+        .reduce(Statistics::default, |mut stats, other| {
+            stats.merge(&other);
+            stats
+        });
+
+    (values, mask, stats)
+}
+
+/// Decode a data unit into the f32 values sent to the GPU
+///
+/// Double precision values are mapped with `encoding` before their
+/// downconversion, the other ones are decoded as with [decode].
+pub fn decode_encoded(
+    data: &[u8],
+    bitpix: i64,
+    scale: &Scale,
+    blank: Option<i64>,
+    encoding: &Encoding,
+) -> Result<(Vec<f32>, Vec<u8>), Error> {
+    // bricks are small enough not to report their progress
+    let progress = Progress::default();
+
+    if bitpix == -64 {
+        let (values, mask, _) = decode_pixels_f64(data, scale, &progress, false);
+        Ok((encode_f32(&values, encoding), mask))
+    } else {
+        let (values, mask, _) = decode_bitpix(data, bitpix, scale, blank, &progress, false)?;
+        Ok((values, mask))
+    }
+}
+
+/// Downconvert double precision values to the f32 ones sent to the GPU
+pub fn encode_f32(values: &[f64], encoding: &Encoding) -> Vec<f32> {
+    values.par_iter().map(|v| encoding.to_gpu(*v)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::Stage;
+
+    /// Data unit made of big-endian pixels
+    fn data_unit<const N: usize>(pixels: impl IntoIterator<Item = [u8; N]>) -> Vec<u8> {
+        pixels.into_iter().flatten().collect()
+    }
+
+    #[test]
+    fn decode_every_bitpix() {
+        let progress = Progress::default();
+        let scale = Scale::default();
+
+        let data_units = [
+            (8, vec![0, 2, 255], [0.0, 2.0, 255.0]),
+            (16, data_unit([-2_i16, 0, 300].map(i16::to_be_bytes)), [-2.0, 0.0, 300.0]),
+            (32, data_unit([-2_i32, 0, 300].map(i32::to_be_bytes)), [-2.0, 0.0, 300.0]),
+            (64, data_unit([-2_i64, 0, 300].map(i64::to_be_bytes)), [-2.0, 0.0, 300.0]),
+            (-32, data_unit([-2.5_f32, 0.0, 300.5].map(f32::to_be_bytes)), [-2.5, 0.0, 300.5]),
+            (-64, data_unit([-2.5_f64, 0.0, 300.5].map(f64::to_be_bytes)), [-2.5, 0.0, 300.5]),
+        ];
+        for (bitpix, data, expected) in data_units {
+            let (values, mask, stats) = decode(&data, bitpix, &scale, None, &progress).unwrap();
+
+            assert_eq!(values, expected, "BITPIX = {}", bitpix);
+            assert_eq!(mask, [VALID; 3]);
+            assert_eq!(stats.count, 3);
+        }
+
+        assert!(decode(&[0; 3], 24, &scale, None, &progress).is_err());
+    }
+
+    #[test]
+    fn apply_bscale_bzero() {
+        let progress = Progress::default();
+
+        // unsigned 16 bits integers are stored with BZERO = 32768
+        let unsigned = Scale { bscale: 1.0, bzero: 32768.0 };
+        let data = data_unit([i16::MIN, -1, i16::MAX].map(i16::to_be_bytes));
+        let (values, _, stats) = decode(&data, 16, &unsigned, None, &progress).unwrap();
+        assert_eq!(values, [0.0, 32767.0, 65535.0]);
+        assert_eq!((stats.min, stats.max), (0.0, 65535.0));
+
+        let scale = Scale { bscale: 0.5, bzero: -10.0 };
+        let data = data_unit([-4_i32, 0, 7].map(i32::to_be_bytes));
+        let (values, _, stats) = decode(&data, 32, &scale, None, &progress).unwrap();
+        assert_eq!(values, [-12.0, -10.0, -6.5]);
+        assert_eq!(stats.mean, -9.5);
+    }
+
+    #[test]
+    fn mask_blank_and_nan_voxels() {
+        let progress = Progress::default();
+        let scale = Scale { bscale: 2.0, bzero: 1.0 };
+
+        // BLANK is compared to the stored value, before the scaling
+        let data = data_unit([5_i16, -1, 3].map(i16::to_be_bytes));
+        let (values, mask, stats) = decode(&data, 16, &scale, Some(-1), &progress).unwrap();
+        assert_eq!(mask, [VALID, INVALID, VALID]);
+        assert_eq!((values[0], values[2]), (11.0, 7.0));
+        assert!(values[1].is_nan());
+        assert_eq!((stats.count, stats.min, stats.max), (2, 7.0, 11.0));
+
+        // BLANK does not apply to floating point data units
+        let data = data_unit([1.0, f32::NAN, -1.0].map(f32::to_be_bytes));
+        let (values, mask, _) = decode(&data, -32, &Scale::default(), Some(-1), &progress).unwrap();
+        assert_eq!(mask, [VALID, INVALID, VALID]);
+        assert_eq!(values[2], -1.0);
+
+        let (values, mask, _) = decode_values([0_u8, 255, 7].iter().copied(), &scale, Some(255), |_| true);
+        assert_eq!(mask, [VALID, INVALID, VALID]);
+        assert_eq!((values[0], values[2]), (1.0, 15.0));
+    }
+
+    #[test]
+    fn keep_the_precision_of_double_cubes() {
+        let progress = Progress::default();
+
+        // an offset f32 cannot hold next to the variations
+        let data = data_unit([1e10 + 0.25, f64::NAN, 1e10 + 0.75].map(f64::to_be_bytes));
+        let (values, mask, stats) = decode_f64(&data, &Scale::default(), &progress);
+        assert_eq!(mask, [VALID, INVALID, VALID]);
+        assert_eq!((stats.min, stats.max), (1e10 + 0.25, 1e10 + 0.75));
+
+        let encoding = Encoding::from_range(stats.min, stats.max);
+        let encoded = encode_f32(&values, &encoding);
+        assert_eq!((encoded[0], encoded[2]), (-1.0, 1.0));
+        assert_eq!(encoding.to_gpu(1e10 + 0.5), 0.0);
+
+        // a constant cube is not rescaled
+        assert_eq!(Encoding::from_range(2.0, 2.0), Encoding::default());
+    }
+
+    #[test]
+    fn decode_chunks_in_parallel() {
+        // a few chunks of pixels
+        let num_pixels = 3 * CHUNK_NUM_PIXELS + 10;
+        let data = data_unit((0..num_pixels).map(|i| (i as i32 % 1000).to_be_bytes()));
+
+        let progress = Progress::default();
+        progress.start(Stage::Parse, data.len() as u64);
+        let (values, _, stats) = decode(&data, 32, &Scale::default(), None, &progress).unwrap();
+        assert_eq!(progress.fraction(), 1.0);
+        assert_eq!(values.len(), num_pixels);
+        assert_eq!(values[CHUNK_NUM_PIXELS + 7], ((CHUNK_NUM_PIXELS + 7) % 1000) as f32);
+
+        let mut expected = Statistics::default();
+        values.iter().for_each(|v| expected.add(*v as f64));
+        assert_eq!((stats.count, stats.min, stats.max), (expected.count, 0.0, 999.0));
+        assert!((stats.mean - expected.mean).abs() < 1e-9);
+
+        // the chunks are skipped once the loading is cancelled
+        progress.cancel();
+        let (_, _, stats) = decode(&data, 32, &Scale::default(), None, &progress).unwrap();
+        assert_eq!(stats.count, 0);
+        assert_eq!(progress.check(), Err(Error::Cancelled));
+    }
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+
+/// Errors raised while loading a cube and uploading it to the GPU
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The bytes do not start with a FITS primary header
+    NotFits,
+    /// The gzip stream is corrupted or truncated
+    Gzip { reason: String },
+    /// None of the HDUs of the file stores a cube
+    NoCube,
+    /// The HDU asked does not store a cube
+    NotACube { hdu: usize, naxis: Vec<u64> },
+    /// A NAXISn card announced by NAXIS is missing from the header
+    MissingAxisCard { hdu: usize, keyword: String },
+    /// BITPIX (or ZBITPIX) is not one of the values allowed by the standard
+    UnsupportedBitpix { bitpix: i64 },
+    /// The tiles are compressed with an algorithm that is not decompressed
+    UnsupportedCompression { zcmptype: Option<String> },
+    /// A tile of a compressed image could not be decompressed
+    CorruptedTile { hdu: usize, tile: usize },
+    /// The data unit ends before all the pixels announced by the header
+    TruncatedData { hdu: usize, expected: usize, available: usize },
+    /// The WCS keywords of the header could not be parsed, the cube is still shown
+    Wcs { reason: String },
+    /// A texture is bigger than what the device supports
+    TextureTooBig { label: String, dim: (u32, u32, u32), limit: u32 },
+    /// An HDU or an axis mapping is asked while no file is loaded
+    NoFileLoaded,
+    /// The loading has been cancelled by the user
+    Cancelled,
+    /// A stored view could not be parsed
+    InvalidView { reason: String },
+    /// A palette file could not be parsed
+    InvalidColormap { reason: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFits => write!(f, "Is not a FITS file"),
+            Error::Gzip { reason } => write!(f, "Corrupted gzip file: {}", reason),
+            Error::NoCube => write!(f, "No HDU of the file stores a cube"),
+            Error::NotACube { hdu, naxis } => {
+                let dims = naxis.iter().map(|n| n.to_string()).collect::<Vec<_>>().join("x");
+                write!(f, "HDU #{} does not store a cube (dimensions: [{}])", hdu, dims)
+            }
+            Error::MissingAxisCard { hdu, keyword } => write!(f, "{} card is missing in HDU #{}", keyword, hdu),
+            Error::UnsupportedBitpix { bitpix } => {
+                write!(f, "BITPIX = {} is not supported, expected one of 8, 16, 32, 64, -32, -64", bitpix)
+            }
+            Error::UnsupportedCompression { zcmptype: Some(zcmptype) } => {
+                write!(
+                    f,
+                    "{} tile compression is not supported, only GZIP_1, GZIP_2, RICE_1 and HCOMPRESS_1 tiles are decompressed",
+                    zcmptype
+                )
+            }
+            Error::UnsupportedCompression { zcmptype: None } => write!(f, "Unknown tile compression"),
+            Error::CorruptedTile { hdu, tile } => write!(f, "Tile #{} of HDU #{} is corrupted", tile, hdu),
+            Error::TruncatedData { hdu, expected, available } => write!(
+                f,
+                "Data unit of HDU #{} is smaller than expected ({} bytes instead of {})",
+                hdu, available, expected
+            ),
+            Error::Wcs { reason } => write!(f, "Invalid WCS, pixel coordinates are shown instead: {}", reason),
+            Error::TextureTooBig { label, dim, limit } => write!(
+                f,
+                "The {} texture of {}x{}x{} texels exceeds the device limit of {} texels per axis",
+                label, dim.0, dim.1, dim.2, limit
+            ),
+            Error::NoFileLoaded => write!(f, "No FITS file loaded"),
+            Error::Cancelled => write!(f, "Loading cancelled"),
+            Error::InvalidView { reason } => write!(f, "Invalid view: {}", reason),
+            Error::InvalidColormap { reason } => write!(f, "Invalid colormap: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
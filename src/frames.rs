@@ -0,0 +1,384 @@
+use fitsrs::hdu::header::ValueMap;
+
+use crate::spectral::C;
+use crate::{get_float, get_integer, get_string};
+
+/// Astronomical unit, in m
+const AU: f64 = 1.495_978_707e11;
+/// Rotation rate of the Earth, in rad/s
+const EARTH_ROTATION: f64 = 7.292_115e-5;
+/// Julian date of the J2000.0 epoch
+const J2000: f64 = 2_451_545.0;
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+/// Velocity of the Sun with respect to the kinematic LSR, in m/s
+const LSRK_SPEED: f64 = 20e3;
+/// Direction of the solar motion with respect to the kinematic LSR (J2000 RA and Dec, in degrees),
+/// the standard 18h +30d of B1900 precessed to J2000
+const LSRK_APEX: (f64, f64) = (270.959_54, 30.004_67);
+/// Peculiar motion (U, V, W) of the Sun with respect to the dynamical LSR, in m/s
+const LSRD_MOTION: [f64; 3] = [9e3, 12e3, 7e3];
+
+/// Rotation matrix from ICRS to galactic cartesian coordinates (Hipparcos)
+const ICRS_TO_GALACTIC: [[f64; 3]; 3] = [
+    [-0.054_875_560_416_215_4, -0.873_437_090_234_885, -0.483_835_015_548_713_2],
+    [0.494_109_427_875_583_7, -0.444_829_629_960_011_2, 0.746_982_244_497_219],
+    [-0.867_666_149_019_004_7, -0.198_076_373_431_201_5, 0.455_983_776_175_066_9],
+];
+
+/// Spectral reference frames of FITS WCS Paper III handled by the viewer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpectralFrame {
+    Topocentric,
+    Geocentric,
+    Barycentric,
+    Heliocentric,
+    LsrKinematic,
+    LsrDynamical,
+}
+
+impl SpectralFrame {
+    /// Frame given by a SPECSYS or SSYSOBS value
+    pub fn from_keyword(keyword: &str) -> Option<Self> {
+        match keyword.trim().to_uppercase().as_str() {
+            "TOPOCENT" => Some(SpectralFrame::Topocentric),
+            "GEOCENTR" => Some(SpectralFrame::Geocentric),
+            "BARYCENT" => Some(SpectralFrame::Barycentric),
+            "HELIOCEN" => Some(SpectralFrame::Heliocentric),
+            "LSRK" => Some(SpectralFrame::LsrKinematic),
+            "LSRD" => Some(SpectralFrame::LsrDynamical),
+            _ => None,
+        }
+    }
+
+    /// Frame given by the AIPS VELREF convention of older headers
+    fn from_velref(velref: i64) -> Option<Self> {
+        match velref % 256 {
+            1 => Some(SpectralFrame::LsrKinematic),
+            2 => Some(SpectralFrame::Heliocentric),
+            3 => Some(SpectralFrame::Topocentric),
+            _ => None,
+        }
+    }
+
+    pub fn all() -> [SpectralFrame; 6] {
+        [
+            SpectralFrame::Topocentric,
+            SpectralFrame::Geocentric,
+            SpectralFrame::Barycentric,
+            SpectralFrame::Heliocentric,
+            SpectralFrame::LsrKinematic,
+            SpectralFrame::LsrDynamical,
+        ]
+    }
+
+    /// SPECSYS value of the frame, also given to the selection callback
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            SpectralFrame::Topocentric => "TOPOCENT",
+            SpectralFrame::Geocentric => "GEOCENTR",
+            SpectralFrame::Barycentric => "BARYCENT",
+            SpectralFrame::Heliocentric => "HELIOCEN",
+            SpectralFrame::LsrKinematic => "LSRK",
+            SpectralFrame::LsrDynamical => "LSRD",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpectralFrame::Topocentric => "Topocentric",
+            SpectralFrame::Geocentric => "Geocentric",
+            SpectralFrame::Barycentric => "Barycentric",
+            SpectralFrame::Heliocentric => "Heliocentric",
+            SpectralFrame::LsrKinematic => "LSR kinematic",
+            SpectralFrame::LsrDynamical => "LSR dynamical",
+        }
+    }
+}
+
+/// Spectral frame of the axis from SPECSYS, or VELREF for older headers
+pub fn spectral_frame(header: &ValueMap) -> Option<SpectralFrame> {
+    match get_string(header, "SPECSYS") {
+        Some(specsys) => SpectralFrame::from_keyword(&specsys),
+        None => get_integer(header, "VELREF").and_then(SpectralFrame::from_velref),
+    }
+}
+
+/// Circumstances of an observation the velocities of the frames are computed from
+///
+/// The velocities are computed with low precision formulas, accurate to a few
+/// tens of m/s, that do not need any ephemeris to be downloaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observation {
+    /// Direction of the pointing, ICRS unit vector
+    direction: [f64; 3],
+    /// Julian date of the observation, needed by the frames moving with the Earth
+    jd: Option<f64>,
+    /// Geocentric position of the observatory (ITRS, in m)
+    location: Option<[f64; 3]>,
+    /// Frame in which the observer is at rest (SSYSOBS), topocentric by default
+    observer: SpectralFrame,
+}
+
+impl Observation {
+    /// Observation described by a header, `None` if its pointing is unknown
+    pub fn from_header(header: &ValueMap) -> Option<Self> {
+        Some(Self {
+            direction: pointing(header)?,
+            jd: epoch(header),
+            location: location(header),
+            observer: get_string(header, "SSYSOBS")
+                .and_then(|ssysobs| SpectralFrame::from_keyword(&ssysobs))
+                .unwrap_or(SpectralFrame::Topocentric),
+        })
+    }
+
+    /// Velocity of a frame with respect to the barycenter, ICRS, in m/s
+    ///
+    /// The heliocentric frame is assimilated to the barycentric one, the Sun
+    /// moving by less than 15 m/s around the barycenter.
+    fn velocity(&self, frame: SpectralFrame) -> Option<[f64; 3]> {
+        match frame {
+            // the observer is at rest in the frame given by SSYSOBS
+            SpectralFrame::Topocentric if self.observer != SpectralFrame::Topocentric => self.velocity(self.observer),
+            SpectralFrame::Topocentric => {
+                let earth = earth_velocity(self.jd?);
+                // without its location the rotation of the observatory (< 0.5 km/s) is neglected
+                let rotation = self.location.map(|r| rotation_velocity(r, self.jd.unwrap_or(J2000))).unwrap_or([0.0; 3]);
+                Some(add(earth, rotation))
+            }
+            SpectralFrame::Geocentric => Some(earth_velocity(self.jd?)),
+            SpectralFrame::Barycentric | SpectralFrame::Heliocentric => Some([0.0; 3]),
+            SpectralFrame::LsrKinematic => Some(scale(radec_to_vector(LSRK_APEX.0, LSRK_APEX.1), -LSRK_SPEED)),
+            SpectralFrame::LsrDynamical => Some(scale(galactic_to_icrs(LSRD_MOTION), -1.0)),
+        }
+    }
+
+    /// Ratio of the frequency of a photon measured in a frame to the one measured in another
+    ///
+    /// The observer of the frame `to` moves towards the source, i.e. sees
+    /// higher frequencies, when its velocity relative to `from` points to it.
+    pub fn doppler(&self, from: SpectralFrame, to: SpectralFrame) -> Option<f64> {
+        if from == to {
+            return Some(1.0);
+        }
+
+        let velocity = sub(self.velocity(to)?, self.velocity(from)?);
+        let beta = dot(velocity, self.direction) / C;
+        Some(((1.0 + beta) / (1.0 - beta)).sqrt())
+    }
+}
+
+/// Pointing of the observation given by the reference point of the celestial axes
+///
+/// The OBSRA/OBSDEC and RA/DEC keywords are used when the cube has no celestial axis.
+fn pointing(header: &ValueMap) -> Option<[f64; 3]> {
+    let num_axis = get_integer(header, "NAXIS").unwrap_or(0);
+    let crval = |prefix: &str| {
+        (1..=num_axis).find_map(|n| {
+            let ctype = get_string(header, &format!("CTYPE{}", n))?.to_uppercase();
+            if ctype.split('-').next() == Some(prefix) {
+                get_float(header, &format!("CRVAL{}", n))
+            } else {
+                None
+            }
+        })
+    };
+
+    if let (Some(ra), Some(dec)) = (crval("RA"), crval("DEC")) {
+        Some(radec_to_vector(ra, dec))
+    } else if let (Some(l), Some(b)) = (crval("GLON"), crval("GLAT")) {
+        Some(galactic_to_icrs(radec_to_vector(l, b)))
+    } else {
+        let ra = get_float(header, "OBSRA").or_else(|| get_float(header, "RA"))?;
+        let dec = get_float(header, "OBSDEC").or_else(|| get_float(header, "DEC"))?;
+        Some(radec_to_vector(ra, dec))
+    }
+}
+
+/// Julian date of the observation from MJD-AVG, DATE-AVG, MJD-OBS or DATE-OBS
+fn epoch(header: &ValueMap) -> Option<f64> {
+    let mjd = |key: &str| get_float(header, key).map(|mjd| mjd + 2_400_000.5);
+    let date = |key: &str| get_string(header, key).and_then(|date| iso_to_jd(&date));
+
+    mjd("MJD-AVG")
+        .or_else(|| date("DATE-AVG"))
+        .or_else(|| mjd("MJD-OBS"))
+        .or_else(|| date("DATE-OBS"))
+}
+
+/// Julian date of an ISO 8601 date, e.g. 2019-05-03T12:34:56.7
+fn iso_to_jd(date: &str) -> Option<f64> {
+    let (day, time) = match date.split_once('T') {
+        Some((day, time)) => (day, Some(time)),
+        None => (date, None),
+    };
+
+    let mut ymd = day.split('-').map(|s| s.parse::<i64>().ok());
+    let (y, m, d) = (ymd.next()??, ymd.next()??, ymd.next()??);
+
+    let mut seconds = 0.0;
+    if let Some(time) = time {
+        for (field, unit) in time.split(':').zip([3600.0, 60.0, 1.0]) {
+            seconds += field.parse::<f64>().ok()? * unit;
+        }
+    }
+
+    // Meeus, Astronomical Algorithms, chapter 7 (Gregorian calendar)
+    let (y, m) = if m <= 2 { (y - 1, m + 12) } else { (y, m) };
+    let a = y.div_euclid(100);
+    let b = 2 - a + a.div_euclid(4);
+    let jd = (365.25 * (y + 4716) as f64).floor() + (30.6001 * (m + 1) as f64).floor() + d as f64 + b as f64 - 1524.5;
+
+    Some(jd + seconds / SECONDS_PER_DAY)
+}
+
+/// Geocentric position (ITRS, in m) of the observatory
+///
+/// Given by OBSGEO-X/Y/Z, or by the geodetic OBSGEO-L/B/H on the WGS84 ellipsoid.
+fn location(header: &ValueMap) -> Option<[f64; 3]> {
+    let xyz = ["OBSGEO-X", "OBSGEO-Y", "OBSGEO-Z"].map(|key| get_float(header, key));
+    if let [Some(x), Some(y), Some(z)] = xyz {
+        return Some([x, y, z]);
+    }
+
+    let lon = get_float(header, "OBSGEO-L")?.to_radians();
+    let lat = get_float(header, "OBSGEO-B")?.to_radians();
+    let height = get_float(header, "OBSGEO-H").unwrap_or(0.0);
+
+    const A: f64 = 6_378_137.0;
+    const F: f64 = 1.0 / 298.257_223_563;
+    let e2 = F * (2.0 - F);
+    let n = A / (1.0 - e2 * lat.sin() * lat.sin()).sqrt();
+
+    Some([
+        (n + height) * lat.cos() * lon.cos(),
+        (n + height) * lat.cos() * lon.sin(),
+        (n * (1.0 - e2) + height) * lat.sin(),
+    ])
+}
+
+/// Heliocentric position of the Earth, equatorial J2000, in m
+///
+/// Low precision solar coordinates of the Astronomical Almanac.
+fn earth_position(jd: f64) -> [f64; 3] {
+    let n = jd - J2000;
+    let l = (280.460 + 0.985_647_4 * n).to_radians();
+    let g = (357.528 + 0.985_600_3 * n).to_radians();
+    let lambda = l + (1.915_f64.to_radians()) * g.sin() + (0.020_f64.to_radians()) * (2.0 * g).sin();
+    let r = (1.000_14 - 0.016_71 * g.cos() - 0.000_14 * (2.0 * g).cos()) * AU;
+    let obliquity = (23.439 - 0.000_000_4 * n).to_radians();
+
+    // the Earth is opposite to the Sun seen from the Earth
+    [
+        -r * lambda.cos(),
+        -r * lambda.sin() * obliquity.cos(),
+        -r * lambda.sin() * obliquity.sin(),
+    ]
+}
+
+/// Velocity of the Earth with respect to the barycenter, in m/s
+fn earth_velocity(jd: f64) -> [f64; 3] {
+    // derivative of the position over a day
+    let dt = 0.5;
+    scale(sub(earth_position(jd + dt), earth_position(jd - dt)), 1.0 / (2.0 * dt * SECONDS_PER_DAY))
+}
+
+/// Velocity of an observatory due to the rotation of the Earth, in m/s
+///
+/// Precession, nutation and polar motion are neglected.
+fn rotation_velocity(location: [f64; 3], jd: f64) -> [f64; 3] {
+    let [x, y, _] = location;
+    let gmst = (280.460_618_37 + 360.985_647_366_29 * (jd - J2000)).to_radians();
+    let (sin, cos) = gmst.sin_cos();
+
+    // omega x r, rotated from the terrestrial to the celestial frame
+    let (vx, vy) = (-EARTH_ROTATION * y, EARTH_ROTATION * x);
+    [vx * cos - vy * sin, vx * sin + vy * cos, 0.0]
+}
+
+fn radec_to_vector(ra: f64, dec: f64) -> [f64; 3] {
+    let (ra, dec) = (ra.to_radians(), dec.to_radians());
+    [dec.cos() * ra.cos(), dec.cos() * ra.sin(), dec.sin()]
+}
+
+fn galactic_to_icrs(v: [f64; 3]) -> [f64; 3] {
+    let m = &ICRS_TO_GALACTIC;
+    [0, 1, 2].map(|i| m[0][i] * v[0] + m[1][i] * v[1] + m[2][i] * v[2])
+}
+
+fn add(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(a: [f64; 3], k: f64) -> [f64; 3] {
+    [a[0] * k, a[1] * k, a[2] * k]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::header;
+
+    fn norm(v: [f64; 3]) -> f64 {
+        dot(v, v).sqrt()
+    }
+
+    #[test]
+    fn offset_the_lsrk_velocities() {
+        // pointing at the apex of the solar motion, the LSRK recedes at 20 km/s from the source
+        let (ra, dec) = (LSRK_APEX.0.to_string(), LSRK_APEX.1.to_string());
+        let apex = Observation::from_header(&header(&[("OBSRA", &ra), ("OBSDEC", &dec), ("SPECSYS", "'BARYCENT'")])).unwrap();
+
+        let doppler = apex.doppler(SpectralFrame::Barycentric, SpectralFrame::LsrKinematic).unwrap();
+        let beta = -LSRK_SPEED / C;
+        assert!((doppler - ((1.0 + beta) / (1.0 - beta)).sqrt()).abs() < 1e-12);
+        // a source at rest in the barycentric frame has a LSRK velocity of +20 km/s
+        assert!((C * (1.0 / doppler - 1.0) - 20e3).abs() < 1.0);
+
+        let round_trip = doppler * apex.doppler(SpectralFrame::LsrKinematic, SpectralFrame::Barycentric).unwrap();
+        assert!((round_trip - 1.0).abs() < 1e-15);
+
+        // the velocities of the frames moving with the Earth need the epoch
+        assert_eq!(apex.doppler(SpectralFrame::Barycentric, SpectralFrame::Topocentric), None);
+        assert!((norm(apex.velocity(SpectralFrame::LsrDynamical).unwrap()) - norm(LSRD_MOTION)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn date_the_observation() {
+        assert_eq!(iso_to_jd("2000-01-01T12:00:00"), Some(J2000));
+        assert_eq!(iso_to_jd("1999-01-01"), Some(2_451_179.5));
+        assert_eq!(iso_to_jd("2019-05-03T18:00:00.0"), Some(2_458_607.25));
+        assert_eq!(iso_to_jd("May 3rd"), None);
+
+        let header = header(&[("MJD-OBS", "58606.0"), ("DATE-OBS", "'2000-01-01'")]);
+        assert_eq!(epoch(&header), Some(2_458_606.5));
+
+        // the Earth orbits the Sun at 29.3 to 30.3 km/s
+        for day in 0..12 {
+            let speed = norm(earth_velocity(J2000 + 30.0 * day as f64));
+            assert!((29.2e3..30.4e3).contains(&speed), "{}", speed);
+        }
+    }
+
+    #[test]
+    fn locate_the_observatory() {
+        let equator = location(&header(&[("OBSGEO-L", "0.0"), ("OBSGEO-B", "0.0")])).unwrap();
+        assert_eq!(equator, [6_378_137.0, 0.0, 0.0]);
+
+        let pole = location(&header(&[("OBSGEO-L", "0.0"), ("OBSGEO-B", "90.0"), ("OBSGEO-H", "100.0")])).unwrap();
+        assert!((pole[2] - 6_356_852.314).abs() < 1e-3, "{:?}", pole);
+
+        // the rotation of the Earth moves the equator at 465 m/s
+        let speed = norm(rotation_velocity(equator, J2000));
+        assert!((speed - 465.1).abs() < 0.1, "{}", speed);
+    }
+}
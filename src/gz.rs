@@ -0,0 +1,107 @@
+use crate::error::Error;
+use crate::progress::Progress;
+use flate2::read::MultiGzDecoder;
+use std::convert::TryInto;
+use std::io::Read;
+
+/// Magic bytes starting every gzip stream
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// Number of decompressed bytes between two updates of the progress
+const CHUNK_SIZE: u64 = 1 << 22;
+/// Largest ratio between the decompressed and the compressed sizes trusted
+/// when allocating the output, the buffer growing beyond it if needed
+const MAX_RATIO: usize = 16;
+
+/// Tell whether the bytes are a gzip stream, e.g. a `.fits.gz` file
+pub fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// Decompress a gzip stream
+///
+/// The whole file is decompressed in memory rather than streamed to the
+/// parser: the parser and the HDU chooser work on a slice of the file, that is
+/// kept once loaded to switch to another HDU without decompressing it again.
+/// Both the compressed and the decompressed copies are held while this runs.
+///
+/// The size of the output is guessed from the ISIZE trailer of the stream
+/// (the uncompressed size modulo 2^32), capped at `MAX_RATIO` times the
+/// compressed size since the trailer of a corrupted file can be anything.
+///
+/// `progress` is advanced by the number of compressed bytes consumed.
+pub fn decompress(bytes: &[u8], progress: &Progress) -> Result<Vec<u8>, Error> {
+    let size_hint = bytes
+        .len()
+        .checked_sub(4)
+        .and_then(|start| bytes[start..].try_into().ok())
+        .map(|isize: [u8; 4]| u32::from_le_bytes(isize) as usize)
+        .unwrap_or(0)
+        .min(bytes.len().saturating_mul(MAX_RATIO));
+
+    let mut data = Vec::with_capacity(size_hint);
+    let mut decoder = MultiGzDecoder::new(bytes);
+    let mut consumed = 0;
+    loop {
+        progress.check()?;
+
+        let num_bytes = decoder
+            .by_ref()
+            .take(CHUNK_SIZE)
+            .read_to_end(&mut data)
+            .map_err(|e| Error::Gzip { reason: e.to_string() })?;
+
+        // the decoder reads the input slice by advancing it
+        let remaining = decoder.get_ref().len();
+        progress.advance((bytes.len() - remaining - consumed) as u64);
+        consumed = bytes.len() - remaining;
+
+        if num_bytes == 0 {
+            break;
+        }
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::Stage;
+    use std::io::Write;
+
+    fn compress(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn decompress_concatenated_streams() {
+        let file = (0..3000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut gz = compress(&file[..1000]);
+        gz.extend(compress(&file[1000..]));
+
+        assert!(is_gzip(&gz));
+        assert!(!is_gzip(b"SIMPLE  =                    T"));
+
+        // the whole stream is consumed
+        let progress = Progress::default();
+        progress.start(Stage::Read, gz.len() as u64);
+        assert_eq!(decompress(&gz, &progress).unwrap(), file);
+        assert_eq!(progress.fraction(), 1.0);
+    }
+
+    #[test]
+    fn report_corrupted_streams() {
+        let mut gz = compress(&[1; 100]);
+        gz.truncate(gz.len() / 2);
+
+        assert!(matches!(decompress(&gz, &Progress::default()), Err(Error::Gzip { .. })));
+
+        // a trailer announcing 4 GiB does not allocate them
+        let mut gz = compress(&[1; 100]);
+        let end = gz.len();
+        gz[(end - 4)..].copy_from_slice(&[0xff; 4]);
+        assert!(matches!(decompress(&gz, &Progress::default()), Err(Error::Gzip { .. })));
+    }
+}
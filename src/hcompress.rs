@@ -0,0 +1,651 @@
+use std::convert::TryInto;
+use std::io::Read;
+
+use fitsrs::hdu::header::ValueMap;
+use flate2::read::GzDecoder;
+
+use crate::error::Error;
+use crate::progress::Progress;
+use crate::table::Table;
+use crate::{get_float, get_integer, get_string};
+
+/// Magic bytes starting every HCOMPRESS stream
+const MAGIC: [u8; 2] = [0xdd, 0x99];
+/// Length of the sequence of random values dithering the quantized floating point images
+const N_RANDOM: usize = 10000;
+/// Quantized value of the zeros of the images quantized with SUBTRACTIVE_DITHER_2
+const ZERO_VALUE: i64 = -2_147_483_646;
+
+/// Pixels of a decompressed image, in the order of the data unit of an image HDU
+pub enum Pixels {
+    /// Values of the images with a positive ZBITPIX, BLANK being applied afterwards
+    Integer(Vec<i32>),
+    /// Unquantized values of the floating point images, NaN for the undefined pixels
+    Float(Vec<f64>),
+}
+
+/// Reader of the bits of a stream, from the most significant one of each byte
+struct Bits<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    num_bits: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            pos: 0,
+            buffer: 0,
+            num_bits: 0,
+        }
+    }
+
+    /// Read `n` bytes, aligned on the bytes of the stream
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.pos..(self.pos + n))?;
+        self.pos += n;
+        Some(bytes)
+    }
+
+    /// Read `n` bits, n <= 8
+    fn bits(&mut self, n: u32) -> Option<u32> {
+        if self.num_bits < n {
+            self.buffer = (self.buffer << 8) | *self.bytes.get(self.pos)? as u32;
+            self.pos += 1;
+            self.num_bits += 8;
+        }
+        self.num_bits -= n;
+
+        Some((self.buffer >> self.num_bits) & ((1 << n) - 1))
+    }
+
+    /// Skip the bits left in the current byte
+    fn align(&mut self) {
+        self.num_bits = 0;
+    }
+
+    /// 4-bit code of a quadtree node, with the fixed Huffman code of HCOMPRESS
+    fn huffman(&mut self) -> Option<u8> {
+        let c = self.bits(3)?;
+        if c < 4 {
+            return Some(1 << c);
+        }
+
+        let c = (c << 1) | self.bits(1)?;
+        let code = match c {
+            8 => 3,
+            9 => 5,
+            10 => 10,
+            11 => 12,
+            12 => 15,
+            _ => match (c << 1) | self.bits(1)? {
+                26 => 6,
+                27 => 7,
+                28 => 9,
+                29 => 11,
+                30 => 13,
+                c => {
+                    if (c << 1) | self.bits(1)? == 62 {
+                        0
+                    } else {
+                        14
+                    }
+                }
+            },
+        };
+
+        Some(code)
+    }
+}
+
+/// Number of halvings to bring a size down to 1
+fn log2_ceil(n: usize) -> u32 {
+    n.next_power_of_two().trailing_zeros()
+}
+
+/// Bit of the 4-bit code of a 2x2 block giving the pixel (i, j) of the block
+fn block_bit(code: u8, i: usize, j: usize) -> bool {
+    (code >> (3 - 2 * (i % 2) - (j % 2))) & 1 == 1
+}
+
+/// Expand the 4-bit codes of the (nx + 1) / 2 x (ny + 1) / 2 blocks to nx x ny bits
+fn expand_blocks(codes: &[u8], nx: usize, ny: usize) -> Vec<u8> {
+    let ny2 = ny.div_ceil(2);
+    (0..(nx * ny))
+        .map(|k| {
+            let (i, j) = (k / ny, k % ny);
+            block_bit(codes[(i / 2) * ny2 + j / 2], i, j) as u8
+        })
+        .collect()
+}
+
+/// Decode the bit planes of a quadrant of nqx x nqy coefficients starting at `a[0]`, rows being `n` apart
+fn decode_quadrant(bits: &mut Bits, a: &mut [i64], n: usize, nqx: usize, nqy: usize, num_planes: u8) -> Option<()> {
+    if nqx == 0 || nqy == 0 {
+        // empty quadrants of the small tiles have no bit plane
+        return if num_planes == 0 { Some(()) } else { None };
+    }
+
+    let log2n = log2_ceil(nqx.max(nqy));
+    let num_blocks = nqx.div_ceil(2) * nqy.div_ceil(2);
+    for bit in (0..num_planes).rev() {
+        let codes = match bits.bits(4)? {
+            // the bit plane is written directly
+            0 => (0..num_blocks).map(|_| bits.bits(4).map(|c| c as u8)).collect::<Option<Vec<_>>>()?,
+            // the bit plane is quadtree-coded, the nodes of each level are expanded
+            0xf => {
+                let mut codes = vec![bits.huffman()?];
+                let (mut nx, mut ny) = (1, 1);
+                let (mut nfx, mut nfy) = (nqx, nqy);
+                let mut c = 1 << log2n;
+                for _ in 1..log2n {
+                    // sizes of the levels: n[k - 1] = (n[k] + 1) / 2
+                    c >>= 1;
+                    nx <<= 1;
+                    ny <<= 1;
+                    if nfx <= c {
+                        nx -= 1;
+                    } else {
+                        nfx -= c;
+                    }
+                    if nfy <= c {
+                        ny -= 1;
+                    } else {
+                        nfy -= c;
+                    }
+
+                    codes = expand_blocks(&codes, nx, ny);
+                    for code in codes.iter_mut().rev() {
+                        if *code != 0 {
+                            *code = bits.huffman()?;
+                        }
+                    }
+                }
+                codes
+            }
+            _ => return None,
+        };
+
+        if codes.len() != num_blocks {
+            return None;
+        }
+        for i in 0..nqx {
+            for j in 0..nqy {
+                if block_bit(codes[(i / 2) * nqy.div_ceil(2) + j / 2], i, j) {
+                    a[i * n + j] |= 1 << bit;
+                }
+            }
+        }
+    }
+
+    Some(())
+}
+
+/// Reorder the coefficients of a row or column of the H-transform, the first
+/// half going to the even elements and the second one to the odd ones
+fn unshuffle(a: &mut [i64], start: usize, n: usize, stride: usize) {
+    let values = (0..n).map(|i| a[start + i * stride]).collect::<Vec<_>>();
+    let half = n.div_ceil(2);
+    for (i, v) in values.into_iter().enumerate() {
+        let k = if i < half { 2 * i } else { 2 * (i - half) + 1 };
+        a[start + k * stride] = v;
+    }
+}
+
+/// Inverse H-transform of the nx x ny coefficients, ny being the fastest varying axis
+fn inverse_h_transform(a: &mut [i64], nx: usize, ny: usize) {
+    let log2n = log2_ceil(nx.max(ny));
+    if log2n == 0 {
+        return;
+    }
+
+    let mut shift = 1;
+    let mut bit0: i64 = 1 << (log2n - 1);
+    let mut bit1 = bit0 << 1;
+    let bit2 = bit0 << 2;
+    let mut mask0 = -bit0;
+    let mut mask1 = mask0 << 1;
+    let mask2 = mask0 << 2;
+    let mut prnd0 = bit0 >> 1;
+    let mut prnd1 = bit1 >> 1;
+    let prnd2 = bit2 >> 1;
+    let mut nrnd0 = prnd0 - 1;
+    let mut nrnd1 = prnd1 - 1;
+    let nrnd2 = prnd2 - 1;
+    let round = |h: i64, positive: i64, negative: i64, mask: i64| (h + if h >= 0 { positive } else { negative }) & mask;
+
+    // h0 is a multiple of bit2
+    a[0] = round(a[0], prnd2, nrnd2, mask2);
+
+    let (mut nxtop, mut nytop) = (1, 1);
+    let (mut nxf, mut nyf) = (nx, ny);
+    let mut c = 1 << log2n;
+    for k in (0..log2n).rev() {
+        // sizes of the levels: ntop[k - 1] = (ntop[k] + 1) / 2
+        c >>= 1;
+        nxtop <<= 1;
+        nytop <<= 1;
+        if nxf <= c {
+            nxtop -= 1;
+        } else {
+            nxf -= c;
+        }
+        if nyf <= c {
+            nytop -= 1;
+        } else {
+            nyf -= c;
+        }
+        // the sums are divided by 4 on the last pass
+        if k == 0 {
+            nrnd0 = 0;
+            shift = 2;
+        }
+
+        for i in 0..nxtop {
+            unshuffle(a, ny * i, nytop, 1);
+        }
+        for j in 0..nytop {
+            unshuffle(a, j, nxtop, ny);
+        }
+
+        let (oddx, oddy) = (nxtop % 2, nytop % 2);
+        for i in (0..(nxtop - oddx)).step_by(2) {
+            for j in (0..(nytop - oddy)).step_by(2) {
+                let (s00, s10) = (ny * i + j, ny * (i + 1) + j);
+                let mut h0 = a[s00];
+                // hx and hy are multiples of bit1, hc of bit0
+                let mut hx = round(a[s10], prnd1, nrnd1, mask1);
+                let mut hy = round(a[s00 + 1], prnd1, nrnd1, mask1);
+                let hc = round(a[s10 + 1], prnd0, nrnd0, mask0);
+
+                // the low bits of hc, hx and hy are propagated to h0
+                let lowbit0 = hc & bit0;
+                hx = if hx >= 0 { hx - lowbit0 } else { hx + lowbit0 };
+                hy = if hy >= 0 { hy - lowbit0 } else { hy + lowbit0 };
+                let lowbit1 = (hc ^ hx ^ hy) & bit1;
+                h0 = if h0 >= 0 {
+                    h0 + lowbit0 - lowbit1
+                } else if lowbit0 == 0 {
+                    h0 + lowbit1
+                } else {
+                    h0 + lowbit0 - lowbit1
+                };
+
+                a[s10 + 1] = (h0 + hx + hy + hc) >> shift;
+                a[s10] = (h0 + hx - hy - hc) >> shift;
+                a[s00 + 1] = (h0 - hx + hy - hc) >> shift;
+                a[s00] = (h0 - hx - hy + hc) >> shift;
+            }
+            if oddy == 1 {
+                // last element of an odd row
+                let (s00, s10) = (ny * i + nytop - 1, ny * (i + 1) + nytop - 1);
+                let hx = round(a[s10], prnd1, nrnd1, mask1);
+                let lowbit1 = hx & bit1;
+                let h0 = if a[s00] >= 0 { a[s00] - lowbit1 } else { a[s00] + lowbit1 };
+                a[s10] = (h0 + hx) >> shift;
+                a[s00] = (h0 - hx) >> shift;
+            }
+        }
+        if oddx == 1 {
+            // last row of an odd column
+            let row = ny * (nxtop - 1);
+            for j in (0..(nytop - oddy)).step_by(2) {
+                let s00 = row + j;
+                let hy = round(a[s00 + 1], prnd1, nrnd1, mask1);
+                let lowbit1 = hy & bit1;
+                let h0 = if a[s00] >= 0 { a[s00] - lowbit1 } else { a[s00] + lowbit1 };
+                a[s00 + 1] = (h0 + hy) >> shift;
+                a[s00] = (h0 - hy) >> shift;
+            }
+            if oddy == 1 {
+                let s00 = row + nytop - 1;
+                a[s00] >>= shift;
+            }
+        }
+
+        // the masks and rounding values are halved for the next level
+        bit1 = bit0;
+        bit0 >>= 1;
+        mask1 = mask0;
+        mask0 >>= 1;
+        prnd1 = prnd0;
+        prnd0 >>= 1;
+        nrnd1 = nrnd0;
+        nrnd0 = prnd0 - 1;
+    }
+}
+
+/// Decompress an HCOMPRESS stream of `width` x `height` pixels
+///
+/// The coefficients of the H-transform are decoded from their bit planes,
+/// each quadrant being quadtree-coded, then multiplied by the scale of the
+/// lossy compression and inverted. The smoothing that can be asked with
+/// ZVAL2 is not applied. `None` if the stream is corrupted or its size differs.
+pub fn decompress(bytes: &[u8], width: usize, height: usize) -> Option<Vec<i64>> {
+    let mut bits = Bits::new(bytes);
+    if bits.bytes(2)? != MAGIC {
+        return None;
+    }
+    let mut int = |n| bits.bytes(n).map(|b| b.iter().fold(0_i64, |v, b| (v << 8) | *b as i64));
+    // nx is the slowest varying axis of the stream
+    let (nx, ny, scale) = (int(4)? as usize, int(4)? as usize, int(4)? as i32);
+    let sum = int(8)?;
+    if (nx, ny) != (height, width) || nx == 0 || ny == 0 {
+        return None;
+    }
+    let num_planes = bits.bytes(3)?;
+    // the coefficients are 64-bit integers
+    if num_planes.iter().any(|n| *n > 63) {
+        return None;
+    }
+
+    let mut a = vec![0; nx * ny];
+    let (nx2, ny2) = (nx.div_ceil(2), ny.div_ceil(2));
+    decode_quadrant(&mut bits, &mut a, ny, nx2, ny2, num_planes[0])?;
+    decode_quadrant(&mut bits, &mut a[ny2..], ny, nx2, ny / 2, num_planes[1])?;
+    decode_quadrant(&mut bits, &mut a[(ny * nx2).min(nx * ny)..], ny, nx / 2, ny2, num_planes[1])?;
+    decode_quadrant(&mut bits, &mut a[(ny * nx2 + ny2).min(nx * ny)..], ny, nx / 2, ny / 2, num_planes[2])?;
+    // the bit planes end with a null nybble
+    if bits.bits(4)? != 0 {
+        return None;
+    }
+
+    // signs of the non null coefficients
+    bits.align();
+    for v in a.iter_mut().filter(|v| **v != 0) {
+        if bits.bits(1)? == 1 {
+            *v = -*v;
+        }
+    }
+    a[0] = sum;
+
+    if scale > 1 {
+        a.iter_mut().for_each(|v| *v = v.wrapping_mul(scale as i64));
+    }
+    inverse_h_transform(&mut a, nx, ny);
+
+    Some(a)
+}
+
+/// Random values dithering the quantization, given by the tiled image convention
+fn random_values() -> Vec<f32> {
+    let (a, m) = (16807.0, 2_147_483_647.0);
+    let mut seed = 1.0_f64;
+
+    (0..N_RANDOM)
+        .map(|_| {
+            let temp = a * seed;
+            seed = temp - m * ((temp / m) as i32 as f64);
+            (seed / m) as f32
+        })
+        .collect()
+}
+
+/// Quantization of the floating point images
+enum Quantization {
+    None,
+    Linear,
+    /// SUBTRACTIVE_DITHER_1 or 2 (with the zeros kept exact)
+    Dithered { keep_zeros: bool, first: usize, random: Vec<f32> },
+}
+
+/// Per tile values of a keyword that can also be given by a column
+fn tile_values(table: &Table, header: &ValueMap, name: &str, num_tiles: usize) -> Option<Vec<f64>> {
+    match table.column(name) {
+        Some(values) => Some(values).filter(|values| values.len() >= num_tiles),
+        None => get_float(header, name).map(|value| vec![value; num_tiles]),
+    }
+}
+
+/// Decompress the HCOMPRESS_1 tiles of a compressed image
+///
+/// The tiles are the rows of the table `table` with ZTILEn pixels along each
+/// axis, the last ones being cut at the border of the image. The quantized
+/// floating point tiles are unquantized with the ZSCALE and ZZERO of their
+/// row, the ones that could not be quantized being gzipped in the
+/// GZIP_COMPRESSED_DATA column. `progress` is advanced by the pixels decompressed.
+pub fn decompress_image(table: &Table, header: &ValueMap, hdu: usize, progress: &Progress) -> Result<Pixels, Error> {
+    let zbitpix = get_integer(header, "ZBITPIX").unwrap_or(0);
+    if !matches!(zbitpix, 8 | 16 | 32 | -32 | -64) {
+        return Err(Error::UnsupportedBitpix { bitpix: zbitpix });
+    }
+    let corrupted = |tile| Error::CorruptedTile { hdu, tile };
+
+    let num_axis = get_integer(header, "ZNAXIS").unwrap_or(0) as usize;
+    let naxis = (1..=num_axis)
+        .map(|i| get_integer(header, &format!("ZNAXIS{}", i)).map(|n| n as usize))
+        .collect::<Option<Vec<_>>>()
+        .filter(|naxis| !naxis.is_empty())
+        .ok_or_else(|| corrupted(0))?;
+    let tile = (1..=num_axis)
+        .map(|i| {
+            let default = if i == 1 { naxis[0] } else { 1 };
+            get_integer(header, &format!("ZTILE{}", i)).map(|n| n as usize).unwrap_or(default).clamp(1, naxis[i - 1].max(1))
+        })
+        .collect::<Vec<_>>();
+    let grid = naxis.iter().zip(&tile).map(|(n, t)| n.div_ceil(*t)).collect::<Vec<_>>();
+    let num_tiles = grid.iter().product::<usize>();
+    let num_pixels = naxis.iter().product::<usize>();
+
+    let arrays = table.arrays("COMPRESSED_DATA").filter(|a| a.len() >= num_tiles).ok_or_else(|| corrupted(0))?;
+    let gzipped = table.arrays("GZIP_COMPRESSED_DATA");
+    let scales = tile_values(table, header, "ZSCALE", num_tiles);
+    let zeros = tile_values(table, header, "ZZERO", num_tiles);
+    let blanks = tile_values(table, header, "ZBLANK", num_tiles);
+
+    let quantization = match (zbitpix < 0, &scales, &zeros) {
+        (false, ..) => Quantization::None,
+        (true, Some(_), Some(_)) => match get_string(header, "ZQUANTIZ").as_deref().map(str::trim) {
+            Some(method @ ("SUBTRACTIVE_DITHER_1" | "SUBTRACTIVE_DITHER_2")) => Quantization::Dithered {
+                keep_zeros: method == "SUBTRACTIVE_DITHER_2",
+                first: (get_integer(header, "ZDITHER0").unwrap_or(1).max(1) - 1) as usize,
+                random: random_values(),
+            },
+            _ => Quantization::Linear,
+        },
+        // floating point tiles can only be gzipped
+        (true, ..) => Quantization::Linear,
+    };
+
+    let mut integers = vec![];
+    let mut floats = vec![];
+    if zbitpix > 0 {
+        integers = vec![0; num_pixels];
+    } else {
+        floats = vec![f64::NAN; num_pixels];
+    }
+
+    for t in 0..num_tiles {
+        progress.check()?;
+
+        // origin and size of the tile, cut at the border of the image
+        let mut index = t;
+        let mut origin = vec![0; num_axis];
+        let mut size = vec![0; num_axis];
+        for axis in 0..num_axis {
+            origin[axis] = (index % grid[axis]) * tile[axis];
+            size[axis] = tile[axis].min(naxis[axis] - origin[axis]);
+            index /= grid[axis];
+        }
+        let width = size[0];
+        let height = size[1..].iter().product::<usize>();
+
+        // the pixels of the tile are stored row after row in the image
+        let rows = (0..height).map(|row| {
+            let mut row = row;
+            let mut start = 0;
+            let mut stride = 1;
+            for axis in 0..num_axis {
+                let coordinate = if axis == 0 {
+                    0
+                } else {
+                    let c = row % size[axis];
+                    row /= size[axis];
+                    c
+                };
+                start += (origin[axis] + coordinate) * stride;
+                stride *= naxis[axis];
+            }
+            start..(start + width)
+        });
+
+        let gzipped_tile = gzipped.as_ref().and_then(|arrays| arrays.get(t)).filter(|a| !a.is_empty());
+        if arrays[t].is_empty() && zbitpix < 0 {
+            // a floating point tile that could not be quantized, its pixels are gzipped as they are
+            let mut bytes = vec![];
+            GzDecoder::new(*gzipped_tile.ok_or_else(|| corrupted(t))?)
+                .read_to_end(&mut bytes)
+                .map_err(|_| corrupted(t))?;
+            let num_bytes = if zbitpix == -32 { 4 } else { 8 };
+            if bytes.len() != width * height * num_bytes {
+                return Err(corrupted(t));
+            }
+
+            let mut values = bytes.chunks_exact(num_bytes).map(|b| {
+                if num_bytes == 4 {
+                    f32::from_be_bytes(b.try_into().unwrap_or_default()) as f64
+                } else {
+                    f64::from_be_bytes(b.try_into().unwrap_or_default())
+                }
+            });
+            for row in rows {
+                floats[row].iter_mut().zip(values.by_ref()).for_each(|(f, v)| *f = v);
+            }
+        } else {
+            let values = decompress(arrays[t], width, height).ok_or_else(|| corrupted(t))?;
+            let mut values = values.into_iter();
+
+            if zbitpix > 0 {
+                for row in rows {
+                    integers[row].iter_mut().zip(values.by_ref()).for_each(|(i, v)| *i = v as i32);
+                }
+            } else {
+                let scale = scales.as_ref().map_or(1.0, |s| s[t]);
+                let zero = zeros.as_ref().map_or(0.0, |z| z[t]);
+                let blank = blanks.as_ref().map(|b| b[t] as i64);
+
+                // the sequence of random values of the tile starts at a position given by its index
+                let (mut seed, mut next) = (0, 0);
+                if let Quantization::Dithered { first, random, .. } = &quantization {
+                    seed = (t + first) % N_RANDOM;
+                    next = (random[seed] * 500.0) as usize;
+                }
+
+                for row in rows {
+                    for (f, v) in floats[row].iter_mut().zip(values.by_ref()) {
+                        *f = match &quantization {
+                            _ if Some(v) == blank => f64::NAN,
+                            Quantization::Dithered { keep_zeros: true, .. } if v == ZERO_VALUE => 0.0,
+                            Quantization::Dithered { random, .. } => (v as f64 - random[next] as f64 + 0.5) * scale + zero,
+                            _ => v as f64 * scale + zero,
+                        };
+                        // the values are unquantized in the precision of the image
+                        if zbitpix == -32 {
+                            *f = *f as f32 as f64;
+                        }
+
+                        if let Quantization::Dithered { random, .. } = &quantization {
+                            next += 1;
+                            if next == N_RANDOM {
+                                seed = (seed + 1) % N_RANDOM;
+                                next = (random[seed] * 500.0) as usize;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        progress.advance((width * height) as u64);
+    }
+
+    Ok(if zbitpix > 0 {
+        Pixels::Integer(integers)
+    } else {
+        Pixels::Float(floats)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fitsrs::{Fits, HDU};
+    use std::io::Cursor;
+
+    /// 15x10x2 cube of (37x + 101y + 7919z) % 2000 - 1000 compressed by `fpack -h -t 15,6`
+    const INT16: &[u8] = include_bytes!("../testdata/hcompress_int16.fits.fz");
+    /// 15x10x2 cube of 100 sin(0.3x) cos(0.2y) + z + 0.37 ((7x + 13y) % 5) compressed by `fpack -h -t 15,6`
+    const FLOAT32: &[u8] = include_bytes!("../testdata/hcompress_float32.fits.fz");
+
+    /// Header and data unit of the compressed HDU of a file
+    fn compressed_hdu(file: &[u8]) -> (ValueMap, &[u8]) {
+        let mut fits = Fits::from_reader(Cursor::new(file));
+        while let Some(Ok(hdu)) = fits.next() {
+            if let HDU::XBinaryTable(hdu) = hdu {
+                let offset = hdu.get_data_unit_byte_offset() as usize;
+                return (ValueMap::clone(hdu.get_header()), &file[offset..(offset + hdu.get_data_unit_byte_size() as usize)]);
+            }
+        }
+
+        panic!("the file has no compressed HDU")
+    }
+
+    fn decompress_file(file: &[u8]) -> Result<Pixels, Error> {
+        let (header, data) = compressed_hdu(file);
+        decompress_image(&Table::new(&header, data), &header, 1, &Progress::default())
+    }
+
+    fn pixels(f: impl Fn(usize, usize, usize) -> f64) -> Vec<f64> {
+        (0..2).flat_map(|z| (0..10).flat_map(move |y| (0..15).map(move |x| (x, y, z)))).map(|(x, y, z)| f(x, y, z)).collect()
+    }
+
+    #[test]
+    fn decompress_the_integer_tiles() {
+        let expected = pixels(|x, y, z| ((x * 37 + y * 101 + z * 7919) % 2000) as f64 - 1000.0);
+        match decompress_file(INT16) {
+            // HCOMPRESS is lossless without a scale
+            Ok(Pixels::Integer(values)) => assert_eq!(values.iter().map(|v| *v as f64).collect::<Vec<_>>(), expected),
+            _ => panic!("the 16-bit tiles are decompressed to integers"),
+        }
+    }
+
+    #[test]
+    fn unquantize_the_floating_point_tiles() {
+        let expected = pixels(|x, y, z| {
+            (100.0 * (x as f64 * 0.3).sin() * (y as f64 * 0.2).cos() + z as f64 + ((x * 7 + y * 13) % 5) as f64 * 0.37) as f32 as f64
+        });
+        let values = match decompress_file(FLOAT32) {
+            Ok(Pixels::Float(values)) => values,
+            _ => panic!("the quantized tiles are decompressed to floats"),
+        };
+
+        // the values written by funpack, dithered with the random values of the tiles
+        assert_eq!(values[..4], [0.0897420346736908, 30.259523391723633, 58.050392150878906, 78.75856018066406]);
+        assert_eq!(values[150..152], [1.1559042930603027, 31.32291030883789]);
+        assert_eq!(values[299], 20.75227928161621);
+        // the quantization error is at most half the largest ZSCALE of the tiles, 0.356
+        assert_eq!(values.len(), expected.len());
+        assert!(values.iter().zip(&expected).all(|(v, e)| (v - e).abs() <= 0.178));
+    }
+
+    #[test]
+    fn reject_corrupted_tiles() {
+        let (width, height) = (15, 6);
+        assert_eq!(decompress(b"not a stream", width, height), None);
+
+        let (header, data) = compressed_hdu(INT16);
+        let tile = Table::new(&header, data).arrays("COMPRESSED_DATA").unwrap()[0];
+        assert!(decompress(tile, width, height).is_some());
+        // the size of the stream is checked against the one of the tile
+        assert_eq!(decompress(tile, width, height - 1), None);
+        assert_eq!(decompress(&tile[..(tile.len() / 2)], width, height), None);
+
+        // the magic bytes of the first tile, at the start of the heap following the 4 rows, are overwritten
+        let mut data = data.to_vec();
+        data[4 * 8] = 0;
+        let image = decompress_image(&Table::new(&header, &data), &header, 1, &Progress::default());
+        assert!(matches!(image, Err(Error::CorruptedTile { hdu: 1, tile: 0 })));
+    }
+}
@@ -7,7 +7,6 @@ use winit::event_loop::ControlFlow;
 extern crate console_error_panic_hook;
 
 use std::iter;
-use std::convert::TryInto;
 use egui_double_slider::DoubleSlider;
 use fitsrs::ImgXY;
 
@@ -20,23 +19,48 @@ use winit::{
     keyboard::{KeyCode, PhysicalKey},
     window::{Fullscreen, Window, WindowId},
 };
+mod axes;
+mod beam;
+mod bricks;
+mod brightness;
+mod colormap;
+mod cuts;
+mod decode;
+mod error;
+mod frames;
 mod gui;
+mod gz;
+mod hcompress;
+mod lod;
 mod math;
+mod progress;
 mod texture;
 mod time;
 mod vertex;
 mod volumetric;
 mod selector;
+mod spectral;
+mod stats;
+mod stretch;
+mod table;
+#[cfg(test)]
+mod testing;
+mod transfer;
+mod view;
 use fitsrs::card::Value;
+use fitsrs::hdu::header::ValueMap;
+use fitsrs::hdu::header::extension::bintable::BinTable;
+use fitsrs::hdu::header::extension::image::Image;
 use fitsrs::HDU;
 
 use crate::math::Vec4;
+use error::Error;
 use texture::Texture;
 use time::Clock;
 use vertex::{VertexNDC, Vertex};
 use crate::selector::SelectorRenderer;
 
-use volumetric::VolumetricRenderer;
+use volumetric::{RenderMode, VolumetricRenderer};
 
 use fitsrs::Fits;
 #[cfg(not(target_arch = "wasm32"))]
@@ -70,6 +94,12 @@ struct State {
     send_data: async_channel::Sender<Vec<u8>>,
     #[cfg(target_arch = "wasm32")]
     recv_data: async_channel::Receiver<Vec<u8>>,
+    // progress of the file being read by the browser
+    #[cfg(target_arch = "wasm32")]
+    reading: Arc<Progress>,
+    // loading job run once a frame showing its progress has been rendered
+    #[cfg(all(target_arch = "wasm32", not(feature = "wasm-threads")))]
+    deferred_load: Option<(Box<dyn FnOnce()>, bool)>,
     
     is_surface_configured: bool,
 
@@ -82,6 +112,14 @@ struct State {
 
     // Cube WCS
     wcs: Option<WCS>,
+    // spectral axis of the cube, along the z axis of the volume
+    spectral: Option<spectral::SpectralAxis>,
+    // type the spectral coordinates are displayed in
+    spectral_type: spectral::SpectralType,
+    // frame the spectral coordinates are displayed in, None if the one of the cube is unknown
+    spectral_frame: Option<frames::SpectralFrame>,
+    // restoring beams of the channels of the cube
+    beams: Option<beam::Beams>,
     // NAXIS of the current loaded cube
     naxis: (u32, u32, u32),
 
@@ -94,6 +132,50 @@ struct State {
     m1: f32,
     // current max cut
     m2: f32,
+    // subset of the valid values of the cube the cuts are computed on
+    cut_samples: Vec<f64>,
+    // statistics and histogram of the valid values of the cube
+    stats: stats::Statistics,
+    // physical unit of the cube values (BUNIT)
+    bunit: Option<String>,
+    // conversion of the cube values between brightness units, None if BUNIT is not one of them
+    brightness: Option<brightness::Brightness>,
+    // brightness unit the values are displayed in, BUNIT if None
+    brightness_unit: Option<brightness::BrightnessUnit>,
+    // BSCALE/BZERO scaling that has been applied to the stored values
+    scale: decode::Scale,
+    // mapping from the physical values to the ones stored on the GPU
+    encoding: decode::Encoding,
+    // options applied when loading the next cube
+    load_options: LoadOptions,
+    // bytes of the FITS file currently displayed
+    source: Option<Arc<Source>>,
+    // cube being loaded in the background
+    loading: Option<Loading>,
+    // HDUs of the FITS file currently displayed
+    hdus: Vec<HduInfo>,
+    // index of the HDU currently displayed
+    hdu_idx: usize,
+    // axes of the HDU currently rendered
+    axis_mapping: axes::AxisMapping,
+    // brick cache and voxels of cubes too big to be uploaded at once
+    bricked: Option<(bricks::BrickCache, bricks::Voxels)>,
+    // channels of the cube still to be uploaded
+    upload: Option<Upload>,
+    // number of channels uploaded so far, the spectral range rendered is limited to them
+    uploaded_channels: u32,
+    // region of the cube explored, in voxels
+    slice_range: [Range<f32>; 3],
+    // number of levels of the pyramid of the cube, 1 if it has no coarse level
+    lod_levels: u32,
+    // level rendered while the camera moves
+    lod_interactive: u32,
+    // level currently rendered
+    lod_level: u32,
+    // true while the camera is moved by the user
+    moving: bool,
+    // errors shown in the notification panel until dismissed
+    errors: Vec<Error>,
 
     freq_min: f32,
     freq_max: f32,
@@ -105,15 +187,34 @@ struct State {
     isosurface: f32,
     // a diffuse color to show the isosurface with
     diffuse_color: [f32; 4],
+    // color painting the rays only crossing NaN/BLANK voxels
+    blank_color: [f32; 4],
+    // transfer function of the direct volume rendering
+    transfer: transfer::TransferFunction,
+    // colormaps of the maximum intensity projection, the builtin ones then the loaded palettes
+    colormaps: Vec<colormap::Colormap>,
+    // index of the colormap shown
+    colormap: usize,
+    reverse_colormap: bool,
+    // function applied to the values normalized between the cuts
+    stretch: stretch::Stretch,
+    // cuts the histogram equalization has been computed for
+    equalized: Option<(f32, f32)>,
+    // palette file typed by the user
+    #[cfg(not(target_arch = "wasm32"))]
+    colormap_path: String,
+    // view pasted by the user to be applied
+    view_text: String,
     // perspective rendering mode
     perspective: bool,
     // slice index
     slice_idx: u32,
 
     /// ui options
-    show_isosurface: bool,
+    render_mode: RenderMode,
     show_options: bool,
     show_unique_slice: bool,
+    show_blank: bool,
 
 
     delta: f64,
@@ -257,6 +358,36 @@ impl State {
                 size: 16,
                 usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
                 mapped_at_creation: false,
+            })),
+            ("blank_color", device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Blank color"),
+                size: 16,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })),
+            ("bricking", device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Bricking"),
+                size: 16,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })),
+            ("lod", device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Level of detail"),
+                size: 16,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })),
+            ("render_mode", device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Render mode"),
+                size: 16,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })),
+            ("stretch", device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Stretch"),
+                size: 16,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
             }))
         ].into_iter().collect();
 
@@ -296,6 +427,12 @@ impl State {
             0,
             bytemuck::bytes_of(&[std::f32::consts::PI, 0.0, 0.0, 0.0]),
         );
+        // a null alpha disables the painting of the blank rays
+        queue.write_buffer(
+            &buffers["blank_color"],
+            0,
+            bytemuck::bytes_of(&[0.0_f32, 0.0, 0.0, 0.0]),
+        );
 
         let clock = Clock::now();
 
@@ -305,6 +442,8 @@ impl State {
         // Transfer local data for wasm
         #[cfg(target_arch = "wasm32")]
         let (send_data, recv_data) = async_channel::unbounded::<Vec<u8>>();
+        #[cfg(target_arch = "wasm32")]
+        let reading = Arc::new(Progress::default());
 
         #[cfg(target_arch = "wasm32")]
         {
@@ -318,14 +457,23 @@ impl State {
 
             let input_cloned = input.clone();
             let sdd = send_data.clone();
+            let reading = reading.clone();
             let closure = Closure::wrap(Box::new(move |_: web_sys::Event| {
                 if let Some(file_list) = input_cloned.files() {
                     if let Some(file) = file_list.get(0) {
                         let reader = web_sys::FileReader::new().unwrap();
+                        reading.start(Stage::Read, file.size() as u64);
+
+                        let progress = reading.clone();
+                        let onprogress_cb = Closure::wrap(Box::new(move |e: web_sys::ProgressEvent| {
+                            progress.set_done(e.loaded() as u64);
+                        }) as Box<dyn FnMut(_)>);
 
                         let reader_cloned = reader.clone();
                         let sd = sdd.clone();
+                        let progress = reading.clone();
                         let onloadend_cb = Closure::wrap(Box::new(move |_: web_sys::Event| {
+                            progress.start(Stage::Idle, 0);
                             let result = reader_cloned.result().unwrap();
                             let array = js_sys::Uint8Array::new(&result);
                             let len = array.length() as usize;
@@ -340,8 +488,10 @@ impl State {
                             web_sys::console::log_1(&format!("Read {} bytes from file", len).into());
                         }) as Box<dyn FnMut(_)>);
 
+                        reader.set_onprogress(Some(onprogress_cb.as_ref().unchecked_ref()));
                         reader.set_onloadend(Some(onloadend_cb.as_ref().unchecked_ref()));
                         reader.read_as_array_buffer(&file).unwrap();
+                        onprogress_cb.forget(); // prevent drop
                         onloadend_cb.forget(); // prevent drop
                     }
                 }
@@ -352,8 +502,17 @@ impl State {
         }
 
         let volumetric_renderer = VolumetricRenderer::new(&device, &queue, &config, &buffers);
+        let transfer = transfer::TransferFunction::default();
+        volumetric_renderer.write_transfer(&queue, &transfer.lut());
+        let colormaps = colormap::Colormap::builtins();
+        volumetric_renderer.write_colormap(&queue, &colormaps[0].lut(false));
         let selector_renderer = SelectorRenderer::new(&device, &config, &buffers);
 
+        let load_options = LoadOptions {
+            max_texture_dimension: device.limits().max_texture_dimension_3d,
+            ..Default::default()
+        };
+
         Self {
             surface,
             device,
@@ -364,6 +523,10 @@ impl State {
             send_data,
             #[cfg(target_arch = "wasm32")]
             recv_data,
+            #[cfg(target_arch = "wasm32")]
+            reading,
+            #[cfg(all(target_arch = "wasm32", not(feature = "wasm-threads")))]
+            deferred_load: None,
 
             is_surface_configured: false,
 
@@ -376,6 +539,28 @@ impl State {
             cut90: 1.0,
             m1: 0.0,
             m2: 1.0,
+            cut_samples: vec![],
+            stats: stats::Statistics::default(),
+            bunit: None,
+            brightness: None,
+            brightness_unit: None,
+            scale: decode::Scale::default(),
+            encoding: decode::Encoding::default(),
+            load_options,
+            source: None,
+            loading: None,
+            hdus: vec![],
+            hdu_idx: 0,
+            axis_mapping: axes::AxisMapping::new(&[1, 1, 1], &[]),
+            bricked: None,
+            upload: None,
+            uploaded_channels: 1,
+            slice_range: [0.0..1.0, 0.0..1.0, 0.0..1.0],
+            lod_levels: 1,
+            lod_interactive: 1,
+            lod_level: 0,
+            moving: false,
+            errors: vec![],
 
             freq_min: 0.0,
             freq_max: 100.0,
@@ -387,10 +572,25 @@ impl State {
             isosurface: 0.0,
             slice_idx: 0,
             diffuse_color: [0.0, 1.0, 0.0, 1.0],
-            show_isosurface: false,
+            blank_color: [0.5, 0.5, 0.5, 1.0],
+            render_mode: RenderMode::Mip,
+            transfer,
+            colormaps,
+            colormap: 0,
+            reverse_colormap: false,
+            stretch: stretch::Stretch::default(),
+            equalized: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            colormap_path: String::new(),
+            view_text: String::new(),
             show_options: false,
             show_unique_slice: false,
+            show_blank: false,
             wcs: None,
+            spectral: None,
+            spectral_type: spectral::SpectralType::Freq,
+            spectral_frame: None,
+            beams: None,
 
             delta: 0.0,
             theta: std::f64::consts::PI,
@@ -445,6 +645,49 @@ impl State {
             0,
             bytemuck::bytes_of(&[elapsed, 0.0, 0.0, 0.0]),
         );
+
+        #[cfg(all(target_arch = "wasm32", not(feature = "wasm-threads")))]
+        self.run_deferred_load();
+        self.poll_loading();
+        self.upload_channels();
+
+        // a coarse level is rendered while the camera moves
+        let lod_level = if self.moving {
+            self.lod_interactive.min(self.lod_levels - 1)
+        } else {
+            0
+        };
+
+        // stream the bricks of the region explored, at a coarser level if they do not fit in the atlas
+        let slice_range = &self.slice_range;
+        let lod_level = if let Some((cache, voxels)) = self.bricked.as_mut() {
+            let region = [0, 1, 2].map(|axis| {
+                let r = &slice_range[axis];
+                (r.start.max(0.0).floor() as u32)..(r.end.max(0.0).ceil() as u32)
+            });
+            let file = self.source.as_ref().map(|s| (**s).as_ref()).unwrap_or(&[]);
+
+            cache.request(region, lod_level);
+            cache.update(&self.queue, voxels, file);
+            cache.level()
+        } else {
+            lod_level
+        };
+        if lod_level != self.lod_level {
+            self.lod_level = lod_level;
+            self.queue.write_buffer(
+                &self.buffers["lod"],
+                0,
+                bytemuck::bytes_of(&[lod_level as f32, 0.0, 0.0, 0.0]),
+            );
+        }
+
+        // the histogram equalization follows the cuts
+        if self.stretch == stretch::Stretch::HistEq && self.equalized != Some((self.m1, self.m2)) {
+            let lut = stretch::equalization(&self.stats.histogram, (self.m1 as f64)..(self.m2 as f64));
+            self.volumetric_renderer.write_stretch(&self.queue, &lut);
+            self.equalized = Some((self.m1, self.m2));
+        }
     }
 
     fn render(&mut self, window: &Window) -> Result<(), wgpu::SurfaceError> {
@@ -473,7 +716,7 @@ impl State {
                     label: Some("Render Encoder"),
                 });
 
-            self.volumetric_renderer.render_frame(&mut encoder, &view, self.show_isosurface);
+            self.volumetric_renderer.render_frame(&mut encoder, &view, self.render_mode);
             self.selector_renderer.render_frame(&mut encoder, &view);
 
             {
@@ -482,9 +725,23 @@ impl State {
                 let mut isosurface = self.isosurface;
                 let mut perspective = self.perspective;
                 let mut diffuse_color = self.diffuse_color;
-                let mut show_isosurface = self.show_isosurface;
+                let mut render_mode = self.render_mode;
+                let mut transfer = self.transfer.clone();
+                let mut colormap = self.colormap;
+                let mut reverse_colormap = self.reverse_colormap;
+                let mut stretch = self.stretch;
+                let colormaps = &self.colormaps;
+                #[cfg(not(target_arch = "wasm32"))]
+                let mut colormap_path = self.colormap_path.clone();
+                #[cfg(not(target_arch = "wasm32"))]
+                let mut load_colormap = false;
+                let current_view = self.current_view();
+                let mut view_text = self.view_text.clone();
+                let mut apply_view = false;
                 let mut show_options = self.show_options;
                 let mut show_unique_slice = self.show_unique_slice;
+                let mut show_blank = self.show_blank;
+                let mut blank_color = self.blank_color;
                 let mut m1 = self.m1;
                 let mut m2 = self.m2;
 
@@ -496,26 +753,216 @@ impl State {
                 let naxis = &self.naxis;
 
                 let mut slice_idx = self.slice_idx;
+                let lod_levels = self.lod_levels;
+                let lod_level = self.lod_level;
+                let mut lod_interactive = self.lod_interactive;
 
+                let loading_progress = self.loading.as_ref().map(|loading| loading.progress.clone());
+                // the file read by the browser is not cancellable
+                #[cfg(target_arch = "wasm32")]
+                let reading_progress = Some(self.reading.clone()).filter(|reading| reading.stage() != Stage::Idle);
+                #[cfg(not(target_arch = "wasm32"))]
+                let reading_progress: Option<Arc<Progress>> = None;
+
+                // channels of the cube displayed still being uploaded
+                let channels_uploaded = self.upload.as_ref().map(|_| (self.uploaded_channels, self.naxis.2));
+
+                let mut cancel_loading = false;
                 egui::TopBottomPanel::top("top_bar").show(self.egui_renderer.context(), |ui| {
                     ui.horizontal(|ui| {
                         ui.heading("WebGPU 3D FITS viewer");
                         ui.checkbox(&mut show_options, "Show options");
+                        if let Some(progress) = loading_progress.as_ref().or(reading_progress.as_ref()) {
+                            let fraction = progress.fraction();
+                            let text = format!("{} {:.0}%", progress.stage().name(), fraction * 100.0);
+                            ui.add(egui::ProgressBar::new(fraction).desired_width(250.0).text(text));
+                        }
+                        if loading_progress.is_some() && ui.button("Cancel").clicked() {
+                            cancel_loading = true;
+                        }
+                        if let Some((uploaded, num_channels)) = channels_uploaded {
+                            let fraction = uploaded as f32 / num_channels as f32;
+                            let text = format!("{} {}/{} channels", Stage::Upload.name(), uploaded, num_channels);
+                            ui.add(egui::ProgressBar::new(fraction).desired_width(250.0).text(text));
+                        }
                     });
                 });
 
+                // notification panel listing the errors not dismissed yet
+                let mut dismissed = None;
+                if !self.errors.is_empty() {
+                    let errors = &self.errors;
+                    egui::TopBottomPanel::bottom("errors").show(self.egui_renderer.context(), |ui| {
+                        for (idx, error) in errors.iter().enumerate() {
+                            ui.horizontal(|ui| {
+                                if ui.small_button("x").on_hover_text("Dismiss").clicked() {
+                                    dismissed = Some(idx);
+                                }
+                                ui.colored_label(ui.visuals().error_fg_color, error.to_string());
+                            });
+                        }
+                    });
+                }
+                if let Some(idx) = dismissed {
+                    self.errors.remove(idx);
+                }
+
                 let data_length = (self.cut90 - self.cut10).abs();
                 let datamin = self.cut10 - data_length;
                 let datamax = self.cut90 + 5.0*data_length;
                 let wcs = &self.wcs;
+                let spectral = &self.spectral;
+                let mut spectral_type = self.spectral_type;
+                let mut spectral_frame = self.spectral_frame;
+                let bunit = &self.bunit;
+                // beam of the channel displayed, whether it varies along the channels and its number of pixels
+                let channel = self.displayed_channel();
+                let beam_info = self.beams.as_ref().and_then(|beams| {
+                    let beam = *beams.beam(channel)?;
+                    Some((beam, beams.is_per_channel(), self.pixels_per_beam(channel)))
+                });
+                // values are displayed in the brightness unit chosen, converted at the channel displayed
+                let (brightness_factor, display_unit) = self.brightness_conversion();
+                let brightness_units = self.brightness.as_ref().map(|brightness| brightness.units(channel));
+                let mut brightness_unit = self.brightness_unit;
+                let scale = &self.scale;
+                let stats = &self.stats;
+                let mut load_options = self.load_options.clone();
+                let hdus = &self.hdus;
+                let mut hdu_idx = self.hdu_idx;
+                let mut axis_mapping = self.axis_mapping.clone();
+                let mut slice_range = self.slice_range.clone();
+                // Some(true) while the bricks of the region explored are uploaded
+                let bricks_loading = self.bricked.as_ref().map(|(cache, _)| cache.is_loading());
+                let mut remap = false;
 
                 if show_options {
                     egui::SidePanel::left("fits3 options")
                     .resizable(true)
                     .show(self.egui_renderer.context(), |ui| {
+                        // cube scope
+                        ui.label(format!("Cube {}x{}x{}", naxis.0, naxis.1, naxis.2));
+                        if wcs.is_none() {
+                            ui.label("No WCS, axes are in pixel coordinates");
+                        }
+                        if let Some(unit) = bunit {
+                            ui.label(format!("Unit: {}", unit));
+                        }
+                        if let (Some(units), Some(unit)) = (&brightness_units, bunit) {
+                            egui::ComboBox::from_label("Brightness unit")
+                                .selected_text(brightness_unit.map(|u| u.name()).unwrap_or(unit))
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(&mut brightness_unit, None, format!("{} (BUNIT)", unit));
+                                    for u in units {
+                                        ui.selectable_value(&mut brightness_unit, Some(*u), u.name());
+                                    }
+                                })
+                                .response
+                                .on_hover_text("Values are converted with the beam and the frequency of the channel displayed, the brightness temperature in the Rayleigh-Jeans approximation. The statistics, cuts and isosurface of the whole cube use the factor of that channel.");
+                        }
+                        if let Some((beam, per_channel, pixels)) = beam_info {
+                            let text = format!(
+                                "Beam: {:.3}\" x {:.3}\", PA {:.1}°",
+                                beam.major * 3600.0,
+                                beam.minor * 3600.0,
+                                beam.position_angle
+                            );
+                            if per_channel {
+                                ui.label(format!("{} (channel {})", text, channel))
+                                    .on_hover_text("The beam varies along the channels, the one of the channel displayed is shown");
+                            } else {
+                                ui.label(text);
+                            }
+                            if let Some(pixels) = pixels {
+                                ui.label(format!("{:.2} pixels per beam", pixels));
+                            }
+                        }
+                        if !scale.is_identity() {
+                            ui.label(format!("BSCALE: {}, BZERO: {}", scale.bscale, scale.bzero));
+                        }
+                        if stats.count > 0 {
+                            let f = brightness_factor;
+                            ui.label(with_unit(&format!("Min: {:.4e}, Max: {:.4e}", stats.min * f, stats.max * f), &display_unit));
+                            ui.label(with_unit(&format!("Mean: {:.4e}, RMS: {:.4e}, Std: {:.4e}", stats.mean * f, stats.rms() * f, stats.std() * f), &display_unit));
+                        }
+                        if let Some(loading) = bricks_loading {
+                            ui.horizontal(|ui| {
+                                ui.label("Bricked cube, only the selected region is streamed");
+                                if loading {
+                                    ui.spinner();
+                                }
+                            });
+                        }
+                        if let Some(selected) = hdus.get(hdu_idx) {
+                            egui::ComboBox::from_label("HDU")
+                                .selected_text(selected.label())
+                                .show_ui(ui, |ui| {
+                                    for info in hdus {
+                                        // only the HDUs storing a cube can be displayed
+                                        ui.add_enabled_ui(info.is_cube(), |ui| {
+                                            ui.selectable_value(&mut hdu_idx, info.index, info.label());
+                                        });
+                                    }
+                                });
+
+                            // FITS axes rendered as the volume
+                            for (slot, name) in ["X axis", "Y axis", "Z axis"].iter().enumerate() {
+                                egui::ComboBox::from_label(*name)
+                                    .selected_text(selected.axis_label(axis_mapping.volume[slot]))
+                                    .show_ui(ui, |ui| {
+                                        for axis in 0..selected.naxis.len() {
+                                            let checked = axis_mapping.volume[slot] == axis;
+                                            if ui.selectable_label(checked, selected.axis_label(axis)).clicked() && !checked {
+                                                axis_mapping.set_volume_axis(slot, axis);
+                                                remap = true;
+                                            }
+                                        }
+                                    });
+                            }
+                            // index of the hyperplane along the other axes
+                            for axis in 0..selected.naxis.len() {
+                                if !axis_mapping.volume.contains(&axis) {
+                                    let response = ui.add(
+                                        egui::Slider::new(&mut axis_mapping.indices[axis], 0..=(selected.naxis[axis] - 1))
+                                            .text(selected.axis_label(axis))
+                                    );
+
+                                    // the hyperplane is extracted once the slider is released
+                                    if response.drag_stopped() || (response.changed() && !response.dragged()) {
+                                        remap = true;
+                                    }
+                                }
+                            }
+                        }
+                        ui.checkbox(&mut load_options.rescale_f64, "Rescale double precision cubes")
+                            .on_hover_text("Center and rescale BITPIX = -64 cubes before converting them to f32. Applied to the next loaded cube");
+
+                        ui.separator();
+
                         // rendering scope
-                        ui.label("Mode");
-                        ui.checkbox(&mut show_isosurface, "Show isosurface");
+                        egui::ComboBox::from_label("Mode")
+                            .selected_text(render_mode.name())
+                            .show_ui(ui, |ui| {
+                                for mode in RenderMode::all() {
+                                    ui.selectable_value(&mut render_mode, mode, mode.name());
+                                }
+                            });
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut show_blank, "Paint blank rays");
+                            ui.add_enabled_ui(show_blank, |ui| {
+                                ui.color_edit_button_rgba_unmultiplied(&mut blank_color);
+                            });
+                        });
+                        if lod_levels > 1 {
+                            ui.add(egui::Slider::new(&mut lod_interactive, 0..=(lod_levels - 1)).text("level while moving"))
+                                .on_hover_text("Level of the pyramid rendered while the camera moves, 0 being the full resolution");
+                        }
+                        if bricks_loading.is_some() {
+                            ui.label(format!("Level of detail: {}", lod_level))
+                                .on_hover_text("Regions too big for the brick atlas are streamed at a coarser level");
+                        } else {
+                            ui.label(format!("Level of detail: {}/{}", lod_level, lod_levels - 1));
+                        }
 
                         ui.separator();
                         ui.checkbox(&mut show_unique_slice, "Slice selector");
@@ -525,9 +972,66 @@ impl State {
 
                         ui.separator();
 
+                        // Cuts scope
+                        ui.label("Cuts");
+                        egui::ComboBox::from_label("Strategy")
+                            .selected_text(load_options.cut_strategy.name())
+                            .show_ui(ui, |ui| {
+                                for strategy in cuts::CutStrategy::all() {
+                                    let checked = load_options.cut_strategy.name() == strategy.name();
+                                    if ui.selectable_label(checked, strategy.name()).clicked() && !checked {
+                                        load_options.cut_strategy = strategy;
+                                    }
+                                }
+                            });
+                        match &mut load_options.cut_strategy {
+                            cuts::CutStrategy::Percentiles { low, high } => {
+                                ui.add(egui::Slider::new(low, 0.0..=100.0).text("low percentile"));
+                                ui.add(egui::Slider::new(high, 0.0..=100.0).text("high percentile"));
+                            }
+                            cuts::CutStrategy::ZScale { contrast } => {
+                                ui.add(egui::Slider::new(contrast, 0.01..=1.0).text("contrast"));
+                            }
+                            cuts::CutStrategy::SigmaClip { sigma, iterations } => {
+                                ui.add(egui::Slider::new(sigma, 1.0..=10.0).text("sigma"));
+                                ui.add(egui::Slider::new(iterations, 1..=20).text("iterations"));
+                            }
+                            cuts::CutStrategy::MinMax => {}
+                        }
+
+                        // histogram of the values over the range of the cut sliders
+                        if stats.count > 0 {
+                            let (rect, _) = ui.allocate_exact_size(egui::vec2(ui.available_width(), 60.0), egui::Sense::hover());
+                            let painter = ui.painter_at(rect);
+
+                            let counts = stats.histogram.rebin((datamin as f64)..(datamax as f64), HISTOGRAM_NUM_COLUMNS);
+                            let max_count = counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+                            let column_width = rect.width() / HISTOGRAM_NUM_COLUMNS as f32;
+                            for (i, count) in counts.iter().enumerate() {
+                                // log scale so that the tails of the distribution stay visible
+                                let h = (*count as f32).ln_1p() / max_count.ln_1p() * rect.height();
+                                let x = rect.left() + (i as f32) * column_width;
+
+                                painter.rect_filled(
+                                    egui::Rect::from_min_max(egui::pos2(x, rect.bottom() - h), egui::pos2(x + column_width, rect.bottom())),
+                                    0.0,
+                                    ui.visuals().widgets.inactive.fg_stroke.color,
+                                );
+                            }
+
+                            // current cuts
+                            for cut in [m1, m2] {
+                                let x = rect.left() + (cut - datamin) / (datamax - datamin) * rect.width();
+                                painter.vline(x, rect.y_range(), egui::Stroke::new(1.0, ui.visuals().selection.bg_fill));
+                            }
+                        }
+
+                        ui.separator();
+
                         // Volumetric scope
-                        ui.add_enabled_ui(!show_isosurface, |ui| {
-                            ui.label("Maximum Intensity Projection");
+                        // the cuts normalize the values of both the projection and the direct volume rendering
+                        ui.add_enabled_ui(render_mode != RenderMode::Isosurface, |ui| {
+                            ui.label("Cuts");
                             ui.add_sized(
                                 [ui.available_width(), 0.0],
                                 DoubleSlider::new(&mut m1, &mut m2, datamin..=datamax)
@@ -537,64 +1041,140 @@ impl State {
                             );
 
                             ui.horizontal(|ui| {
-                                ui.add(egui::Slider::new(&mut m1, datamin..=datamax).text("min cut"));
+                                ui.add(brightness_slider(&mut m1, datamin..=datamax, brightness_factor).text(with_unit("min cut", &display_unit)));
                             });
                             ui.horizontal(|ui| {
-                                ui.add(egui::Slider::new(&mut m2, datamin..=datamax).text("max cut"));
+                                ui.add(brightness_slider(&mut m2, datamin..=datamax, brightness_factor).text(with_unit("max cut", &display_unit)));
                             });
+
+                            egui::ComboBox::from_label("Stretch")
+                                .selected_text(stretch.name())
+                                .show_ui(ui, |ui| {
+                                    for s in stretch::Stretch::all() {
+                                        let checked = stretch.name() == s.name();
+                                        if ui.selectable_label(checked, s.name()).clicked() && !checked {
+                                            stretch = s;
+                                        }
+                                    }
+                                });
+                            match &mut stretch {
+                                stretch::Stretch::Log { base } => {
+                                    ui.add(egui::Slider::new(base, 1.0..=100000.0).logarithmic(true).text("log base"));
+                                }
+                                stretch::Stretch::Power { exponent } => {
+                                    ui.add(egui::Slider::new(exponent, 0.05..=5.0).logarithmic(true).text("exponent"));
+                                }
+                                stretch::Stretch::Asinh { softening } => {
+                                    ui.add(egui::Slider::new(softening, 0.001..=1.0).logarithmic(true).text("softening"));
+                                }
+                                stretch::Stretch::Linear | stretch::Stretch::Sqrt | stretch::Stretch::HistEq => {}
+                            }
                         });
-                        
+
+                        // Colormap scope
+                        if render_mode == RenderMode::Mip {
+                            ui.horizontal(|ui| {
+                                egui::ComboBox::from_label("Colormap")
+                                    .selected_text(&colormaps[colormap].name)
+                                    .show_ui(ui, |ui| {
+                                        for (i, c) in colormaps.iter().enumerate() {
+                                            ui.selectable_value(&mut colormap, i, &c.name);
+                                        }
+                                    });
+                                ui.checkbox(&mut reverse_colormap, "Reversed");
+                            });
+                            colormap::preview(ui, &colormaps[colormap], reverse_colormap, 12.0);
+
+                            #[cfg(not(target_arch = "wasm32"))]
+                            ui.horizontal(|ui| {
+                                ui.add(egui::TextEdit::singleline(&mut colormap_path).hint_text("Palette file (.csv, .json)"));
+                                load_colormap = ui
+                                    .add_enabled(!colormap_path.trim().is_empty(), egui::Button::new("Load"))
+                                    .on_hover_text("One r, g, b color per line, or a matplotlib JSON colormap")
+                                    .clicked();
+                            });
+                        }
+
                         ui.separator();
 
+                        // Direct volume rendering scope
+                        if render_mode == RenderMode::Dvr {
+                            ui.label("Transfer function")
+                                .on_hover_text("Drag the opacity control points, double-click to add one and right-click to remove it");
+                            let counts = stats.histogram.rebin((m1 as f64)..(m2 as f64), HISTOGRAM_NUM_COLUMNS);
+                            transfer::editor(ui, &mut transfer, &counts);
+
+                            ui.separator();
+                        }
+
                         // Isosurface scope
-                        ui.add_enabled_ui(show_isosurface, |ui| {
+                        ui.add_enabled_ui(render_mode == RenderMode::Isosurface, |ui| {
                             ui.label("Isosurface");
-                            ui.add(egui::Slider::new(&mut isosurface, datamin..=datamax).text("value"));
+                            ui.add(brightness_slider(&mut isosurface, datamin..=datamax, brightness_factor).text(with_unit("value", &display_unit)));
                             ui.label("Diffuse color");
                             ui.color_edit_button_rgba_unmultiplied(&mut diffuse_color);
                         });
                         
                         ui.separator();
                         // freq_min, freq_max, fov, ra, dec
-                        if let Some(wcs) = wcs.as_ref() {
-                            if ui.button("select").clicked() {
+                        // selecting a region needs world coordinates, the sliders giving pixels along the
+                        // celestial axes of the WCS (the first two FITS axes) only if they are rendered as X and Y
+                        let celestial = wcs.is_some() && axis_mapping.volume[..2] == [0, 1];
+                        ui.add_enabled_ui(celestial, |ui| {
+                            let reason = if wcs.is_some() { "The celestial axes are not rendered as X and Y" } else { "The cube has no WCS" };
+                            let select = ui.button("select").on_disabled_hover_text(reason);
+                            if let (true, Some(wcs)) = (select.clicked(), wcs.as_ref()) {
                                 let x_px = ra as f64;
                                 let y_px = dec as f64;
                                 let w_px = fov as f64;
 
+                                // spectral range in the type displayed
+                                #[cfg(target_arch = "wasm32")]
+                                let spectral_range = spectral.as_ref().and_then(|axis| {
+                                    Some((
+                                        axis.display(freq_min as f64, spectral_type, spectral_frame)?,
+                                        axis.display(freq_max as f64, spectral_type, spectral_frame)?,
+                                    ))
+                                });
+
                                 let freq_min = freq_min / (naxis.2 as f32);
                                 let freq_max = freq_max / (naxis.2 as f32);
 
-                                let p = wcs
-                                    .unproj(&ImgXY::new(x_px, y_px))
-                                    .unwrap();
-                                let p1 = wcs
-                                    .unproj(&ImgXY::new(x_px - w_px * 0.5, y_px))
-                                    .unwrap();
-                                let p2 = wcs
-                                    .unproj(&ImgXY::new(x_px + w_px * 0.5, y_px))
-                                    .unwrap();
-
-                                #[cfg(target_arch = "wasm32")]
-                                ONSELECT.with(|f| {
-                                    if let Some(cb) = &*f.borrow() {
-                                        use js_sys::Array;
-                                        let ra_min = p1.lon().to_degrees();
-                                        let ra_max = p2.lon().to_degrees();
-                                        let ra = p.lon().to_degrees();
-                                        let dec = p.lat().to_degrees();
-
-                                        let args = Array::new();
-                                        args.push(&JsValue::from_f64(ra));
-                                        args.push(&JsValue::from_f64(dec));
-                                        args.push(&JsValue::from_f64((ra_min - ra_max).abs()));
-                                        args.push(&JsValue::from_f64(freq_min as f64));
-                                        args.push(&JsValue::from_f64(freq_max as f64));
-                                        cb.apply(&JsValue::NULL, &args).unwrap();
-                                    }
-                                });
+                                let unproj = |x: f64| wcs.unproj(&ImgXY::new(x, y_px));
+                                let region = (unproj(x_px), unproj(x_px - w_px * 0.5), unproj(x_px + w_px * 0.5));
+
+                                // nothing is selected if the region is outside of the projection
+                                if let (Some(p), Some(p1), Some(p2)) = region {
+                                    #[cfg(target_arch = "wasm32")]
+                                    ONSELECT.with(|f| {
+                                        if let Some(cb) = &*f.borrow() {
+                                            use js_sys::Array;
+                                            let ra_min = p1.lon().to_degrees();
+                                            let ra_max = p2.lon().to_degrees();
+                                            let ra = p.lon().to_degrees();
+                                            let dec = p.lat().to_degrees();
+
+                                            let args = Array::new();
+                                            args.push(&JsValue::from_f64(ra));
+                                            args.push(&JsValue::from_f64(dec));
+                                            args.push(&JsValue::from_f64((ra_min - ra_max).abs()));
+                                            args.push(&JsValue::from_f64(freq_min as f64));
+                                            args.push(&JsValue::from_f64(freq_max as f64));
+                                            // followed by the spectral range, its type, unit and frame if the cube has a spectral axis
+                                            if let Some((spec_min, spec_max)) = spectral_range {
+                                                let (unit, _) = spectral_type.display_unit();
+                                                args.push(&JsValue::from_f64(spec_min));
+                                                args.push(&JsValue::from_f64(spec_max));
+                                                args.push(&JsValue::from_str(spectral_type.code()));
+                                                args.push(&JsValue::from_str(unit));
+                                                args.push(&spectral_frame.map(|f| JsValue::from_str(f.keyword())).unwrap_or(JsValue::NULL));
+                                            }
+                                            cb.apply(&JsValue::NULL, &args).unwrap();
+                                        }
+                                    });
+                                }
                             }
-                        }
+                        });
 
                         ui.separator();
 
@@ -602,48 +1182,97 @@ impl State {
                         ui.label("Viewport");
                         ui.checkbox(&mut perspective, "Perspective");
 
-                        if ui.button("RA Dec (Front)").clicked() {
+                        // cubes without WCS, or whose celestial axes are not rendered as X and Y, are explored along their pixel axes
+                        let [lon, lat, spec] = if celestial { ["RA", "Dec", "Freq"] } else { ["X", "Y", "Z"] };
+
+                        if ui.button(format!("{} {} (Front)", lon, lat)).clicked() {
                             new_view = Some((std::f32::consts::PI, 0.0));
                         }
 
-                        if ui.button("-RA Dec (Back)").clicked() {
+                        if ui.button(format!("-{} {} (Back)", lon, lat)).clicked() {
                             new_view = Some((0.0, 0.0));
                         }
 
-                        if ui.button("-Freq Dec (Left)").clicked() {
+                        if ui.button(format!("-{} {} (Left)", spec, lat)).clicked() {
                             new_view = Some((-std::f32::consts::PI/2.0, 0.0));
                         }
 
-                        if ui.button("Freq Dec (Right)").clicked() {
+                        if ui.button(format!("{} {} (Right)", spec, lat)).clicked() {
                             new_view = Some((std::f32::consts::PI/2.0, 0.0));
                         }
 
-                        if ui.button("RA Freq (Top)").clicked() {
+                        if ui.button(format!("{} {} (Top)", lon, spec)).clicked() {
                             new_view = Some((std::f32::consts::PI, std::f32::consts::PI * 0.5 - 1e-3));
                         }
 
-                        if ui.button("RA -Freq (Bottom)").clicked() {
+                        if ui.button(format!("{} -{} (Bottom)", lon, spec)).clicked() {
                             new_view = Some((std::f32::consts::PI, -std::f32::consts::PI * 0.5 + 1e-3));
                         }
 
-                        ui.label("Select a frequency range");
+                        ui.label(if spectral.is_some() { "Select a spectral range" } else { "Select a channel range" });
                         ui.add_sized(
                             [ui.available_width(), 0.0],
                             DoubleSlider::new(&mut freq_min, &mut freq_max, 0.0..=naxis.2 as f32)
                                 .width(ui.available_width())
                                 //.separation_distance((datamax - datamin) / 100.0)
                         );
+                        if let Some(axis) = spectral {
+                            egui::ComboBox::from_label("Spectral coordinate")
+                                .selected_text(format!("{} ({})", spectral_type.name(), spectral_type.code()))
+                                .show_ui(ui, |ui| {
+                                    for t in axis.types() {
+                                        ui.selectable_value(&mut spectral_type, t, format!("{} ({})", t.name(), t.code()));
+                                    }
+                                });
+
+                            let frames = axis.frames();
+                            if let Some(frame) = spectral_frame.filter(|_| frames.len() > 1) {
+                                egui::ComboBox::from_label("Spectral frame")
+                                    .selected_text(format!("{} ({})", frame.name(), frame.keyword()))
+                                    .show_ui(ui, |ui| {
+                                        for f in frames {
+                                            ui.selectable_value(&mut spectral_frame, Some(f), format!("{} ({})", f.name(), f.keyword()));
+                                        }
+                                    });
+                            } else if spectral_frame.is_none() {
+                                ui.label("Unknown spectral frame (no SPECSYS)");
+                            }
+
+                            let (unit, _) = spectral_type.display_unit();
+                            let start = axis.display(freq_min as f64, spectral_type, spectral_frame);
+                            let end = axis.display(freq_max as f64, spectral_type, spectral_frame);
+                            if let (Some(start), Some(end)) = (start, end) {
+                                ui.label(format!("{:.6} to {:.6} {}", start, end, unit));
+                            }
+                        }
 
                         ui.add(egui::Slider::new(&mut fov, 0.0..=naxis.0 as f32).text("Select fov"));
 
-                        ui.add(egui::Slider::new(&mut ra, 0.0..=naxis.0 as f32).text("Select ra"));
+                        ui.add(egui::Slider::new(&mut ra, 0.0..=naxis.0 as f32).text(format!("Select {}", lon.to_lowercase())));
+
+                        ui.add(egui::Slider::new(&mut dec, 0.0..=naxis.1 as f32).text(format!("Select {}", lat.to_lowercase())));
 
-                        ui.add(egui::Slider::new(&mut dec, 0.0..=naxis.1 as f32).text("Select dec"));
+                        ui.separator();
+
+                        // View scope
+                        ui.label("View");
+                        ui.horizontal(|ui| {
+                            if ui.button("Copy view").on_hover_text("Copy the camera, cuts, mode and transfer function").clicked() {
+                                ui.ctx().copy_text(current_view.to_text());
+                            }
+                            apply_view = ui.add_enabled(!view_text.trim().is_empty(), egui::Button::new("Apply view")).clicked();
+                        });
+                        ui.add(
+                            egui::TextEdit::multiline(&mut view_text)
+                                .hint_text("Paste a copied view")
+                                .desired_rows(3)
+                                .desired_width(f32::INFINITY),
+                        );
 
                         self.queue.write_buffer(
                             &self.buffers["isosurface"],
                             0,
-                            bytemuck::bytes_of(&[isosurface, 0.0, 0.0, 0.0]),
+                            bytemuck::bytes_of(&[self.encoding.to_gpu(isosurface as f64), 0.0, 0.0, 0.0]),
                         );
                         self.queue.write_buffer(
                             &self.buffers["perspective"],
@@ -658,7 +1287,12 @@ impl State {
                         self.queue.write_buffer(
                             &self.buffers["cuts"],
                             0,
-                            bytemuck::bytes_of(&[m1, m2, 0.0, 0.0]),
+                            bytemuck::bytes_of(&[self.encoding.to_gpu(m1 as f64), self.encoding.to_gpu(m2 as f64), 0.0, 0.0]),
+                        );
+                        self.queue.write_buffer(
+                            &self.buffers["blank_color"],
+                            0,
+                            bytemuck::bytes_of(&if show_blank { blank_color } else { [0.0; 4] }),
                         );
 
                         let (sx, sy, sz) = if show_unique_slice {
@@ -676,16 +1310,8 @@ impl State {
                             )
                         };
 
-                        self.queue.write_buffer(
-                            &self.buffers["slice_range"],
-                            0,
-                            bytemuck::bytes_of(&[
-                                sx.start as f32, sx.end as f32,
-                                sy.start as f32, sy.end as f32,
-                                sz.start as f32, sz.end as f32,
-                                0.0, 0.0
-                            ]),
-                        );
+                        slice_range = [sx, sy, sz];
+                        self.write_slice_range(&slice_range);
                     });
 
                     if let Some((theta,delta)) = new_view {
@@ -702,12 +1328,50 @@ impl State {
                     }
 
                     self.isosurface = isosurface;
+                    self.spectral_type = spectral_type;
+                    self.spectral_frame = spectral_frame;
+                    self.brightness_unit = brightness_unit;
                     self.perspective = perspective;
                     self.diffuse_color = diffuse_color;
-                    self.show_isosurface = show_isosurface;
+                    if render_mode != self.render_mode {
+                        self.set_render_mode(render_mode);
+                    }
+                    if transfer != self.transfer {
+                        self.set_transfer(transfer);
+                    }
+                    if stretch != self.stretch {
+                        self.set_stretch(stretch);
+                    }
+                    if colormap != self.colormap || reverse_colormap != self.reverse_colormap {
+                        self.set_colormap(colormap, reverse_colormap);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    {
+                        self.colormap_path = colormap_path;
+                        if load_colormap {
+                            let path = std::path::Path::new(self.colormap_path.trim());
+                            let name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+                            let palette = std::fs::read_to_string(path)
+                                .map_err(|e| Error::InvalidColormap { reason: format!("{}: {}", path.display(), e) })
+                                .and_then(|text| colormap::Colormap::from_file(&name, &text));
+                            match palette {
+                                Ok(palette) => self.add_colormap(palette),
+                                Err(error) => self.report(error),
+                            }
+                        }
+                    }
                     self.show_unique_slice = show_unique_slice;
+                    self.show_blank = show_blank;
+                    let cut_strategy = load_options.cut_strategy;
+                    let cuts_changed = cut_strategy != self.load_options.cut_strategy;
+                    self.load_options = load_options;
+                    self.blank_color = blank_color;
                     self.m1 = m1;
                     self.m2 = m2;
+                    // the new cuts override the ones set by the sliders
+                    if cuts_changed {
+                        self.set_cut_strategy(cut_strategy);
+                    }
 
                     self.freq_min = freq_min;
                     self.freq_max = freq_max;
@@ -716,9 +1380,35 @@ impl State {
                     self.dec = dec;
 
                     self.slice_idx = slice_idx;
+                    self.slice_range = slice_range;
+                    self.lod_interactive = lod_interactive;
+
+                    self.view_text = view_text;
+                    if apply_view {
+                        match view::View::from_text(&self.view_text, &self.current_view()) {
+                            Ok(view) => self.apply_view(view),
+                            Err(error) => self.report(error),
+                        }
+                    }
+
+                    if hdu_idx != self.hdu_idx {
+                        if let Err(error) = self.select_hdu(hdu_idx) {
+                            self.report(error);
+                        }
+                    } else if remap {
+                        if let Err(error) = self.remap_axes(axis_mapping) {
+                            self.report(error);
+                        }
+                    }
                 }
 
                 self.show_options = show_options;
+                if cancel_loading {
+                    self.cancel_loading();
+                }
+
+                // drawn in the area left by the panels
+                self.paint_beam(self.egui_renderer.context());
 
                 #[cfg(not(target_arch = "wasm32"))]
                 let screen_descriptor = egui_wgpu::ScreenDescriptor {
@@ -748,94 +1438,689 @@ impl State {
         Ok(())
     }
 
-    fn visualize_cube<R: AsRef<[u8]> + std::fmt::Debug>(
-        &mut self,
-        reader: Cursor<R>,
-    ) -> Result<(), &'static str> {
-        let (new_cube, mincut, maxcut, dim, wcs) = read_fits(reader, &self.device, &self.queue)?;
+    /// Compute again the cuts of the current cube with another strategy
+    fn set_cut_strategy(&mut self, strategy: cuts::CutStrategy) {
+        let cuts = strategy.compute(&self.stats, &self.cut_samples);
+        self.load_options.cut_strategy = strategy;
 
-        // reset the cutoff values
+        self.cut10 = cuts.start as f32;
+        self.cut90 = cuts.end as f32;
+        self.m1 = self.cut10;
+        self.m2 = self.cut90;
         self.queue.write_buffer(
             &self.buffers["cuts"],
             0,
-            bytemuck::bytes_of(&[mincut, maxcut, 0.0, 0.0]),
+            bytemuck::bytes_of(&[self.encoding.to_gpu(cuts.start), self.encoding.to_gpu(cuts.end), 0.0, 0.0]),
         );
+    }
+
+    fn set_render_mode(&mut self, mode: RenderMode) {
+        self.render_mode = mode;
         self.queue.write_buffer(
-            &self.buffers["size"],
+            &self.buffers["render_mode"],
             0,
-            bytemuck::bytes_of(&[dim.0 as f32, dim.1 as f32, dim.2 as f32, 0.0]),
+            bytemuck::bytes_of(&[mode.uniform(), 0.0, 0.0, 0.0]),
         );
+    }
 
-        self.ra = (dim.0 as f32) * 0.5;
-        self.dec = (dim.1 as f32) * 0.5;
-        self.freq_min = 0.0;
-        self.freq_max = dim.2 as f32;
-        self.fov = dim.0 as f32; // todo
+    fn set_transfer(&mut self, transfer: transfer::TransferFunction) {
+        self.volumetric_renderer.write_transfer(&self.queue, &transfer.lut());
+        self.transfer = transfer;
+    }
 
-        if !self.show_unique_slice {
-            self.queue.write_buffer(
-                &self.buffers["slice_range"],
-                0,
-                bytemuck::bytes_of(&[
-                    0.0, dim.0 as f32,
-                    0.0, dim.1 as f32,
-                    0.0, dim.2 as f32,
-                    0.0, 0.0
-                ]),
-            );
+    fn set_stretch(&mut self, stretch: stretch::Stretch) {
+        self.stretch = stretch;
+        self.queue.write_buffer(&self.buffers["stretch"], 0, bytemuck::bytes_of(&stretch.uniform()));
+    }
+
+    fn set_colormap(&mut self, index: usize, reversed: bool) {
+        self.colormap = index;
+        self.reverse_colormap = reversed;
+        self.volumetric_renderer.write_colormap(&self.queue, &self.colormaps[index].lut(reversed));
+    }
+
+    /// Add a loaded palette to the colormaps and show it
+    ///
+    /// A palette loaded again with the same name replaces the previous one.
+    fn add_colormap(&mut self, colormap: colormap::Colormap) {
+        let index = match self.colormaps.iter().position(|c| c.name == colormap.name) {
+            Some(index) => {
+                self.colormaps[index] = colormap;
+                index
+            }
+            None => {
+                self.colormaps.push(colormap);
+                self.colormaps.len() - 1
+            }
+        };
+
+        self.set_colormap(index, self.reverse_colormap);
+    }
+
+    /// Settings of the current view
+    fn current_view(&self) -> view::View {
+        view::View {
+            camera: (self.theta + self.dtheta, self.delta + self.ddelta),
+            perspective: self.perspective,
+            mode: self.render_mode,
+            cuts: (self.m1, self.m2),
+            stretch: self.stretch,
+            isosurface: self.isosurface,
+            transfer: self.transfer.clone(),
+            colormap: self.colormaps[self.colormap].name.clone(),
+            reverse_colormap: self.reverse_colormap,
         }
+    }
 
-        self.cut10 = mincut;
-        self.cut90 = maxcut;
-        // by default, set the cuts to the one precalculated
-        self.m1 = mincut;
-        self.m2 = maxcut;
+    /// Restore a view stored with [view::View::to_text]
+    fn apply_view(&mut self, view: view::View) {
+        let (theta, delta) = view.camera;
+        self.theta = theta;
+        self.delta = delta.clamp(-std::f64::consts::FRAC_PI_2 + 1e-3, std::f64::consts::FRAC_PI_2 - 1e-3);
+        self.dtheta = 0.0;
+        self.ddelta = 0.0;
+        self.perspective = view.perspective;
+        self.m1 = view.cuts.0;
+        self.m2 = view.cuts.1;
+        self.isosurface = view.isosurface;
 
-        self.naxis = dim;
-        self.wcs = Some(wcs);
+        self.queue.write_buffer(
+            &self.buffers["cam_origin"],
+            0,
+            bytemuck::bytes_of(&[self.theta as f32, self.delta as f32, 0.0, 0.0]),
+        );
+        self.queue.write_buffer(
+            &self.buffers["perspective"],
+            0,
+            bytemuck::bytes_of(&[if self.perspective { 1.0_f32 } else { 0.0_f32 }, 0.0, 0.0, 0.0]),
+        );
+        self.queue.write_buffer(
+            &self.buffers["cuts"],
+            0,
+            bytemuck::bytes_of(&[self.encoding.to_gpu(self.m1 as f64), self.encoding.to_gpu(self.m2 as f64), 0.0, 0.0]),
+        );
+        self.queue.write_buffer(
+            &self.buffers["isosurface"],
+            0,
+            bytemuck::bytes_of(&[self.encoding.to_gpu(self.isosurface as f64), 0.0, 0.0, 0.0]),
+        );
+        self.set_render_mode(view.mode);
+        self.set_stretch(view.stretch);
+        let colormap = self.colormaps.iter().position(|c| c.name == view.colormap);
+        self.set_transfer(view.transfer);
+        match colormap {
+            Some(index) => self.set_colormap(index, view.reverse_colormap),
+            None => self.report(Error::InvalidView {
+                reason: format!("unknown colormap {}, load its palette first", view.colormap),
+            }),
+        }
+    }
+
+    /// Log an error and show it in the notification panel
+    fn report(&mut self, error: Error) {
+        log::error!("{}", error);
+        self.errors.push(error);
+    }
 
-        self.volumetric_renderer.set_volume(&self.device, &self.buffers, new_cube);
+    /// Load the first cube of a FITS file
+    ///
+    /// The cube is loaded in the background, the current one stays
+    /// displayed until it is ready.
+    fn visualize_cube<R: AsRef<[u8]> + Send + Sync + 'static>(&mut self, source: R) {
+        // a new file is opened on its first cube
+        let options = LoadOptions {
+            hdu: None,
+            axes: None,
+            ..self.load_options.clone()
+        };
 
-        Ok(())
+        self.load(Arc::new(source), options);
     }
-}
 
-use std::ops::Range;
-#[cfg(target_arch = "wasm32")]
-#[derive(Debug, Default)]
-struct Params {
-    perspective: Option<bool>,
-    cuts: Option<Range<f32>>,
-    data: Option<Vec<u8>>,
-}
+    fn select_hdu(&mut self, index: usize) -> Result<(), Error> {
+        self.reload(LoadOptions {
+            hdu: Some(index),
+            axes: None,
+            ..self.load_options.clone()
+        })
+    }
 
-#[cfg(target_arch = "wasm32")]
-thread_local! {
-    #[cfg(target_arch = "wasm32")]
-    static ONSELECT: std::cell::RefCell<Option<js_sys::Function>> =
-        std::cell::RefCell::new(None);
-}
+    /// Extract another hyperplane of the current HDU
+    fn remap_axes(&mut self, mapping: axes::AxisMapping) -> Result<(), Error> {
+        self.reload(LoadOptions {
+            hdu: Some(self.hdu_idx),
+            axes: Some(mapping),
+            ..self.load_options.clone()
+        })
+    }
 
-#[cfg(target_arch = "wasm32")]
-use lazy_static::lazy_static;
-#[cfg(target_arch = "wasm32")]
-lazy_static! {
-    static ref CHANNEL_PARAMS: (
-        async_channel::Sender<Params>,
-        async_channel::Receiver<Params>,
-    ) = async_channel::unbounded::<Params>();
-}
+    /// Load again the current file with other options
+    fn reload(&mut self, options: LoadOptions) -> Result<(), Error> {
+        let source = self.source.clone().ok_or(Error::NoFileLoaded)?;
+        self.load(source, options);
 
-#[cfg(target_arch = "wasm32")]
-static mut PARAMS: Params = Params {
-    perspective: None,
-    cuts: None,
-    data: None,
-};
+        Ok(())
+    }
 
-#[cfg(target_arch = "wasm32")]
-#[wasm_bindgen(js_name = "setPerspective")]
-pub fn set_perspective(perspective: bool) {
+    /// Parse a cube in the background, it is shown by [State::update] once ready
+    ///
+    /// On native, the cube is parsed by another thread so that the window stays
+    /// responsive. Decoding and statistics are themselves spread over the rayon
+    /// thread pool. The cube currently displayed is kept until the new one is
+    /// ready, a cube still loading is cancelled.
+    fn load(&mut self, source: Arc<Source>, options: LoadOptions) {
+        self.cancel_loading();
+
+        let progress = Arc::new(Progress::default());
+        progress.start(Stage::Read, 0);
+        let (sender, receiver) = mpsc::channel();
+
+        let job = {
+            let progress = progress.clone();
+            move || {
+                let loaded = load_file(source, &options, &progress);
+                // the receiver is dropped if another file has been asked in the meantime
+                let _ = sender.send((loaded, options));
+            }
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(job);
+        // the thread pool of the page is set up by initThreadPool
+        #[cfg(all(target_arch = "wasm32", feature = "wasm-threads"))]
+        rayon::spawn(job);
+        // without threads the page is blocked while the cube is parsed,
+        // the progress bar is rendered first so that the user knows why
+        #[cfg(all(target_arch = "wasm32", not(feature = "wasm-threads")))]
+        {
+            self.deferred_load = Some((Box::new(job), false));
+        }
+
+        self.loading = Some(Loading { progress, receiver });
+    }
+
+    /// Stop loading a cube, the one displayed is kept
+    fn cancel_loading(&mut self) {
+        if let Some(loading) = self.loading.take() {
+            loading.progress.cancel();
+        }
+
+        #[cfg(all(target_arch = "wasm32", not(feature = "wasm-threads")))]
+        {
+            self.deferred_load = None;
+        }
+    }
+
+    /// Run the loading job once a frame has been rendered since [State::load]
+    #[cfg(all(target_arch = "wasm32", not(feature = "wasm-threads")))]
+    fn run_deferred_load(&mut self) {
+        match self.deferred_load.take() {
+            Some((job, true)) => job(),
+            Some((job, false)) => self.deferred_load = Some((job, true)),
+            None => {}
+        }
+    }
+
+    /// Show a cube once it has been loaded
+    fn poll_loading(&mut self) {
+        let Some(loading) = &self.loading else {
+            return;
+        };
+
+        match loading.receiver.try_recv() {
+            Ok((loaded, options)) => {
+                self.loading = None;
+
+                let shown = loaded.and_then(|(cube, source)| {
+                    self.show_cube(cube, &options)?;
+                    // keep the file to be able to switch to another of its HDUs
+                    self.source = Some(source);
+                    Ok(())
+                });
+                if let Err(error) = shown {
+                    self.report(error);
+                }
+            }
+            Err(mpsc::TryRecvError::Empty) => {}
+            Err(mpsc::TryRecvError::Disconnected) => self.loading = None,
+        }
+    }
+
+    /// Upload a cube on the GPU
+    fn show_cube(&mut self, cube: Cube, options: &LoadOptions) -> Result<(), Error> {
+        let Cube {
+            voxels,
+            levels,
+            dim,
+            mincut,
+            maxcut,
+            samples,
+            stats,
+            wcs,
+            wcs_error,
+            spectral,
+            beams,
+            bunit,
+            scale,
+            encoding,
+            hdus,
+            hdu_idx,
+            axes,
+            ..
+        } = cube;
+
+        // the textures are created first so that the current cube stays displayed if they cannot be
+        let (upload, bricked, lod_levels) = match voxels {
+            bricks::Voxels::Decoded { data, mask } if !bricks::needs_bricking(dim, options.max_texture_dimension) => {
+                let cube_levels = levels
+                    .iter()
+                    .map(|(values, _)| bytemuck::cast_slice(values))
+                    .collect::<Vec<&[u8]>>();
+                let mask_levels = levels.iter().map(|(_, valid)| &valid[..]).collect::<Vec<&[u8]>>();
+
+                // the full resolution channels are uploaded by batches in the next frames
+                let upload = Upload {
+                    cube: Texture::from_mips::<f32>(&self.device, &self.queue, &cube_levels, dim, 4, "cube")?,
+                    mask: Texture::from_mips::<u8>(&self.device, &self.queue, &mask_levels, dim, 1, "mask")?,
+                    data,
+                    valid: mask,
+                };
+                (Some(upload), None, levels.len() as u32 + 1)
+            }
+            voxels => {
+                let cache = bricks::BrickCache::new(&self.device, &self.queue, dim)?;
+                (None, Some((cache, voxels)), lod::num_levels(dim))
+            }
+        };
+
+        // nothing can fail from here, the state is switched to the new cube
+        if let Some(upload) = &upload {
+            // the cube is not bricked, its texture is given in place of the brick table
+            self.volumetric_renderer.set_volume(&self.device, &self.buffers, &upload.cube, &upload.mask, &upload.cube);
+            self.queue.write_buffer(&self.buffers["bricking"], 0, bytemuck::bytes_of(&[0.0_f32; 4]));
+            self.uploaded_channels = 0;
+        } else if let Some((cache, _)) = &bricked {
+            self.volumetric_renderer.set_volume(&self.device, &self.buffers, cache.atlas(), cache.mask(), cache.table());
+            self.queue.write_buffer(&self.buffers["bricking"], 0, bytemuck::bytes_of(&cache.bricking()));
+            self.uploaded_channels = dim.2;
+        }
+        self.upload = upload;
+        self.bricked = bricked;
+        self.lod_levels = lod_levels;
+
+        // reset the cutoff values
+        self.queue.write_buffer(
+            &self.buffers["cuts"],
+            0,
+            bytemuck::bytes_of(&[encoding.to_gpu(mincut as f64), encoding.to_gpu(maxcut as f64), 0.0, 0.0]),
+        );
+        self.queue.write_buffer(
+            &self.buffers["size"],
+            0,
+            bytemuck::bytes_of(&[dim.0 as f32, dim.1 as f32, dim.2 as f32, 0.0]),
+        );
+
+        self.ra = (dim.0 as f32) * 0.5;
+        self.dec = (dim.1 as f32) * 0.5;
+        self.freq_min = 0.0;
+        self.freq_max = dim.2 as f32;
+        self.fov = dim.0 as f32; // todo
+
+        self.cut10 = mincut;
+        self.cut90 = maxcut;
+        self.cut_samples = samples;
+        self.stats = stats;
+        self.equalized = None;
+        // by default, set the cuts to the one precalculated
+        self.m1 = mincut;
+        self.m2 = maxcut;
+
+        self.naxis = dim;
+        self.wcs = wcs;
+        self.spectral_type = spectral.as_ref().map(|axis| axis.ctype).unwrap_or(self.spectral_type);
+        self.spectral_frame = spectral.as_ref().and_then(|axis| axis.frame);
+        self.spectral = spectral;
+        self.beams = beams;
+        if let Some(error) = wcs_error {
+            self.report(error);
+        }
+        self.bunit = bunit;
+        self.scale = scale;
+        self.encoding = encoding;
+        self.hdus = hdus;
+        self.hdu_idx = hdu_idx;
+        self.axis_mapping = axes;
+
+        // the brightness unit chosen is kept for the next cubes
+        let pixel_solid_angle = self.wcs.as_ref().zip(self.celestial_center()).and_then(|(wcs, center)| beam::pixel_solid_angle(wcs, center));
+        let spectral = self.spectral.as_ref();
+        self.brightness = self.bunit.as_deref().and_then(|bunit| {
+            brightness::Brightness::new(bunit, self.beams.as_ref(), pixel_solid_angle, dim.2, |channel| {
+                spectral?.coordinate(channel as f64 + 0.5, spectral::SpectralType::Freq, None)
+            })
+        });
+
+        self.slice_range = [0.0..(dim.0 as f32), 0.0..(dim.1 as f32), 0.0..(dim.2 as f32)];
+
+        if !self.show_unique_slice {
+            self.write_slice_range(&self.slice_range);
+        }
+
+        Ok(())
+    }
+
+    /// Upload the next batch of channels of a cube
+    ///
+    /// The channels of a batch are written at once in the textures allocated by
+    /// [State::show_cube], so that the cube builds up instead of appearing
+    /// once fully uploaded.
+    fn upload_channels(&mut self) {
+        let Some(upload) = &self.upload else {
+            return;
+        };
+
+        let (nx, ny, nz) = self.naxis;
+        let channel_len = nx as usize * ny as usize;
+        let Range { start, end } = upload_batch(self.naxis, self.uploaded_channels);
+
+        let voxels = (start as usize * channel_len)..(end as usize * channel_len);
+        let batch_dim = (nx, ny, end - start);
+        upload.cube.write_data(&self.queue, (0, 0, start), bytemuck::cast_slice(&upload.data[voxels.clone()]), batch_dim);
+        upload.mask.write_data(&self.queue, (0, 0, start), &upload.valid[voxels], batch_dim);
+
+        self.uploaded_channels = end;
+        if end == nz {
+            self.upload = None;
+        }
+        if !self.show_unique_slice {
+            self.write_slice_range(&self.slice_range);
+        }
+    }
+
+    /// Write the region of the cube rendered
+    ///
+    /// The spectral range is limited to the channels uploaded so far.
+    fn write_slice_range(&self, range: &[Range<f32>; 3]) {
+        let [sx, sy, sz] = range;
+        let sz = sz.start..sz.end.min(self.uploaded_channels as f32);
+
+        self.queue.write_buffer(
+            &self.buffers["slice_range"],
+            0,
+            bytemuck::bytes_of(&[
+                sx.start, sx.end,
+                sy.start, sy.end,
+                sz.start, sz.end,
+                0.0, 0.0
+            ]),
+        );
+    }
+
+    /// Channel of the volume displayed, the middle one of the spectral range selected
+    fn displayed_channel(&self) -> u32 {
+        let channel = if self.show_unique_slice {
+            self.slice_idx
+        } else {
+            (0.5 * (self.freq_min + self.freq_max)).max(0.0) as u32
+        };
+
+        channel.min(self.naxis.2.saturating_sub(1))
+    }
+
+    /// Factor converting the values of the channel displayed into the brightness unit chosen, with that unit
+    ///
+    /// The values are shown in BUNIT if no unit is chosen or if they cannot be converted into it.
+    /// When the factor varies along the channels, the unit names the channel it is valid at.
+    fn brightness_conversion(&self) -> (f64, Option<String>) {
+        let channel = self.displayed_channel();
+        self.brightness
+            .as_ref()
+            .zip(self.brightness_unit)
+            .and_then(|(brightness, unit)| {
+                let factor = brightness.factor(unit, channel)?;
+                let name = if brightness.is_per_channel(unit) {
+                    format!("{} at channel {}", unit.name(), channel)
+                } else {
+                    unit.name().to_owned()
+                };
+
+                Some((factor, Some(name)))
+            })
+            .unwrap_or((1.0, self.bunit.clone()))
+    }
+
+    /// Pixel at the center of the celestial plane of the HDU displayed
+    fn celestial_center(&self) -> Option<(f64, f64)> {
+        let naxis = &self.hdus.get(self.hdu_idx)?.naxis;
+        Some((*naxis.first()? as f64 * 0.5, *naxis.get(1)? as f64 * 0.5))
+    }
+
+    /// Number of pixels in the beam of a channel of the volume
+    ///
+    /// Converts fluxes per beam into fluxes per pixel. `None` if the cube has
+    /// no beam or no WCS giving the solid angle of its pixels.
+    fn pixels_per_beam(&self, channel: u32) -> Option<f64> {
+        let beam = self.beams.as_ref()?.beam(channel)?;
+        let pixel_solid_angle = beam::pixel_solid_angle(self.wcs.as_ref()?, self.celestial_center()?)?;
+
+        Some(beam.num_pixels(pixel_solid_angle))
+    }
+
+    /// Draw the beam of the channel displayed in the bottom left corner of the view
+    ///
+    /// The ellipse is projected with the camera like the celestial plane of the
+    /// volume so that it follows the orientation of the sky. It is drawn at the
+    /// scale of the volume, unless it would be too small to be seen or would
+    /// not fit in the corner.
+    fn paint_beam(&self, ctx: &egui::Context) {
+        let (Some(wcs), Some(beams), Some(center)) = (&self.wcs, &self.beams, self.celestial_center()) else {
+            return;
+        };
+        let Some(beam) = beams.beam(self.displayed_channel()) else {
+            return;
+        };
+        // the celestial axes of the WCS are the first two FITS axes, they must both be rendered
+        let slots = [0, 1].map(|axis| self.axis_mapping.volume.iter().position(|a| *a == axis));
+        let ([Some(lon_slot), Some(lat_slot)], Some([major, minor])) = (slots, beam.pixel_axes(wcs, center)) else {
+            return;
+        };
+
+        // screen basis of the camera, computed as in the volumetric shader
+        let lon = self.theta + self.dtheta;
+        let lat = (self.delta + self.ddelta).clamp(-std::f64::consts::FRAC_PI_2 + 1e-3, std::f64::consts::FRAC_PI_2 - 1e-3);
+        let dir = [-lat.cos() * lon.sin(), -lat.sin(), -lat.cos() * lon.cos()];
+        let norm = dir[0].hypot(dir[2]);
+        let ox = [dir[2] / norm, 0.0, -dir[0] / norm];
+        let oy = [
+            ox[2] * dir[1] - ox[1] * dir[2],
+            ox[0] * dir[2] - ox[2] * dir[0],
+            ox[1] * dir[0] - ox[0] * dir[1],
+        ];
+
+        // the volume is scaled to a unit cube whose unit spans half the width of the view
+        let rect = ctx.available_rect();
+        let points_per_unit = ctx.content_rect().width() as f64 * 0.5;
+        let dim = [self.naxis.0, self.naxis.1, self.naxis.2];
+        let to_screen = |(dx, dy): (f64, f64)| {
+            let mut v = [0.0; 3];
+            v[lon_slot] = dx / dim[lon_slot] as f64;
+            v[lat_slot] = dy / dim[lat_slot] as f64;
+
+            let x = v[0] * ox[0] + v[1] * ox[1] + v[2] * ox[2];
+            let y = v[0] * oy[0] + v[1] * oy[1] + v[2] * oy[2];
+            // the y axis of the screen points downward
+            egui::vec2((x * points_per_unit) as f32, (-y * points_per_unit) as f32)
+        };
+        let (major, minor) = (to_screen(major), to_screen(minor));
+
+        let radius = major.length().max(minor.length());
+        if radius <= 0.0 || !radius.is_finite() {
+            return;
+        }
+        let scale = radius.clamp(BEAM_MIN_RADIUS, BEAM_MAX_RADIUS) / radius;
+        let center = rect.left_bottom() + egui::vec2(BEAM_MAX_RADIUS + 10.0, -(BEAM_MAX_RADIUS + 10.0));
+
+        let points = (0..BEAM_NUM_POINTS)
+            .map(|i| {
+                let t = (i as f32) / (BEAM_NUM_POINTS as f32) * std::f32::consts::TAU;
+                center + (major * t.cos() + minor * t.sin()) * scale
+            })
+            .collect::<Vec<_>>();
+
+        let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("beam")));
+        painter.add(egui::Shape::convex_polygon(
+            points,
+            egui::Color32::from_white_alpha(64),
+            egui::Stroke::new(1.0, egui::Color32::WHITE),
+        ));
+    }
+}
+
+/// Radius range of the beam ellipse drawn in the corner of the view, in points
+const BEAM_MIN_RADIUS: f32 = 6.0;
+const BEAM_MAX_RADIUS: f32 = 40.0;
+/// Number of vertices of the beam ellipse
+const BEAM_NUM_POINTS: usize = 64;
+
+/// Slider over the values of the cube showing them multiplied by the factor converting them into the brightness unit displayed
+fn brightness_slider(value: &mut f32, range: std::ops::RangeInclusive<f32>, factor: f64) -> egui::Slider<'_> {
+    let slider = egui::Slider::new(value, range);
+    if factor == 1.0 {
+        return slider;
+    }
+
+    slider
+        .custom_formatter(move |v, _| format!("{:.4e}", v * factor))
+        .custom_parser(move |text| text.trim().parse::<f64>().ok().map(|v| v / factor))
+}
+
+/// Number of bytes of the channels uploaded to the GPU per frame
+const UPLOAD_BATCH_NUM_BYTES: usize = 1 << 26;
+
+/// Channels of a cube uploaded in the frame following the upload of the `uploaded` first ones
+///
+/// At least one channel is uploaded per frame, whatever its size.
+fn upload_batch(dim: (u32, u32, u32), uploaded: u32) -> Range<u32> {
+    let channel_len = dim.0 as usize * dim.1 as usize;
+    let num_channels = (UPLOAD_BATCH_NUM_BYTES / (channel_len * 4)).max(1) as u32;
+
+    uploaded..(uploaded + num_channels).min(dim.2)
+}
+
+/// Decoded cube whose channels are being uploaded to the GPU
+struct Upload {
+    cube: Texture,
+    mask: Texture,
+    data: Vec<f32>,
+    valid: Vec<u8>,
+}
+
+/// Bytes of a FITS file, memory mapped or received from the browser
+type Source = dyn AsRef<[u8]> + Send + Sync;
+
+/// Cube parsed from a file, with the file it has been parsed from
+type Parsed = Result<(Cube, Arc<Source>), Error>;
+
+/// Cube being loaded in the background
+struct Loading {
+    progress: Arc<Progress>,
+    receiver: mpsc::Receiver<(Parsed, LoadOptions)>,
+}
+
+/// Parse the cube of a FITS file
+///
+/// The file is given back along with the cube, decompressed if it was gzipped.
+/// Everything but the upload to the GPU is done here, off the main thread.
+fn load_file(source: Arc<Source>, options: &LoadOptions, progress: &Progress) -> Parsed {
+    let source = if gz::is_gzip((*source).as_ref()) {
+        // only the decompressed file is kept
+        progress.start(Stage::Read, (*source).as_ref().len() as u64);
+        let data = gz::decompress((*source).as_ref(), progress)?;
+        drop(source);
+        Arc::new(data) as Arc<Source>
+    } else {
+        source
+    };
+
+    let mut cube = parse_fits_data_cube((*source).as_ref(), options, progress)?;
+    progress.check()?;
+
+    // the coarse levels are stored as the mip levels of the textures
+    progress.start(Stage::Upload, 0);
+    if let bricks::Voxels::Decoded { data, mask } = &cube.voxels {
+        if !bricks::needs_bricking(cube.dim, options.max_texture_dimension) {
+            cube.levels = lod::build(data, mask, cube.dim);
+        }
+    }
+
+    Ok((cube, source))
+}
+
+/// Append the physical unit of the cube values to a label
+fn with_unit(label: &str, unit: &Option<String>) -> String {
+    if let Some(unit) = unit {
+        format!("{} ({})", label, unit)
+    } else {
+        label.to_owned()
+    }
+}
+
+use std::borrow::Cow;
+use std::ops::Range;
+use std::sync::mpsc;
+use progress::{Progress, Stage};
+#[cfg(target_arch = "wasm32")]
+#[derive(Debug, Default)]
+struct Params {
+    perspective: Option<bool>,
+    cuts: Option<Range<f32>>,
+    cut_strategy: Option<cuts::CutStrategy>,
+    // keyword of the render mode
+    render_mode: Option<String>,
+    stretch: Option<stretch::Stretch>,
+    // name and reversal of the colormap
+    colormap: Option<(String, bool)>,
+    // name and content of a palette file
+    palette: Option<(String, String)>,
+    view: Option<String>,
+    data: Option<Vec<u8>>,
+}
+
+#[cfg(target_arch = "wasm32")]
+thread_local! {
+    #[cfg(target_arch = "wasm32")]
+    static ONSELECT: std::cell::RefCell<Option<js_sys::Function>> =
+        std::cell::RefCell::new(None);
+    // view of the last rendered frame given to getView
+    static VIEW: std::cell::RefCell<String> = std::cell::RefCell::new(String::new());
+}
+
+#[cfg(target_arch = "wasm32")]
+use lazy_static::lazy_static;
+#[cfg(target_arch = "wasm32")]
+lazy_static! {
+    static ref CHANNEL_PARAMS: (
+        async_channel::Sender<Params>,
+        async_channel::Receiver<Params>,
+    ) = async_channel::unbounded::<Params>();
+}
+
+#[cfg(target_arch = "wasm32")]
+static mut PARAMS: Params = Params {
+    perspective: None,
+    cuts: None,
+    cut_strategy: None,
+    render_mode: None,
+    stretch: None,
+    colormap: None,
+    palette: None,
+    view: None,
+    data: None,
+};
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setPerspective")]
+pub fn set_perspective(perspective: bool) {
     wasm_bindgen_futures::spawn_local(async move {
         CHANNEL_PARAMS
             .0
@@ -862,6 +2147,171 @@ pub fn normalize(min: f32, max: f32) {
     });
 }
 
+#[cfg(target_arch = "wasm32")]
+fn send_cut_strategy(strategy: cuts::CutStrategy) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                cut_strategy: Some(strategy),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Cuts at the given percentiles, e.g. 0.5 and 99.5
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setPercentileCuts")]
+pub fn set_percentile_cuts(low: f32, high: f32) {
+    send_cut_strategy(cuts::CutStrategy::Percentiles { low, high });
+}
+
+/// Cuts given by the IRAF ZScale algorithm
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setZScaleCuts")]
+pub fn set_zscale_cuts(contrast: f32) {
+    send_cut_strategy(cuts::CutStrategy::ZScale { contrast });
+}
+
+/// Min and max of the values kept after a MAD-based sigma-clipping
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setSigmaClipCuts")]
+pub fn set_sigma_clip_cuts(sigma: f32, iterations: u32) {
+    send_cut_strategy(cuts::CutStrategy::SigmaClip { sigma, iterations });
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setMinMaxCuts")]
+pub fn set_min_max_cuts() {
+    send_cut_strategy(cuts::CutStrategy::MinMax);
+}
+
+#[cfg(target_arch = "wasm32")]
+fn send_stretch(stretch: stretch::Stretch) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                stretch: Some(stretch),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setLinearStretch")]
+pub fn set_linear_stretch() {
+    send_stretch(stretch::Stretch::Linear);
+}
+
+/// log(a x + 1) / log(a + 1) of the values x normalized between the cuts, a being the base
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setLogStretch")]
+pub fn set_log_stretch(base: f32) {
+    send_stretch(stretch::Stretch::Log { base });
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setSqrtStretch")]
+pub fn set_sqrt_stretch() {
+    send_stretch(stretch::Stretch::Sqrt);
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setPowerStretch")]
+pub fn set_power_stretch(exponent: f32) {
+    send_stretch(stretch::Stretch::Power { exponent });
+}
+
+/// asinh(x / a) / asinh(1 / a) of the values x normalized between the cuts, a being the softening
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setAsinhStretch")]
+pub fn set_asinh_stretch(softening: f32) {
+    send_stretch(stretch::Stretch::Asinh { softening });
+}
+
+/// Histogram equalization of the values between the cuts
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setHistEqStretch")]
+pub fn set_histeq_stretch() {
+    send_stretch(stretch::Stretch::HistEq);
+}
+
+/// Rendering mode among "mip", "dvr" and "isosurface"
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setRenderMode")]
+pub fn set_render_mode(mode: String) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                render_mode: Some(mode),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Colormap of the maximum intensity projection, one of the builtin ones or of the loaded palettes
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setColormap")]
+pub fn set_colormap(name: String, reversed: bool) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                colormap: Some((name, reversed)),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Load a palette from the content of a CSV or matplotlib JSON file and show it
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "loadColormap")]
+pub fn load_colormap(name: String, text: String) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                palette: Some((name, text)),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Restore a view given by getView, the missing keys are left unchanged
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setView")]
+pub fn set_view(text: String) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                view: Some(text),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Camera, cuts, rendering mode and transfer function of the view, as key = value lines
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "getView")]
+pub fn get_view() -> String {
+    VIEW.with(|view| view.borrow().clone())
+}
+
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen(js_name = "onselect")]
 pub fn onselect(func: js_sys::Function) {
@@ -870,6 +2320,10 @@ pub fn onselect(func: js_sys::Function) {
     });
 }
 
+// exported as initThreadPool, to be awaited before loading a cube
+#[cfg(all(target_arch = "wasm32", feature = "wasm-threads"))]
+pub use wasm_bindgen_rayon::init_thread_pool;
+
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen(js_name = "displayFITS")]
 pub fn display(raw_bytes: js_sys::Uint8Array) {
@@ -947,8 +2401,7 @@ impl App {
             let file = File::open(&CUBES_PATH[0]).unwrap();
             let mmap = unsafe { Mmap::map(&file).unwrap() };
 
-            let reader = Cursor::new(mmap);
-            let _ = state.visualize_cube(reader);
+            state.visualize_cube(mmap);
         }
 
         self.window.get_or_insert(window);
@@ -968,14 +2421,7 @@ impl ApplicationHandler for App {
             .unwrap();
         #[cfg(target_arch = "wasm32")]
         if let Ok(data) = state.recv_data.try_recv() {
-            let reader = Cursor::new(data.as_slice());
-            match state.visualize_cube(reader) {
-                Ok(()) => {}
-                Err(error) => web_sys::window()
-                    .unwrap()
-                    .alert_with_message(error)
-                    .unwrap(),
-            }
+            state.visualize_cube(data);
         }
 
         #[cfg(target_arch = "wasm32")]
@@ -983,10 +2429,63 @@ impl ApplicationHandler for App {
             let Params {
                 perspective,
                 cuts,
+                cut_strategy,
+                render_mode,
+                stretch,
+                colormap,
+                palette,
+                view,
                 data,
                 ..
             } = params;
 
+            if let Some(strategy) = cut_strategy {
+                state.set_cut_strategy(strategy);
+            }
+
+            if let Some(mode) = render_mode {
+                match RenderMode::from_keyword(&mode) {
+                    Some(mode) => state.set_render_mode(mode),
+                    None => state.report(Error::InvalidView {
+                        reason: format!("unknown render mode {}", mode),
+                    }),
+                }
+            }
+
+            // the parameters given from JavaScript are not checked by the sliders
+            if let Some(stretch) = stretch {
+                if stretch.is_valid() {
+                    state.set_stretch(stretch);
+                } else {
+                    state.report(Error::InvalidView {
+                        reason: format!("the parameter of the {} stretch must be finite and positive", stretch.name().to_lowercase()),
+                    });
+                }
+            }
+
+            if let Some((name, text)) = palette {
+                match colormap::Colormap::from_file(&name, &text) {
+                    Ok(palette) => state.add_colormap(palette),
+                    Err(error) => state.report(error),
+                }
+            }
+
+            if let Some((name, reversed)) = colormap {
+                match state.colormaps.iter().position(|c| c.name == name) {
+                    Some(index) => state.set_colormap(index, reversed),
+                    None => state.report(Error::InvalidColormap {
+                        reason: format!("unknown colormap {}, load its palette first", name),
+                    }),
+                }
+            }
+
+            if let Some(text) = view {
+                match view::View::from_text(&text, &state.current_view()) {
+                    Ok(view) => state.apply_view(view),
+                    Err(error) => state.report(error),
+                }
+            }
+
             if let Some(perspective) = perspective {
                 state.queue.write_buffer(
                     &state.buffers["perspective"],
@@ -1006,7 +2505,12 @@ impl ApplicationHandler for App {
                 state.queue.write_buffer(
                     &state.buffers["cuts"],
                     0,
-                    bytemuck::bytes_of(&[cuts.start, cuts.end, 0.0_f32, 0.0_f32]),
+                    bytemuck::bytes_of(&[
+                        state.encoding.to_gpu(cuts.start as f64),
+                        state.encoding.to_gpu(cuts.end as f64),
+                        0.0_f32,
+                        0.0_f32,
+                    ]),
                 );
 
                 state.m1 = cuts.start;
@@ -1014,14 +2518,7 @@ impl ApplicationHandler for App {
             }
 
             if let Some(data) = data {
-                let reader = Cursor::new(data.as_slice());
-                match state.visualize_cube(reader) {
-                    Ok(()) => {}
-                    Err(error) => web_sys::window()
-                        .unwrap()
-                        .alert_with_message(error)
-                        .unwrap(),
-                }
+                state.visualize_cube(data);
             }
         }
 
@@ -1060,10 +2557,7 @@ impl ApplicationHandler for App {
                 let file = File::open(&CUBES_PATH[self.i]).unwrap();
                 let mmap = unsafe { Mmap::map(&file).unwrap() };
 
-                let reader = Cursor::new(mmap);
-
-                let _ = state
-                    .visualize_cube(reader);
+                state.visualize_cube(mmap);
             }
             WindowEvent::KeyboardInput {
                 event:
@@ -1194,6 +2688,9 @@ impl ApplicationHandler for App {
                 let window = self.window.as_ref().unwrap();
                 let _ = state.render(window);
 
+                #[cfg(target_arch = "wasm32")]
+                VIEW.with(|view| *view.borrow_mut() = state.current_view().to_text());
+
                 window.request_redraw();
             }
             // Moving
@@ -1203,6 +2700,7 @@ impl ApplicationHandler for App {
                 ..
             } => {
                 self.panning = true;
+                state.moving = true;
                 self.start_cursor_pos = self.cursor_pos;
                 state.dtheta = 0.0;
                 state.ddelta = 0.0;
@@ -1213,6 +2711,7 @@ impl ApplicationHandler for App {
                 ..
             } => {
                 self.panning = false;
+                state.moving = false;
                 state.theta += state.dtheta;
                 state.delta += state.ddelta;
 
@@ -1279,8 +2778,8 @@ impl ApplicationHandler for App {
                         &state.buffers["cuts"],
                         0,
                         bytemuck::bytes_of(&[
-                            state.m1,
-                            state.m2,
+                            state.encoding.to_gpu(state.m1 as f64),
+                            state.encoding.to_gpu(state.m2 as f64),
                             0.0,
                             0.0,
                         ]),
@@ -1342,218 +2841,720 @@ fn create_window(event_loop: &ActiveEventLoop) -> Window {
 }
 
 
-struct Cube<'a> {
-    data: &'a [u8],
+/// Options driving how a cube is loaded
+#[derive(Debug, Clone)]
+struct LoadOptions {
+    // center and rescale double precision cubes before their conversion to f32
+    rescale_f64: bool,
+    // index of the HDU to load, the first one storing a cube if None
+    hdu: Option<usize>,
+    // axes of the HDU to render, deduced from the CTYPEs if None
+    axes: Option<axes::AxisMapping>,
+    // bigger cubes are rendered by bricks
+    max_texture_dimension: u32,
+    // algorithm computing the initial cuts
+    cut_strategy: cuts::CutStrategy,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            rescale_f64: true,
+            hdu: None,
+            axes: None,
+            max_texture_dimension: wgpu::Limits::default().max_texture_dimension_3d,
+            cut_strategy: cuts::CutStrategy::default(),
+        }
+    }
+}
+
+struct Cube {
+    // native-endian values of the volume ready to be sent to the GPU along
+    // with the validity mask of the voxels (NaN and BLANK ones are set to decode::INVALID)
+    // physical values are retrieved with `encoding`
+    voxels: bricks::Voxels,
+    // coarser levels of detail of the decoded voxels, built before the upload
+    levels: Vec<(Vec<f32>, Vec<u8>)>,
     dim: (u32, u32, u32),
     mincut: f32,
     maxcut: f32,
-    wcs: fitsrs::WCS
+    // subset of the valid physical values the cuts are computed on
+    samples: Vec<f64>,
+    // statistics of all the valid physical values
+    stats: stats::Statistics,
+    // None for plain arrays, the cube is then shown in pixel coordinates
+    wcs: Option<fitsrs::WCS>,
+    // WCS present in the header but that could not be parsed
+    wcs_error: Option<Error>,
+    // spectral axis along the z axis of the volume
+    spectral: Option<spectral::SpectralAxis>,
+    // restoring beams of the channels of the volume
+    beams: Option<beam::Beams>,
+    // BSCALE/BZERO linear scaling applied to the stored values
+    scale: decode::Scale,
+    // physical unit of the values given by BUNIT
+    bunit: Option<String>,
+    // mapping from the physical values to the ones in `data`
+    encoding: decode::Encoding,
+    // all the HDUs of the file
+    hdus: Vec<HduInfo>,
+    // index of the HDU the cube has been extracted from
+    hdu_idx: usize,
+    // axes of the HDU rendered
+    axes: axes::AxisMapping,
 }
 
-fn parse_fits_data_cube<'a, R>(fits: &'a mut Fits<Cursor<R>>) -> Result<Cube<'a>, &'static str>
-where
-    R: AsRef<[u8]> + std::fmt::Debug + 'a,
-{
-    if let Some(Ok(hdu)) = fits.next() {
-        match hdu {
-            HDU::Primary(hdu) => {
-                let header = hdu.get_header();
-
-                if let (
-                    Some(Value::Integer { value: w, .. }),
-                    Some(Value::Integer { value: h, .. }),
-                    Some(Value::Integer { value: d, .. }),
-                    Some(Value::Integer { value: b, .. })
-                ) = (
-                    header.get("NAXIS1"),
-                    header.get("NAXIS2"),
-                    header.get("NAXIS3"),
-                    header.get("BITPIX"),
-                ) {
-                    let image = fits.get_data(&hdu);
-
-                    let d1 = *w as u32;
-                    let d2 = *h as u32;
-                    let mut d3 = *d as u32;
-
-                    if d3 == 1 {
-                        // parse NAXIS4 instead it there is
-                        if let Some(Value::Integer { value, .. }) = header.get("NAXIS4") {
-                            d3 = *value as u32;
-                        }
-                    }
-
-                    let data = image.raw_bytes();
+/// WCS of an HDU, if any
+///
+/// Plain arrays without WCS keywords, e.g. simulation boxes, are viewed in
+/// pixel coordinates. A WCS that cannot be parsed is returned as an error to
+/// report, the cube being viewed in pixel coordinates too.
+fn optional_wcs<E: std::fmt::Display>(wcs: Result<fitsrs::WCS, E>, header: &ValueMap) -> (Option<fitsrs::WCS>, Option<Error>) {
+    match wcs {
+        Ok(wcs) => (Some(wcs), None),
+        Err(_) if header.get("CTYPE1").is_none() => (None, None),
+        Err(e) => (None, Some(Error::Wcs { reason: e.to_string() })),
+    }
+}
 
-                    let cuts = match b {
-                        -32 => {
-                            let mut floats: Vec<f32> = data
-                                .chunks_exact(4)
-                                .map(|b| f32::from_be_bytes(b.try_into().unwrap()))
-                                .collect();
+fn get_float(header: &ValueMap, key: &str) -> Option<f64> {
+    match header.get(key) {
+        Some(Value::Float { value, .. }) => Some(*value),
+        Some(Value::Integer { value, .. }) => Some(*value as f64),
+        _ => None,
+    }
+}
 
-                            first_and_last_percent_f32(&mut floats, 1.0, 99.0)
-                        }
-                        8 => {
-                            
-                            let mut bytes: Vec<u8> = data.to_vec();
-                            let range = first_and_last_percent(&mut bytes, 1.0, 99.0);
-                            (range.start as f32)..(range.end as f32)
-                        }
-                        16 => {
-                            let mut shorts: Vec<i16> = data
-                                .chunks_exact(2)
-                                .map(|b| i16::from_be_bytes(b.try_into().unwrap()))
-                                .collect();
-
-                            let range = first_and_last_percent(&mut shorts, 1.0, 99.0);
-                            (range.start as f32)..(range.end as f32)
-                        }
-                        32 => {
-                            let mut int32: Vec<i32> = data
-                                .chunks_exact(4)
-                                .map(|b| i32::from_be_bytes(b.try_into().unwrap()))
-                                .collect();
-
-                            let range = first_and_last_percent(&mut int32, 1.0, 99.0);
-                            (range.start as f32)..(range.end as f32)
-                        }
-                        64 => {
-                            let mut int64: Vec<i64> = data
-                                .chunks_exact(8)
-                                .map(|b| i64::from_be_bytes(b.try_into().unwrap()))
-                                .collect();
-
-                            let range = first_and_last_percent(&mut int64, 1.0, 99.0);
-                            (range.start as f32)..(range.end as f32)
-                        },
-                        _ => {
-                            return Err("F32, U8, I16, I32, I64 only supported");
-                        }
-                    };
-
-                    let wcs = hdu.wcs().map_err(|_| "wcs not found")?;
-                    Ok(Cube {
-                        data,
-                        dim: (d1, d2, d3),
-                        mincut: cuts.start,
-                        maxcut: cuts.end,
-                        wcs
-                    })
-                } else {
-                    Err("FITS image extension not found")
-                }
-            }
-            _ => Err("FITS image extension not found"),
+fn get_string(header: &ValueMap, key: &str) -> Option<String> {
+    match header.get(key) {
+        Some(Value::String { value, .. }) if !value.trim().is_empty() => {
+            Some(value.trim().to_owned())
         }
-    } else {
-        Err("Is not a FITS file")
+        _ => None,
     }
 }
 
-use std::fmt::Debug;
-fn read_fits<R: AsRef<[u8]> + Debug>(
-    reader: Cursor<R>,
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-) -> Result<(Texture, f32, f32, (u32, u32, u32), fitsrs::WCS), &'static str> {
-    let mut fits = Fits::from_reader(reader);
-    let Cube {
-        data: raw_bytes,
-        dim,
-        mincut,
-        maxcut,
-        wcs
-    } = parse_fits_data_cube(&mut fits)?;
-
-    Ok((
-        Texture::from_raw_bytes::<f32>(&device, &queue, Some(raw_bytes), dim, 4, "cube")?,
-        mincut,
-        maxcut,
-        dim,
-        wcs
-    ))
+fn get_integer(header: &ValueMap, key: &str) -> Option<i64> {
+    match header.get(key) {
+        Some(Value::Integer { value, .. }) => Some(*value),
+        _ => None,
+    }
+}
+
+/// Description of a HDU listed in the HDU chooser
+#[derive(Debug, Clone)]
+struct HduInfo {
+    index: usize,
+    // PRIMARY, IMAGE, ZIMAGE (tile-compressed image), BINTABLE or TABLE
+    kind: &'static str,
+    extname: Option<String>,
+    naxis: Vec<u64>,
+    // CTYPEi of each axis
+    ctypes: Vec<Option<String>>,
+    bitpix: i64,
+    // first NAXISn keyword announced by NAXIS and missing from the header
+    missing_card: Option<String>,
 }
 
-pub fn first_and_last_percent_f32(
-    slice: &mut [f32],
-    mut first_percent: f32,
-    mut last_percent: f32,
-) -> Range<f32> {
-    if slice.is_empty() {
-        return 0.0..0.0;
+impl HduInfo {
+    fn new(index: usize, kind: &'static str, header: &ValueMap) -> Self {
+        // a tile-compressed image describes the uncompressed one with the Z keywords
+        let prefix = if kind == "ZIMAGE" { "Z" } else { "" };
+
+        let num_axis = get_integer(header, &format!("{}NAXIS", prefix)).unwrap_or(0) as usize;
+        let naxis = (1..=num_axis)
+            .map_while(|i| get_integer(header, &format!("{}NAXIS{}", prefix, i)))
+            .map(|n| n as u64)
+            .collect::<Vec<_>>();
+        let missing_card = if naxis.len() < num_axis {
+            Some(format!("{}NAXIS{}", prefix, naxis.len() + 1))
+        } else {
+            None
+        };
+        let ctypes = (1..=naxis.len())
+            .map(|i| get_string(header, &format!("CTYPE{}", i)))
+            .collect();
+
+        Self {
+            index,
+            kind,
+            extname: get_string(header, "EXTNAME"),
+            naxis,
+            ctypes,
+            bitpix: get_integer(header, &format!("{}BITPIX", prefix)).unwrap_or(0),
+            missing_card,
+        }
     }
 
-    if first_percent > last_percent {
-        std::mem::swap(&mut first_percent, &mut last_percent);
+    /// Tell whether a 3D volume can be extracted from the HDU
+    fn is_cube(&self) -> bool {
+        matches!(self.kind, "PRIMARY" | "IMAGE" | "ZIMAGE")
+            && self.naxis.len() >= 3
+            && self.naxis.iter().all(|n| *n > 0)
+    }
+
+    fn axis_kinds(&self) -> Vec<axes::AxisKind> {
+        self.ctypes
+            .iter()
+            .map(|ctype| axes::AxisKind::from_ctype(ctype.as_deref()))
+            .collect()
+    }
+
+    /// Mapping of the axes to render, the one asked if it applies to the HDU
+    fn axis_mapping(&self, asked: &Option<axes::AxisMapping>) -> axes::AxisMapping {
+        match asked {
+            Some(mapping) if mapping.is_valid(&self.naxis) => mapping.clone(),
+            _ => axes::AxisMapping::new(&self.naxis, &self.axis_kinds()),
+        }
     }
 
-    // Move all NaNs to the end
-    let valid_len = {
-        let mut i = 0;
-        for j in 0..slice.len() {
-            if !slice[j].is_nan() {
-                slice.swap(i, j);
-                i += 1;
+    fn axis_label(&self, axis: usize) -> String {
+        format!(
+            "NAXIS{} {} ({})",
+            axis + 1,
+            self.ctypes[axis].as_deref().unwrap_or(""),
+            self.naxis[axis]
+        )
+    }
+
+    fn label(&self) -> String {
+        let dims = self.naxis
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join("x");
+
+        format!(
+            "#{} {} [{}] {} BITPIX={}",
+            self.index,
+            self.extname.as_deref().unwrap_or(""),
+            self.kind,
+            if dims.is_empty() { "-" } else { &dims },
+            self.bitpix
+        )
+    }
+}
+
+fn parse_fits_data_cube(bytes: &[u8], options: &LoadOptions, progress: &Progress) -> Result<Cube, Error> {
+    progress.start(Stage::Parse, 0);
+    let mut fits = Fits::from_reader(Cursor::new(bytes));
+
+    // enumerate all the HDUs and extract the cube from the one to display
+    let mut hdus = vec![];
+    let mut selected = None;
+    // headers and data units of the binary tables, e.g. the lookup tables of -TAB axes
+    let mut tables = vec![];
+    let mut index = 0;
+    while let Some(hdu) = fits.next() {
+        let hdu = match hdu {
+            Ok(hdu) => hdu,
+            Err(_) if index == 0 => return Err(Error::NotFits),
+            // keep the HDUs that have been parsed so far
+            Err(_) => break,
+        };
+
+        let info = match &hdu {
+            HDU::Primary(hdu) => HduInfo::new(index, "PRIMARY", hdu.get_header()),
+            HDU::XImage(hdu) => HduInfo::new(index, "IMAGE", hdu.get_header()),
+            HDU::XBinaryTable(hdu) => {
+                let header = hdu.get_header();
+                let kind = match header.get("ZIMAGE") {
+                    Some(Value::Logical { value: true, .. }) => "ZIMAGE",
+                    _ => "BINTABLE",
+                };
+                if kind == "BINTABLE" {
+                    let offset = hdu.get_data_unit_byte_offset() as usize;
+                    tables.push((ValueMap::clone(header), offset..(offset + hdu.get_data_unit_byte_size() as usize)));
+                }
+
+                HduInfo::new(index, kind, header)
             }
+            HDU::XASCIITable(hdu) => HduInfo::new(index, "TABLE", hdu.get_header()),
+        };
+
+        let wanted = match options.hdu {
+            Some(idx) => idx == index,
+            // the first HDU storing a cube is picked by default
+            None => info.is_cube(),
+        };
+        if wanted && selected.is_none() {
+            if let Some(keyword) = &info.missing_card {
+                return Err(Error::MissingAxisCard { hdu: index, keyword: keyword.clone() });
+            }
+            if !info.is_cube() {
+                return Err(Error::NotACube { hdu: index, naxis: info.naxis.clone() });
+            }
+
+            let cube = match hdu {
+                HDU::Primary(hdu) | HDU::XImage(hdu) => parse_image_hdu(bytes, &hdu, &info, options, progress)?,
+                HDU::XBinaryTable(hdu) => parse_compressed_hdu(bytes, &mut fits, &hdu, &info, options, progress)?,
+                HDU::XASCIITable(_) => return Err(Error::NotACube { hdu: index, naxis: info.naxis.clone() }),
+            };
+            selected = Some(cube);
         }
-        i
+
+        hdus.push(info);
+        index += 1;
+    }
+
+    let mut cube = match (selected, options.hdu) {
+        (Some(cube), _) => cube,
+        // the HDU asked is beyond the last one parsed
+        (None, Some(hdu)) => return Err(Error::NotACube { hdu, naxis: vec![] }),
+        (None, None) => return Err(Error::NoCube),
     };
+    cube.hdus = hdus;
+
+    // the tables may follow the cube in the file
+    let tables = tables
+        .iter()
+        .map(|(header, data)| table::Table::new(header, bytes.get(data.clone()).unwrap_or(&[])))
+        .collect::<Vec<_>>();
+    if let Some(axis) = cube.spectral.as_mut() {
+        if !axis.read_table(&tables) {
+            cube.spectral = None;
+        }
+    }
 
-    if valid_len == 0 {
-        return f32::NAN..f32::NAN;
+    // the per-channel beams of a BEAMS table take precedence over BMAJ/BMIN/BPA
+    let kinds = cube.hdus[cube.hdu_idx].axis_kinds();
+    let index_along = |kind| kinds.iter().position(|k| *k == kind).map(|axis| (axis, cube.axes.indices[axis]));
+    let channel = match index_along(axes::AxisKind::Spectral) {
+        Some((axis, _)) if axis == cube.axes.volume[2] => None,
+        // the beam of the first channel is taken when the spectral axis is along x or y
+        Some((_, index)) => Some(index),
+        None => Some(0),
+    };
+    let polarization = index_along(axes::AxisKind::Stokes).map(|(_, index)| index).unwrap_or(0);
+    if let Some(beams) = beam::Beams::from_tables(&tables, channel, polarization) {
+        cube.beams = Some(beams);
     }
 
-    let valid = &mut slice[..valid_len];
+    Ok(cube)
+}
 
-    let i1 = (first_percent.clamp(0.0, 100.0) as usize * valid_len) / 100;
-    let i2 = (last_percent.clamp(0.0, 100.0) as usize * valid_len) / 100;
+fn parse_image_hdu(
+    bytes: &[u8],
+    hdu: &fitsrs::fits::HDU<Image>,
+    info: &HduInfo,
+    options: &LoadOptions,
+    progress: &Progress,
+) -> Result<Cube, Error> {
+    let header = hdu.get_header();
+    let b = info.bitpix;
+    let axes = info.axis_mapping(&options.axes);
+    let (d1, d2, d3) = axes.dim(&info.naxis);
+
+    let offset = hdu.get_data_unit_byte_offset() as usize;
+    let num_bytes_per_pixel = decode::num_bytes_per_pixel(b)?;
+    let num_bytes = info.naxis.iter().product::<u64>() as usize * num_bytes_per_pixel;
+
+    let end = offset + hdu.get_data_unit_byte_size() as usize;
+    let raw_bytes = bytes.get(offset..end).filter(|raw| raw.len() >= num_bytes).ok_or(Error::TruncatedData {
+        hdu: info.index,
+        expected: num_bytes,
+        available: bytes.len().min(end).saturating_sub(offset),
+    })?;
+
+    let scale = decode::Scale {
+        bscale: get_float(header, "BSCALE").unwrap_or(1.0),
+        bzero: get_float(header, "BZERO").unwrap_or(0.0),
+    };
+    let blank = get_integer(header, "BLANK");
+
+    let mut volume = bricks::RawVolume {
+        offset,
+        naxis: info.naxis.clone(),
+        axes: axes.clone(),
+        bitpix: b,
+        scale,
+        blank,
+        encoding: decode::Encoding::default(),
+    };
+    // cubes too big for the GPU are decoded by bricks when rendered,
+    // the cut strategies working on samples use a subset of their voxels
+    let bricked = bricks::needs_bricking((d1, d2, d3), options.max_texture_dimension);
+    let volume_num_bytes = (d1 as u64) * (d2 as u64) * (d3 as u64) * (num_bytes_per_pixel as u64);
+    let raw_bytes = if bricked {
+        Cow::Owned(volume.sample_bytes(bytes, CUTS_MAX_SAMPLES))
+    } else {
+        // only keep the 3D hyperplane to render
+        axes.extract_bytes(&info.naxis, &raw_bytes[..num_bytes], num_bytes_per_pixel)
+    };
 
-    let min_val = {
-        let (_, min_val, _) =
-            valid.select_nth_unstable_by(i1, |a, b| {
-                a.total_cmp(&b)
-            });
-        *min_val
+    // double precision values are kept until the cuts are known
+    progress.start(Stage::Parse, raw_bytes.len() as u64);
+    // the statistics are accumulated while decoding
+    let (values, data, mask, stats) = if b == -64 {
+        let (values, mask, stats) = decode::decode_f64(&raw_bytes, &scale, progress);
+        (values, vec![], mask, stats)
+    } else {
+        let (data, mask, stats) = decode::decode(&raw_bytes, b, &scale, blank, progress)?;
+        (vec![], data, mask, stats)
     };
-    let max_val = {
-        let (_, max_val, _) =
-            valid.select_nth_unstable_by(i2, |a, b| {
-                a.total_cmp(&b)
-            });
-        *max_val
+    progress.check()?;
+
+    // the ones of bricked cubes are streamed from the file as only samples are decoded
+    let stats = if bricked {
+        progress.start(Stage::Stats, volume_num_bytes);
+        let stats = volume.statistics(bytes, progress);
+        progress.check()?;
+        stats
+    } else {
+        stats
     };
 
-    min_val..max_val
+    let (data, samples, cuts, encoding) = if b == -64 {
+        // cuts are computed in double precision before downconverting the values
+        let samples = cut_samples(&values);
+        let cuts = options.cut_strategy.compute(&stats, &samples);
+
+        let encoding = if options.rescale_f64 {
+            decode::Encoding::from_range(cuts.start, cuts.end)
+        } else {
+            decode::Encoding::default()
+        };
+
+        (decode::encode_f32(&values, &encoding), samples, cuts, encoding)
+    } else {
+        let samples = cut_samples(&data);
+        let cuts = options.cut_strategy.compute(&stats, &samples);
+        (data, samples, cuts, decode::Encoding::default())
+    };
+
+    let voxels = if bricked {
+        volume.encoding = encoding;
+        bricks::Voxels::Raw(volume)
+    } else {
+        bricks::Voxels::Decoded { data, mask }
+    };
+
+    let (wcs, wcs_error) = optional_wcs(hdu.wcs(), header);
+    let spectral = spectral::SpectralAxis::from_header(header, axes.volume[2]);
+    Ok(Cube {
+        voxels,
+        levels: vec![],
+        dim: (d1, d2, d3),
+        mincut: cuts.start as f32,
+        maxcut: cuts.end as f32,
+        samples,
+        stats,
+        wcs,
+        wcs_error,
+        spectral,
+        beams: beam::Beam::from_header(header).map(beam::Beams::Common),
+        scale,
+        bunit: get_string(header, "BUNIT"),
+        encoding,
+        hdus: vec![],
+        hdu_idx: info.index,
+        axes,
+    })
 }
 
-pub fn first_and_last_percent<T>(
-    slice: &mut [T],
-    mut first_percent: f32,
-    mut last_percent: f32,
-) -> Range<T>
-where
-    T: std::cmp::Ord + cgmath::Zero + Copy
-{
-    if slice.is_empty() {
-        return T::zero()..T::zero();
+/// Decompress a tile-compressed image stored in a binary table
+///
+/// The tiles are decompressed by fitsrs that also handles the ZSCALE/ZZERO
+/// quantization of floating point images, except the HCOMPRESS_1 ones that are
+/// read from the table in `bytes`. The BSCALE/BZERO and BLANK keywords of the
+/// original image are then applied the same way as for an image HDU.
+fn parse_compressed_hdu(
+    bytes: &[u8],
+    fits: &mut Fits<Cursor<&[u8]>>,
+    hdu: &fitsrs::fits::HDU<BinTable>,
+    info: &HduInfo,
+    options: &LoadOptions,
+    progress: &Progress,
+) -> Result<Cube, Error> {
+    use fitsrs::hdu::data::bintable::data::BinaryTableData;
+    use fitsrs::hdu::data::bintable::tile_compressed::pixels::Pixels;
+
+    let header = hdu.get_header();
+    let axes = info.axis_mapping(&options.axes);
+    let (d1, d2, d3) = axes.dim(&info.naxis);
+    let num_pixels = info.naxis.iter().product::<u64>() as usize;
+
+    let scale = decode::Scale {
+        bscale: get_float(header, "BSCALE").unwrap_or(1.0),
+        bzero: get_float(header, "BZERO").unwrap_or(0.0),
+    };
+    let blank = get_integer(header, "ZBLANK").or_else(|| get_integer(header, "BLANK"));
+
+    // the tiles are decompressed sequentially, the progress is given by the pixels yielded
+    // and the statistics of the hyperplane displayed are accumulated on the way
+    progress.start(Stage::Parse, num_pixels as u64);
+    let in_volume = axes.in_volume(&info.naxis);
+    let (data, mask, stats) = match fits.get_data(hdu) {
+        BinaryTableData::TileCompressed(Pixels::U8(pixels)) => decode::decode_values(progress.track(pixels.take(num_pixels)), &scale, blank, in_volume),
+        BinaryTableData::TileCompressed(Pixels::I16(pixels)) => decode::decode_values(progress.track(pixels.take(num_pixels)), &scale, blank, in_volume),
+        BinaryTableData::TileCompressed(Pixels::I32(pixels)) => decode::decode_values(progress.track(pixels.take(num_pixels)), &scale, blank, in_volume),
+        BinaryTableData::TileCompressed(Pixels::F32(pixels)) => decode::decode_values(progress.track(pixels.take(num_pixels)), &scale, blank, in_volume),
+        // double precision images are unquantized in f32 by the decompressor
+        BinaryTableData::TileCompressed(Pixels::F64(pixels)) => decode::decode_values(progress.track(pixels.take(num_pixels)), &scale, blank, in_volume),
+        // fitsrs only decompresses GZIP and RICE tiles, HCOMPRESS_1 and PLIO_1 ones end up here
+        BinaryTableData::Table(_) if get_string(header, "ZCMPTYPE").as_deref() == Some("HCOMPRESS_1") => {
+            let offset = hdu.get_data_unit_byte_offset() as usize;
+            let size = hdu.get_data_unit_byte_size() as usize;
+            let data = bytes.get(offset..(offset + size)).ok_or(Error::TruncatedData {
+                hdu: info.index,
+                expected: size,
+                available: bytes.len().saturating_sub(offset),
+            })?;
+
+            match hcompress::decompress_image(&table::Table::new(header, data), header, info.index, progress)? {
+                hcompress::Pixels::Integer(pixels) => decode::decode_values(pixels.into_iter(), &scale, blank, in_volume),
+                // the undefined pixels are already NaN
+                hcompress::Pixels::Float(pixels) => decode::decode_values(pixels.into_iter(), &scale, None, in_volume),
+            }
+        }
+        BinaryTableData::Table(_) => {
+            return Err(Error::UnsupportedCompression {
+                zcmptype: get_string(header, "ZCMPTYPE"),
+            })
+        }
+    };
+    // the pixels stop being yielded once the loading is cancelled
+    progress.check()?;
+
+    if data.len() < num_pixels {
+        let num_bytes_per_pixel = decode::num_bytes_per_pixel(info.bitpix)?;
+        return Err(Error::TruncatedData {
+            hdu: info.index,
+            expected: num_pixels * num_bytes_per_pixel,
+            available: data.len() * num_bytes_per_pixel,
+        });
     }
 
-    if first_percent > last_percent {
-        std::mem::swap(&mut first_percent, &mut last_percent);
+    // only keep the 3D hyperplane to render
+    let data = axes.extract(&info.naxis, &data).into_owned();
+    let mask = axes.extract(&info.naxis, &mask).into_owned();
+
+    let samples = cut_samples(&data);
+    let cuts = options.cut_strategy.compute(&stats, &samples);
+
+    let (wcs, wcs_error) = optional_wcs(hdu.wcs(), header);
+    let spectral = spectral::SpectralAxis::from_header(header, axes.volume[2]);
+    Ok(Cube {
+        voxels: bricks::Voxels::Decoded { data, mask },
+        levels: vec![],
+        dim: (d1, d2, d3),
+        mincut: cuts.start as f32,
+        maxcut: cuts.end as f32,
+        samples,
+        stats,
+        wcs,
+        wcs_error,
+        spectral,
+        beams: beam::Beam::from_header(header).map(beam::Beams::Common),
+        scale,
+        bunit: get_string(header, "BUNIT"),
+        encoding: decode::Encoding::default(),
+        hdus: vec![],
+        hdu_idx: info.index,
+        axes,
+    })
+}
+
+/// Number of columns of the histogram shown in the options panel
+const HISTOGRAM_NUM_COLUMNS: usize = 128;
+
+/// Maximum number of voxels the sample-based cuts are estimated on
+const CUTS_MAX_SAMPLES: usize = 1 << 20;
+
+/// Valid physical values the cuts are computed on
+///
+/// The values are regularly subsampled so that the cuts can be computed
+/// again with another strategy without reading the file.
+fn cut_samples<T: Copy + Into<f64>>(values: &[T]) -> Vec<f64> {
+    let stride = (values.len() / CUTS_MAX_SAMPLES).max(1);
+
+    values
+        .iter()
+        .step_by(stride)
+        .map(|v| (*v).into())
+        .filter(|v: &f64| v.is_finite())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{bintable, fits_file, BLOCK_SIZE};
+
+    /// File with a 2x2x2 BITPIX = -32 primary cube and a 2x2x2 BITPIX = 16 image extension
+    fn two_cubes() -> Vec<u8> {
+        let primary: &[(&str, &str)] = &[
+            ("SIMPLE", "T"),
+            ("BITPIX", "-32"),
+            ("NAXIS", "3"),
+            ("NAXIS1", "2"),
+            ("NAXIS2", "2"),
+            ("NAXIS3", "2"),
+        ];
+        let image: &[(&str, &str)] = &[
+            ("XTENSION", "'IMAGE   '"),
+            ("BITPIX", "16"),
+            ("NAXIS", "3"),
+            ("NAXIS1", "2"),
+            ("NAXIS2", "2"),
+            ("NAXIS3", "2"),
+            ("PCOUNT", "0"),
+            ("GCOUNT", "1"),
+            ("EXTNAME", "'SECOND  '"),
+        ];
+
+        fits_file(&[
+            (primary, (0..8).flat_map(|i| (i as f32).to_be_bytes()).collect()),
+            (image, (0..8).flat_map(|i| (10 * i as i16).to_be_bytes()).collect()),
+        ])
+    }
+
+    #[test]
+    fn list_the_hdus_and_pick_one() {
+        let file = two_cubes();
+        let progress = Progress::default();
+
+        // the first cube is picked by default
+        let cube = parse_fits_data_cube(&file, &LoadOptions::default(), &progress).unwrap();
+        assert_eq!(cube.hdu_idx, 0);
+        assert_eq!(cube.dim, (2, 2, 2));
+        assert_eq!(
+            cube.hdus.iter().map(|hdu| hdu.label()).collect::<Vec<_>>(),
+            ["#0  [PRIMARY] 2x2x2 BITPIX=-32", "#1 SECOND [IMAGE] 2x2x2 BITPIX=16"]
+        );
+
+        let options = LoadOptions { hdu: Some(1), ..LoadOptions::default() };
+        let cube = parse_fits_data_cube(&file, &options, &progress).unwrap();
+        assert_eq!(cube.hdu_idx, 1);
+        assert_eq!((cube.stats.min, cube.stats.max), (0.0, 70.0));
+
+        let options = LoadOptions { hdu: Some(2), ..LoadOptions::default() };
+        assert!(matches!(
+            parse_fits_data_cube(&file, &options, &progress),
+            Err(Error::NotACube { hdu: 2, .. })
+        ));
+    }
+
+    /// File with an empty primary HDU followed by a 2x2x2 BITPIX = 32 tile-compressed image
+    fn compressed_cube(zcmptype: &str) -> Vec<u8> {
+        use std::io::Write;
+
+        let pixels = (-4..4).flat_map(|i: i32| i.to_be_bytes()).collect::<Vec<_>>();
+        let mut gz = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gz.write_all(&pixels).unwrap();
+        let tile = gz.finish().unwrap();
+
+        let primary: &[(&str, &str)] = &[("SIMPLE", "T"), ("BITPIX", "8"), ("NAXIS", "0"), ("EXTEND", "T")];
+        let heap_size = tile.len().to_string();
+        let tform = format!("'1PB({})'", tile.len());
+        let zcmptype = format!("'{}'", zcmptype);
+        let table = bintable(
+            &[("'COMPRESSED_DATA'", &tform)],
+            1,
+            &[
+                ("PCOUNT", &heap_size),
+                ("ZIMAGE", "T"),
+                ("ZCMPTYPE", &zcmptype),
+                ("ZBITPIX", "32"),
+                ("ZNAXIS", "3"),
+                ("ZNAXIS1", "2"),
+                ("ZNAXIS2", "2"),
+                ("ZNAXIS3", "2"),
+                ("ZTILE1", "2"),
+                ("ZTILE2", "2"),
+                ("ZTILE3", "2"),
+            ],
+        );
+        let table = table.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect::<Vec<_>>();
+        // the descriptor of the tile, its length then its offset in the heap
+        let mut data = [tile.len() as i32, 0].iter().flat_map(|v| v.to_be_bytes()).collect::<Vec<_>>();
+        data.extend_from_slice(&tile);
+
+        fits_file(&[(primary, vec![]), (&table, data)])
+    }
+
+    #[test]
+    fn decompress_tile_compressed_images() {
+        let progress = Progress::default();
+
+        let cube = parse_fits_data_cube(&compressed_cube("GZIP_1"), &LoadOptions::default(), &progress).unwrap();
+        assert_eq!(cube.hdus[1].kind, "ZIMAGE");
+        assert_eq!(cube.dim, (2, 2, 2));
+        match cube.voxels {
+            bricks::Voxels::Decoded { data, .. } => assert_eq!(data, [-4.0, -3.0, -2.0, -1.0, 0.0, 1.0, 2.0, 3.0]),
+            _ => panic!("a small compressed cube is decoded at once"),
+        }
+
+        // the HCOMPRESS tiles written by fpack are decompressed by the viewer
+        let file = include_bytes!("../testdata/hcompress_int16.fits.fz");
+        let cube = parse_fits_data_cube(file, &LoadOptions::default(), &progress).unwrap();
+        assert_eq!(cube.dim, (15, 10, 2));
+        match cube.voxels {
+            bricks::Voxels::Decoded { data, .. } => assert_eq!(data[..3], [-1000.0, -963.0, -926.0]),
+            _ => panic!("a small compressed cube is decoded at once"),
+        }
+
+        // PLIO tiles are not decompressed
+        assert!(matches!(
+            parse_fits_data_cube(&compressed_cube("PLIO_1"), &LoadOptions::default(), &progress),
+            Err(Error::UnsupportedCompression { zcmptype: Some(zcmptype) }) if zcmptype == "PLIO_1"
+        ));
     }
 
-   
-    let n = slice.len();
-    let i1 = (first_percent.clamp(0.0, 100.0) as usize * n) / 100;
-    let i2 = (last_percent.clamp(0.0, 100.0) as usize * n) / 100;
+    #[test]
+    fn report_invalid_files() {
+        let progress = Progress::default();
+        let options = LoadOptions::default();
+        let parse = |file: &[u8]| parse_fits_data_cube(file, &options, &progress).err();
+
+        assert_eq!(parse(b"not a FITS file"), Some(Error::NotFits));
 
-    let (_, min_val, _) =
-        slice.select_nth_unstable(i1);
-    let min_val = *min_val;
-    let (_, max_val, _) =
-        slice.select_nth_unstable(i2);
-    let max_val = *max_val;
+        let image: &[(&str, &str)] = &[("SIMPLE", "T"), ("BITPIX", "16"), ("NAXIS", "2"), ("NAXIS1", "2"), ("NAXIS2", "2")];
+        assert_eq!(parse(&fits_file(&[(image, vec![0; 8])])), Some(Error::NoCube));
 
-    min_val..max_val
+        // the data unit of the primary cube is cut in the middle
+        let mut file = two_cubes();
+        file.truncate(BLOCK_SIZE + 16);
+        assert_eq!(
+            parse(&file),
+            Some(Error::TruncatedData { hdu: 0, expected: 32, available: 16 })
+        );
+        assert_eq!(
+            Error::TruncatedData { hdu: 0, expected: 32, available: 16 }.to_string(),
+            "Data unit of HDU #0 is smaller than expected (16 bytes instead of 32)"
+        );
+    }
+
+    #[test]
+    fn upload_the_channels_by_batches() {
+        // 4 bytes per voxel, 4096 channels of 4096 voxels per batch
+        assert_eq!(upload_batch((64, 64, 10000), 0), 0..4096);
+        assert_eq!(upload_batch((64, 64, 10000), 8192), 8192..10000);
+        // channels bigger than a batch are uploaded one by one
+        assert_eq!(upload_batch((8192, 8192, 3), 1), 1..2);
+    }
+
+    #[test]
+    fn view_plain_arrays_in_pixel_coordinates() {
+        let progress = Progress::default();
+
+        let cube = parse_fits_data_cube(&two_cubes(), &LoadOptions::default(), &progress).unwrap();
+        assert!(cube.wcs.is_none());
+        assert_eq!(cube.wcs_error, None);
+
+        // an unknown projection is reported while the cube is still shown
+        let header: &[(&str, &str)] = &[
+            ("SIMPLE", "T"),
+            ("BITPIX", "8"),
+            ("NAXIS", "3"),
+            ("NAXIS1", "2"),
+            ("NAXIS2", "2"),
+            ("NAXIS3", "2"),
+            ("CTYPE1", "'RA---XYZ'"),
+            ("CTYPE2", "'DEC--XYZ'"),
+        ];
+        let cube = parse_fits_data_cube(&fits_file(&[(header, vec![1; 8])]), &LoadOptions::default(), &progress).unwrap();
+        assert!(cube.wcs.is_none());
+        assert!(matches!(cube.wcs_error, Some(Error::Wcs { .. })));
+    }
 }
@@ -0,0 +1,136 @@
+use rayon::prelude::*;
+
+/// Maximum number of levels of the pyramid, the full resolution one included
+const MAX_LEVELS: u32 = 5;
+/// Coarser levels are added as long as the largest axis is bigger than this
+const MIN_SIZE: u32 = 32;
+
+/// Number of levels of the pyramid of a cube
+pub fn num_levels(dim: (u32, u32, u32)) -> u32 {
+    let mut levels = 1;
+    let mut size = dim.0.max(dim.1).max(dim.2);
+
+    while levels < MAX_LEVELS && size > MIN_SIZE {
+        size /= 2;
+        levels += 1;
+    }
+
+    levels
+}
+
+/// Dimensions of a level, following the size of the GPU mip levels
+pub fn level_dim(dim: (u32, u32, u32), level: u32) -> (u32, u32, u32) {
+    (
+        (dim.0 >> level).max(1),
+        (dim.1 >> level).max(1),
+        (dim.2 >> level).max(1),
+    )
+}
+
+/// Range of the voxels of the finer level pooled in a voxel of the coarser one
+#[inline]
+fn pooled(i: u32, size: u32, coarse_size: u32) -> std::ops::Range<u32> {
+    // the last voxel of an odd axis is pooled with the two preceding ones
+    let end = if i == coarse_size - 1 { size } else { 2 * i + 2 };
+    (2 * i)..end
+}
+
+/// Halve the resolution of a level
+///
+/// Each coarse voxel keeps the maximum of the valid voxels it covers so that
+/// bright features survive in the maximum intensity projection. It is
+/// invalid if it covers only invalid voxels.
+fn downsample(data: &[f32], mask: &[u8], dim: (u32, u32, u32)) -> (Vec<f32>, Vec<u8>) {
+    let coarse = level_dim(dim, 1);
+
+    // the slices of the coarse level are computed in parallel
+    let slices = (0..coarse.2).into_par_iter().map(|k| {
+        let num_voxels = (coarse.0 as usize) * (coarse.1 as usize);
+        let mut values = Vec::with_capacity(num_voxels);
+        let mut valid = Vec::with_capacity(num_voxels);
+
+        for j in 0..coarse.1 {
+            for i in 0..coarse.0 {
+                let mut v = f32::NEG_INFINITY;
+                let mut m = crate::decode::INVALID;
+
+                for z in pooled(k, dim.2, coarse.2) {
+                    for y in pooled(j, dim.1, coarse.1) {
+                        for x in pooled(i, dim.0, coarse.0) {
+                            let idx = (x + dim.0 * (y + dim.1 * z)) as usize;
+
+                            if mask[idx] != crate::decode::INVALID {
+                                v = v.max(data[idx]);
+                                m = crate::decode::VALID;
+                            }
+                        }
+                    }
+                }
+
+                values.push(if m == crate::decode::VALID { v } else { f32::NAN });
+                valid.push(m);
+            }
+        }
+
+        (values, valid)
+    });
+
+    let slices = slices.collect::<Vec<_>>();
+    let values = slices.iter().flat_map(|(v, _)| v).copied().collect();
+    let valid = slices.iter().flat_map(|(_, m)| m).copied().collect();
+
+    (values, valid)
+}
+
+/// Build the coarse levels of the pyramid of a cube
+///
+/// The full resolution level is not part of the returned ones.
+pub fn build(data: &[f32], mask: &[u8], dim: (u32, u32, u32)) -> Vec<(Vec<f32>, Vec<u8>)> {
+    let mut levels: Vec<(Vec<f32>, Vec<u8>)> = vec![];
+
+    for level in 1..num_levels(dim) {
+        let finer_dim = level_dim(dim, level - 1);
+        let coarse = match levels.last() {
+            Some((values, valid)) => downsample(values, valid, finer_dim),
+            None => downsample(data, mask, finer_dim),
+        };
+
+        levels.push(coarse);
+    }
+
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{INVALID, VALID};
+
+    #[test]
+    fn count_the_levels() {
+        assert_eq!(num_levels((32, 32, 32)), 1);
+        assert_eq!(num_levels((33, 10, 1)), 2);
+        assert_eq!(num_levels((4096, 4096, 4096)), MAX_LEVELS);
+        assert_eq!(level_dim((5, 64, 1), 2), (1, 16, 1));
+    }
+
+    #[test]
+    fn max_pool_the_valid_voxels() {
+        // 3x2x1 so that the last voxel along x is pooled with the two preceding ones
+        let data = [1.0, 5.0, 9.0, 2.0, f32::NAN, 3.0];
+        let mask = [VALID, VALID, INVALID, VALID, INVALID, VALID];
+        let (values, valid) = downsample(&data, &mask, (3, 2, 1));
+        assert_eq!(values, [5.0]);
+        assert_eq!(valid, [VALID]);
+
+        let (values, valid) = downsample(&[f32::NAN; 8], &[INVALID; 8], (2, 2, 2));
+        assert!(values[0].is_nan());
+        assert_eq!(valid, [INVALID]);
+
+        // a 64 voxels wide line has a single coarse level of 32 voxels
+        let data = (0..64).map(|i| i as f32).collect::<Vec<_>>();
+        let levels = build(&data, &[VALID; 64], (64, 1, 1));
+        assert_eq!(levels.len(), 1);
+        assert_eq!(levels[0].0, (0..32).map(|i| (2 * i + 1) as f32).collect::<Vec<_>>());
+    }
+}
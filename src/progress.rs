@@ -0,0 +1,153 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+
+use crate::error::Error;
+
+/// Step of the loading of a cube
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    Idle,
+    /// Reading the file and decompressing it if it is gzipped
+    Read,
+    /// Parsing the headers and decoding the pixels
+    Parse,
+    /// Computing the statistics and the cuts
+    Stats,
+    /// Building the levels of detail and uploading them to the GPU
+    Upload,
+}
+
+impl Stage {
+    const ALL: [Stage; 5] = [Stage::Idle, Stage::Read, Stage::Parse, Stage::Stats, Stage::Upload];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Idle => "Idle",
+            Stage::Read => "Reading file",
+            Stage::Parse => "Parsing",
+            Stage::Stats => "Computing statistics",
+            Stage::Upload => "Uploading to GPU",
+        }
+    }
+}
+
+/// Progress of the loading of a cube
+///
+/// Shared between the threads loading the cube, that advance it, and the
+/// UI showing it and possibly cancelling the loading.
+#[derive(Debug, Default)]
+pub struct Progress {
+    stage: AtomicU8,
+    done: AtomicU64,
+    total: AtomicU64,
+    cancelled: AtomicBool,
+}
+
+/// Number of items an iterator yields between two updates of the progress
+const TRACK_PERIOD: u64 = 1 << 16;
+
+impl Progress {
+    /// Start a new stage of `total` units of work
+    pub fn start(&self, stage: Stage, total: u64) {
+        self.stage.store(stage as u8, Ordering::Relaxed);
+        self.done.store(0, Ordering::Relaxed);
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    pub fn stage(&self) -> Stage {
+        Stage::ALL[self.stage.load(Ordering::Relaxed) as usize]
+    }
+
+    pub fn advance(&self, amount: u64) {
+        self.done.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    /// Set the units of work of the stage done so far, as reported by the browser
+    #[cfg(target_arch = "wasm32")]
+    pub fn set_done(&self, done: u64) {
+        self.done.store(done, Ordering::Relaxed);
+    }
+
+    /// Fraction of the stage done, between 0 and 1
+    pub fn fraction(&self) -> f32 {
+        let total = self.total.load(Ordering::Relaxed);
+        if total == 0 {
+            0.0
+        } else {
+            (self.done.load(Ordering::Relaxed) as f64 / total as f64).min(1.0) as f32
+        }
+    }
+
+    /// Ask the threads loading the cube to stop as soon as possible
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fails if the loading has been cancelled
+    ///
+    /// Called between the stages, the loops of a stage stop processing their
+    /// items once the loading is cancelled.
+    pub fn check(&self) -> Result<(), Error> {
+        if self.is_cancelled() {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Advance the progress by one for each item yielded by an iterator
+    ///
+    /// The iterator stops once the loading is cancelled.
+    pub fn track<'a, I: Iterator + 'a>(&'a self, iter: I) -> impl Iterator<Item = I::Item> + 'a {
+        let mut pending = 0;
+        iter.take_while(move |_| !self.is_cancelled()).inspect(move |_| {
+            pending += 1;
+            if pending == TRACK_PERIOD {
+                self.advance(pending);
+                pending = 0;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follow_the_stages() {
+        let progress = Progress::default();
+        assert_eq!(progress.stage(), Stage::Idle);
+
+        progress.start(Stage::Parse, 4 * TRACK_PERIOD);
+        assert_eq!(progress.track(0..3 * TRACK_PERIOD + 1).count() as u64, 3 * TRACK_PERIOD + 1);
+        // the items are reported by periods
+        assert_eq!(progress.fraction(), 0.75);
+
+        progress.advance(8 * TRACK_PERIOD);
+        assert_eq!(progress.fraction(), 1.0);
+
+        progress.start(Stage::Stats, 10);
+        assert_eq!((progress.stage(), progress.fraction()), (Stage::Stats, 0.0));
+    }
+
+    #[test]
+    fn stop_once_cancelled() {
+        let progress = Progress::default();
+        assert_eq!(progress.check(), Ok(()));
+
+        let yielded = progress
+            .track(0..10)
+            .inspect(|i| {
+                if *i == 4 {
+                    progress.cancel();
+                }
+            })
+            .count();
+        assert_eq!(yielded, 5);
+        assert_eq!(progress.check(), Err(Error::Cancelled));
+    }
+}
@@ -45,6 +45,24 @@ uniform Slices {
     vec2 sz;
     vec2 sw;
 };
+layout(set = 0, binding = 12) uniform texture3D t_mask;
+layout(set = 0, binding = 13)
+uniform BlankColor {
+    vec4 blank_color;
+};
+// slot in the brick atlas (rgb) and residency (a) of each brick
+layout(set = 0, binding = 14) uniform texture3D t_bricks;
+// x: side of a brick in voxels, 0 if the cube is not bricked
+// yzw: size of the brick atlas in voxels
+layout(set = 0, binding = 15)
+uniform Bricking {
+    vec4 bricking;
+};
+// x: mip level of t_map and t_mask sampled
+layout(set = 0, binding = 16)
+uniform Lod {
+    vec4 lod;
+};
 
 vec3 lonlat2xyz(float lon, float lat) {
     float lat_s = sin(lat);
@@ -133,19 +151,6 @@ vec4 colormap(float x) {
     return vec4(r, g, b, 1.0);
 }
 
-float to_l_endian(float x) {
-    uint y = floatBitsToUint(x);
-
-    uint a = y & 0xff;
-    uint b = (y >> 8) & 0xff;
-    uint c = (y >> 16) & 0xff;
-    uint d = y >> 24;
-
-    uint w = (a << 24) | (b << 16) | (c << 8) | d;
-
-    return uintBitsToFloat(w);
-}
-
 // Parameters:
 //   x - input intensity (usually normalized to [0,1])
 //   scale - scaling factor to control the stretch strength
@@ -164,19 +169,49 @@ bool is_finite_f32(float x) {
 const float fov = 0.523333;
 const float camera_near = 1.0;
 
-float probe_cube(vec3 p) {
-    float v = to_l_endian(texture(sampler3D(t_map, s_map), p).r);
-    // max suppress the NaNs, if v is NaN then we add 0.0
-    return max(v, 0.0);
+// position in t_map and t_mask of a point of the cube
+// negative if it falls in a brick that is not resident
+vec3 locate(vec3 p) {
+    if (bricking.x == 0.0) {
+        return p;
+    }
+
+    vec3 voxel = clamp(floor(p * cube_size.xyz), vec3(0.0), cube_size.xyz - 1.0);
+    vec4 entry = texelFetch(sampler3D(t_bricks, s_map), ivec3(floor(voxel / bricking.x)), 0);
+    if (entry.a < 0.5) {
+        return vec3(-1.0);
+    }
+
+    // the resident brick can be of a coarser level L (entry.a = L + 1) sampling every 2^L voxels,
+    // the last voxels of an odd axis being in the last voxel of that level
+    float scale = exp2(entry.a - 1.0);
+    vec3 level_voxel = min(floor(voxel / scale), max(floor(cube_size.xyz / scale), 1.0) - 1.0);
+    vec3 brick = floor(level_voxel / bricking.x);
+
+    return (entry.xyz * bricking.x + (level_voxel - brick * bricking.x) + 0.5) / bricking.yzw;
+}
+
+// NaN and BLANK voxels are flagged with 0 in the mask
+bool is_valid(vec3 p) {
+    vec3 q = locate(p);
+    return q.x >= 0.0 && textureLod(sampler3D(t_mask, s_map), q, lod.x).r > 0.5;
+}
+
+// invalid voxels are replaced by the fallback value
+float probe_cube(vec3 p, float fallback) {
+    float v = textureLod(sampler3D(t_map, s_map), locate(p), lod.x).r;
+    return is_valid(p) ? v : fallback;
 }
 
 vec3 compute_normal(vec3 p) {
-    vec3 dv = 2.0 / cube_size.xyz;
+    vec3 dv = 2.0 * exp2(lod.x) / cube_size.xyz;
+    // blank neighbours do not contribute to the gradient
+    float f = isosurface.x;
 
     vec3 n = vec3(
-        probe_cube(p - vec3(dv.x, 0.0, 0.0)) - probe_cube(p + vec3(dv.x, 0.0, 0.0)),
-        probe_cube(p - vec3(0.0, dv.y, 0.0)) - probe_cube(p + vec3(0.0, dv.y, 0.0)),
-        probe_cube(p - vec3(0.0, 0.0, dv.z)) - probe_cube(p + vec3(0.0, 0.0, dv.z))
+        probe_cube(p - vec3(dv.x, 0.0, 0.0), f) - probe_cube(p + vec3(dv.x, 0.0, 0.0), f),
+        probe_cube(p - vec3(0.0, dv.y, 0.0), f) - probe_cube(p + vec3(0.0, dv.y, 0.0), f),
+        probe_cube(p - vec3(0.0, 0.0, dv.z), f) - probe_cube(p + vec3(0.0, 0.0, dv.z), f)
     );
 
     return normalize(n);
@@ -218,7 +253,7 @@ void main() {
         discard;
     }
 
-    vec3 voxel_size = 2.0 / cube_size.xyz;
+    vec3 voxel_size = 2.0 * exp2(lod.x) / cube_size.xyz;
     vec3 inv_dir = abs(r) / voxel_size;
     float step = 1.0 / max(max(inv_dir.x, inv_dir.y), inv_dir.z);
     //float step = 1.0 / 512.0;
@@ -235,15 +270,24 @@ void main() {
     int i = 0;
     // Set v to negative infinity
     float v = -1e30;
+    // true as long as the ray only crossed blank voxels
+    bool blank_ray = true;
     while (i < num_sampling && v < isosurface.x) {
         pp = p;
         p += dr;
 
-        v = probe_cube(p);
+        // blank voxels never cross the isosurface
+        v = probe_cube(p, -1e30);
+        blank_ray = blank_ray && !is_valid(p);
         i++;
     }
 
-    float vv = probe_cube(pp);
+    if (blank_ray && blank_color.a > 0.0) {
+        f_color = blank_color;
+        return;
+    }
+
+    float vv = probe_cube(pp, v);
     vec3 ps = pp + (p - pp) * (isosurface.x - vv) / (v - vv);
 
     vec3 N = compute_normal(ps);
@@ -45,6 +45,41 @@ uniform Slices {
     vec2 sz;
     vec2 sw;
 };
+layout(set = 0, binding = 12) uniform texture3D t_mask;
+layout(set = 0, binding = 13)
+uniform BlankColor {
+    vec4 blank_color;
+};
+// slot in the brick atlas (rgb) and residency (a) of each brick
+layout(set = 0, binding = 14) uniform texture3D t_bricks;
+// x: side of a brick in voxels, 0 if the cube is not bricked
+// yzw: size of the brick atlas in voxels
+layout(set = 0, binding = 15)
+uniform Bricking {
+    vec4 bricking;
+};
+// x: mip level of t_map and t_mask sampled
+layout(set = 0, binding = 16)
+uniform Lod {
+    vec4 lod;
+};
+// color (rgb) and opacity (a) of the direct volume rendering
+layout(set = 0, binding = 17) uniform texture3D t_transfer;
+// x: 0 for the maximum intensity projection, 1 for the direct volume rendering
+layout(set = 0, binding = 18)
+uniform RenderMode {
+    vec4 render_mode;
+};
+// colors (rgb) of the maximum intensity projection, stored in sRGB
+layout(set = 0, binding = 19) uniform texture3D t_colormap;
+// x: 0 linear, 1 log, 2 sqrt, 3 power, 4 asinh, 5 histogram equalization
+// y: log base, power exponent or asinh softening
+layout(set = 0, binding = 20)
+uniform Stretch {
+    vec4 stretch;
+};
+// fraction of the values between the cuts below each value, for the histogram equalization
+layout(set = 0, binding = 21) uniform texture3D t_stretch;
 
 vec3 lonlat2xyz(float lon, float lat) {
     float lat_s = sin(lat);
@@ -54,96 +89,16 @@ vec3 lonlat2xyz(float lon, float lat) {
 
     return vec3(lat_c * lon_s, lat_s, lat_c * lon_c);
 }
-float colormap_red(float x) {
-    if (x < 0.7) {
-        return 4.0 * x - 1.5;
-    } else {
-        return -4.0 * x + 4.5;
-    }
-}
-
-float colormap_green(float x) {
-    if (x < 0.5) {
-        return 4.0 * x - 0.5;
-    } else {
-        return -4.0 * x + 3.5;
-    }
-}
-
-float colormap_blue(float x) {
-    if (x < 0.3) {
-       return 4.0 * x + 0.5;
-    } else {
-       return -4.0 * x + 2.5;
-    }
-}
-/*
-vec3 colormap_viridis(float t) {
-    // Clamp input to [0,1]
-    t = clamp(t, 0.0, 1.0);
-
-    // Coefficients from the original viridis colormap (Matplotlib)
-    const vec3 c0 = vec3(0.280, 0.165, 0.476);
-    const vec3 c1 = vec3(0.110, 0.573, 0.664);
-    const vec3 c2 = vec3(0.478, 0.821, 0.318);
-
-    // Interpolation logic
-    if (t < 0.5) {
-        float f = smoothstep(0.0, 0.5, t);
-        return mix(c0, c1, f);
-    } else {
-        float f = smoothstep(0.5, 1.0, t);
-        return mix(c1, c2, f);
-    }
-}*/
-
-vec3 colormap_viridis(float t) {
-    vec3 c0 = vec3(0.2777273272234177, 0.005407344544966578, 0.3340998053353061);
-    vec3 c1 = vec3(0.1050930431085774, 1.404613529898575, 1.384590162594685);
-    vec3 c2 = vec3(-0.3308618287255563, 0.214847559468213, 0.09509516302823659);
-    vec3 c3 = vec3(-4.634230498983486, -5.799100973351585, -19.33244095627987);
-    vec3 c4 = vec3(6.228269936347081, 14.17993336680509, 56.69055260068105);
-    vec3 c5 = vec3(4.776384997670288, -13.74514537774601, -65.35303263337234);
-    vec3 c6 = vec3(-5.435455855934631, 4.645852612178535, 26.3124352495832);
-    return c0+t*(c1+t*(c2+t*(c3+t*(c4+t*(c5+t*c6)))));
-}
-
-vec3 colormap_turbo(in float x) {
-    const vec4 kRedVec4 = vec4(0.13572138, 4.61539260, -42.66032258, 132.13108234);
-    const vec4 kGreenVec4 = vec4(0.09140261, 2.19418839, 4.84296658, -14.18503333);
-    const vec4 kBlueVec4 = vec4(0.10667330, 12.64194608, -60.58204836, 110.36276771);
-    const vec2 kRedVec2 = vec2(-152.94239396, 59.28637943);
-    const vec2 kGreenVec2 = vec2(4.27729857, 2.82956604);
-    const vec2 kBlueVec2 = vec2(-89.90310912, 27.34824973);
-  
-    x = clamp(x,0.0,1.0);
-    vec4 v4 = vec4( 1.0, x, x * x, x * x * x);
-    vec2 v2 = v4.zw * v4.z;
-    return vec3(
-        dot(v4, kRedVec4)   + dot(v2, kRedVec2),
-        dot(v4, kGreenVec4) + dot(v2, kGreenVec2),
-        dot(v4, kBlueVec4)  + dot(v2, kBlueVec2)
-    );
-}
+// number of entries of t_colormap
+const int colormap_size = 256;
 
+// colormap linearly interpolated at x between 0 and 1
 vec4 colormap(float x) {
-    float r = clamp(colormap_red(x), 0.0, 1.0);
-    float g = clamp(colormap_green(x), 0.0, 1.0);
-    float b = clamp(colormap_blue(x), 0.0, 1.0);
-    return vec4(r, g, b, 1.0);
-}
-
-float to_l_endian(float x) {
-    uint y = floatBitsToUint(x);
-
-    uint a = y & 0xff;
-    uint b = (y >> 8) & 0xff;
-    uint c = (y >> 16) & 0xff;
-    uint d = y >> 24;
-
-    uint w = (a << 24) | (b << 16) | (c << 8) | d;
-
-    return uintBitsToFloat(w);
+    float t = clamp(x, 0.0, 1.0) * float(colormap_size - 1);
+    int i = int(floor(t));
+    vec4 a = texelFetch(sampler3D(t_colormap, s_map), ivec3(i, 0, 0), 0);
+    vec4 b = texelFetch(sampler3D(t_colormap, s_map), ivec3(min(i + 1, colormap_size - 1), 0, 0), 0);
+    return vec4(mix(a, b, fract(t)).rgb, 1.0);
 }
 
 // Parameters:
@@ -157,18 +112,83 @@ float asinhStretch(float x, float scale, float nonlinearity) {
     return asinh(scale * x) / asinh(scale * nonlinearity);
 }
 
+// number of entries of t_stretch
+const int stretch_size = 1024;
+
+// value normalized between the cuts mapped by the stretch selected
+float apply_stretch(float x) {
+    if (stretch.x == 1.0) {
+        return log(stretch.y * x + 1.0) / log(stretch.y + 1.0);
+    } else if (stretch.x == 2.0) {
+        return sqrt(x);
+    } else if (stretch.x == 3.0) {
+        return pow(x, stretch.y);
+    } else if (stretch.x == 4.0) {
+        return asinhStretch(x, 1.0 / stretch.y, 1.0);
+    } else if (stretch.x == 5.0) {
+        float t = x * float(stretch_size - 1);
+        int i = int(floor(t));
+        float a = texelFetch(sampler3D(t_stretch, s_map), ivec3(i, 0, 0), 0).r;
+        float b = texelFetch(sampler3D(t_stretch, s_map), ivec3(min(i + 1, stretch_size - 1), 0, 0), 0).r;
+        return mix(a, b, fract(t));
+    }
+
+    return x;
+}
+
 bool is_finite_f32(float x) {
     return abs(x) <= 3.402823e38;
 }
 
+// number of entries of t_transfer
+const int transfer_size = 256;
+// thickness of the slab whose opacity is given by t_transfer
+const float transfer_step = 1.0 / 128.0;
+
+// transfer function linearly interpolated at x between 0 and 1
+vec4 transfer(float x) {
+    float t = x * float(transfer_size - 1);
+    int i = int(floor(t));
+    vec4 a = texelFetch(sampler3D(t_transfer, s_map), ivec3(i, 0, 0), 0);
+    vec4 b = texelFetch(sampler3D(t_transfer, s_map), ivec3(min(i + 1, transfer_size - 1), 0, 0), 0);
+    return mix(a, b, fract(t));
+}
+
 const float fov = 0.523333;
 const float camera_near = 1.0;
 //const float dmin = -2.451346722E-03;
 //const float dmax = 1.179221552E-02;
 
-float probe_cube(vec3 p) {
-    float v = to_l_endian(texture(sampler3D(t_map, s_map), p).r);
-    return max(v, 0.0);
+// position in t_map and t_mask of a point of the cube
+// negative if it falls in a brick that is not resident
+vec3 locate(vec3 p) {
+    if (bricking.x == 0.0) {
+        return p;
+    }
+
+    vec3 voxel = clamp(floor(p * cube_size.xyz), vec3(0.0), cube_size.xyz - 1.0);
+    vec4 entry = texelFetch(sampler3D(t_bricks, s_map), ivec3(floor(voxel / bricking.x)), 0);
+    if (entry.a < 0.5) {
+        return vec3(-1.0);
+    }
+
+    // the resident brick can be of a coarser level L (entry.a = L + 1) sampling every 2^L voxels,
+    // the last voxels of an odd axis being in the last voxel of that level
+    float scale = exp2(entry.a - 1.0);
+    vec3 level_voxel = min(floor(voxel / scale), max(floor(cube_size.xyz / scale), 1.0) - 1.0);
+    vec3 brick = floor(level_voxel / bricking.x);
+
+    return (entry.xyz * bricking.x + (level_voxel - brick * bricking.x) + 0.5) / bricking.yzw;
+}
+
+// q is given by locate
+float probe_cube(vec3 q) {
+    return textureLod(sampler3D(t_map, s_map), q, lod.x).r;
+}
+
+// NaN and BLANK voxels are flagged with 0 in the mask
+bool is_valid(vec3 q) {
+    return q.x >= 0.0 && textureLod(sampler3D(t_mask, s_map), q, lod.x).r > 0.5;
 }
 
 void main() {
@@ -207,8 +227,10 @@ void main() {
         discard;
     }
 
-    float intensity = 0.0;
-    vec3 voxel_size = 1.0 / cube_size.xyz;
+    float intensity = -1e30;
+    // true as long as the ray only crossed blank voxels
+    bool blank_ray = true;
+    vec3 voxel_size = 1.0 * exp2(lod.x) / cube_size.xyz;
     vec3 inv_dir = abs(r) / voxel_size;
     float step = 1.0 / max(max(inv_dir.x, inv_dir.y), inv_dir.z);
     //float step = 1.0 / 512.0;
@@ -231,22 +253,57 @@ void main() {
     vec3 p = p_cam + r * t_s + vec3(0.5);
     //int n = 1;
     int i = 0;
+
+    if (render_mode.x == 1.0) {
+        // front-to-back compositing of the colors and opacities given by the transfer function
+        vec4 acc = vec4(0.0);
+        while (i < num_sampling && acc.a < 0.99) {
+            vec3 q = locate(p);
+            if (is_valid(q)) {
+                float x = apply_stretch(clamp((probe_cube(q) - cut.x) / (cut.y - cut.x), 0.0, 1.0));
+                vec4 c = transfer(x);
+                // opacity of the slab crossed by the step
+                float alpha = 1.0 - pow(1.0 - min(c.a, 0.999), step / transfer_step);
+
+                acc.rgb += (1.0 - acc.a) * alpha * c.rgb;
+                acc.a += (1.0 - acc.a) * alpha;
+                blank_ray = false;
+            }
+
+            p += dr;
+            i++;
+        }
+
+        if (blank_ray && blank_color.a > 0.0) {
+            f_color = blank_color;
+            return;
+        }
+
+        // composited over the background
+        f_color = vec4(acc.rgb + (1.0 - acc.a) * vec3(0.01), 1.0);
+        return;
+    }
+
     while(i < num_sampling && intensity < cut.y) {
-        float v = probe_cube(p);
+        vec3 q = locate(p);
+        if (is_valid(q)) {
+            intensity = max(intensity, probe_cube(q));
+            blank_ray = false;
+        }
 
-        intensity = max(intensity, v);
         p += dr;
         i++;
     }
 
+    if (blank_ray && blank_color.a > 0.0) {
+        f_color = blank_color;
+        return;
+    }
+
     //intensity /= float(num_sampling);
-    //intensity = asinhStretch(intensity, 1.0, 1.0);
 
-    intensity = clamp((intensity - cut.x) / (cut.y - cut.x), 0.0, 1.0);
+    intensity = apply_stretch(clamp((intensity - cut.x) / (cut.y - cut.x), 0.0, 1.0));
 
-    //f_color = vec4(colormap_turbo(intensity), 1.0);
-    //f_color = vec4(pow(colormap_viridis(intensity), vec3(2.2)).rgb, 1.0);
-    //f_color = vec4(intensity);
     f_color = colormap(intensity);
 }
  
\ No newline at end of file
@@ -0,0 +1,544 @@
+use fitsrs::hdu::header::ValueMap;
+
+use crate::frames::{self, Observation, SpectralFrame};
+use crate::table::Table;
+use crate::{get_float, get_integer, get_string};
+
+/// Speed of light in vacuum, in m/s
+pub(crate) const C: f64 = 299_792_458.0;
+
+/// Spectral coordinate types of FITS WCS Paper III handled by the viewer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpectralType {
+    Freq,
+    Wave,
+    Vrad,
+    Vopt,
+    Zopt,
+}
+
+impl SpectralType {
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "FREQ" => Some(SpectralType::Freq),
+            "WAVE" => Some(SpectralType::Wave),
+            "VRAD" => Some(SpectralType::Vrad),
+            "VOPT" => Some(SpectralType::Vopt),
+            "ZOPT" => Some(SpectralType::Zopt),
+            _ => None,
+        }
+    }
+
+    pub fn all() -> [SpectralType; 5] {
+        [
+            SpectralType::Freq,
+            SpectralType::Wave,
+            SpectralType::Vrad,
+            SpectralType::Vopt,
+            SpectralType::Zopt,
+        ]
+    }
+
+    /// CTYPE code of the type, also given to the selection callback
+    pub fn code(&self) -> &'static str {
+        match self {
+            SpectralType::Freq => "FREQ",
+            SpectralType::Wave => "WAVE",
+            SpectralType::Vrad => "VRAD",
+            SpectralType::Vopt => "VOPT",
+            SpectralType::Zopt => "ZOPT",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpectralType::Freq => "Frequency",
+            SpectralType::Wave => "Wavelength",
+            SpectralType::Vrad => "Radio velocity",
+            SpectralType::Vopt => "Optical velocity",
+            SpectralType::Zopt => "Redshift",
+        }
+    }
+
+    /// Unit the coordinates are displayed in, with its value in SI units
+    pub fn display_unit(&self) -> (&'static str, f64) {
+        match self {
+            SpectralType::Freq => ("GHz", 1e9),
+            SpectralType::Wave => ("nm", 1e-9),
+            SpectralType::Vrad | SpectralType::Vopt => ("km/s", 1e3),
+            SpectralType::Zopt => ("", 1.0),
+        }
+    }
+
+    /// Velocities and redshifts are defined with respect to a rest frequency
+    fn needs_rest_frequency(&self) -> bool {
+        matches!(self, SpectralType::Vrad | SpectralType::Vopt | SpectralType::Zopt)
+    }
+
+    /// Frequency, in Hz, of a coordinate given in SI units
+    fn to_frequency(self, value: f64, rest_frequency: f64) -> f64 {
+        match self {
+            SpectralType::Freq => value,
+            SpectralType::Wave => C / value,
+            SpectralType::Vrad => rest_frequency * (1.0 - value / C),
+            SpectralType::Vopt => rest_frequency / (1.0 + value / C),
+            SpectralType::Zopt => rest_frequency / (1.0 + value),
+        }
+    }
+
+    /// Coordinate, in SI units, of a frequency given in Hz
+    fn at_frequency(self, frequency: f64, rest_frequency: f64) -> f64 {
+        match self {
+            SpectralType::Freq => frequency,
+            SpectralType::Wave => C / frequency,
+            SpectralType::Vrad => C * (1.0 - frequency / rest_frequency),
+            SpectralType::Vopt => C * (rest_frequency / frequency - 1.0),
+            SpectralType::Zopt => rest_frequency / frequency - 1.0,
+        }
+    }
+}
+
+/// Spectral variable an axis is linearly sampled in, the X of the X2P algorithm codes
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Basis {
+    Freq,
+    Wave,
+    /// Relativistic radial velocity
+    Velo,
+}
+
+impl Basis {
+    fn from_letter(letter: char) -> Option<Self> {
+        match letter {
+            'F' => Some(Basis::Freq),
+            'W' => Some(Basis::Wave),
+            'V' => Some(Basis::Velo),
+            // air wavelengths are not handled
+            _ => None,
+        }
+    }
+
+    fn to_frequency(self, value: f64, rest_frequency: f64) -> f64 {
+        match self {
+            Basis::Freq => value,
+            Basis::Wave => C / value,
+            Basis::Velo => rest_frequency * ((C - value) / (C + value)).sqrt(),
+        }
+    }
+
+    fn at_frequency(self, frequency: f64, rest_frequency: f64) -> f64 {
+        match self {
+            Basis::Freq => frequency,
+            Basis::Wave => C / frequency,
+            Basis::Velo => {
+                let ratio = (rest_frequency / frequency).powi(2);
+                C * (ratio - 1.0) / (ratio + 1.0)
+            }
+        }
+    }
+}
+
+/// Coordinates of a -TAB axis tabulated in a binary table
+#[derive(Debug, Clone, PartialEq)]
+struct Lookup {
+    /// Values of the intermediate coordinate at which the coordinates are tabulated
+    index: Vec<f64>,
+    /// 1-based positions of the elements of the coordinate array
+    positions: Vec<f64>,
+    coords: Vec<f64>,
+}
+
+/// Piecewise linear interpolation of monotonic nodes, extrapolated beyond the ends
+fn interpolate(xs: &[f64], ys: &[f64], x: f64) -> f64 {
+    let n = xs.len();
+    if n < 2 {
+        return ys.first().copied().unwrap_or(f64::NAN);
+    }
+
+    let increasing = xs[n - 1] >= xs[0];
+    let i = xs
+        .partition_point(|v| if increasing { *v < x } else { *v > x })
+        .clamp(1, n - 1);
+    let t = (x - xs[i - 1]) / (xs[i] - xs[i - 1]);
+
+    ys[i - 1] + t * (ys[i] - ys[i - 1])
+}
+
+/// Algorithm of a spectral axis (FITS WCS Paper III)
+#[derive(Debug, Clone, PartialEq)]
+enum Algorithm {
+    /// Linear in the spectral type of the axis
+    Linear,
+    /// Logarithmic, e.g. WAVE-LOG
+    Log,
+    /// Linear in another spectral variable, e.g. VOPT-F2W is linear in frequency
+    NonLinear(Basis),
+    /// Tabulated in a binary table extension given by its EXTNAME, EXTVER and
+    /// the names of its coordinate and index columns, read once the file is parsed
+    Table {
+        extname: String,
+        extver: i64,
+        coords: String,
+        index: Option<String>,
+        lookup: Option<Lookup>,
+    },
+}
+
+/// Value in SI units of a CUNITn unit
+///
+/// Only the units expected for the spectral types handled are recognized.
+fn unit_scale(unit: &str) -> Option<f64> {
+    match unit {
+        "Hz" => Some(1.0),
+        "kHz" => Some(1e3),
+        "MHz" => Some(1e6),
+        "GHz" => Some(1e9),
+        "THz" => Some(1e12),
+        "m" => Some(1.0),
+        "cm" => Some(1e-2),
+        "mm" => Some(1e-3),
+        "um" => Some(1e-6),
+        "nm" => Some(1e-9),
+        "Angstrom" | "angstrom" => Some(1e-10),
+        "m/s" | "m s-1" => Some(1.0),
+        "km/s" | "km s-1" => Some(1e3),
+        "cm/s" | "cm s-1" => Some(1e-2),
+        _ => None,
+    }
+}
+
+/// Spectral axis of a cube
+///
+/// Converts the positions along the z axis of the volume, in voxels from the
+/// start of the first channel, into spectral coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpectralAxis {
+    /// Type given by CTYPEn
+    pub ctype: SpectralType,
+    algorithm: Algorithm,
+    // reference value and increment, in the unit given by CUNITn
+    crval: f64,
+    cdelt: f64,
+    crpix: f64,
+    // value of the unit in SI units
+    unit_scale: f64,
+    /// Rest frequency in Hz, from RESTFRQ or RESTWAV
+    pub rest_frequency: Option<f64>,
+    /// Reference frame of the coordinates, from SPECSYS
+    pub frame: Option<SpectralFrame>,
+    // pointing and epoch the coordinates are converted to other frames with
+    observation: Option<Observation>,
+}
+
+impl SpectralAxis {
+    /// Spectral axis described by the WCS keywords of the FITS axis `axis` (0-based)
+    ///
+    /// `None` if the axis is not spectral or its type, algorithm or unit is not handled.
+    pub fn from_header(header: &ValueMap, axis: usize) -> Option<Self> {
+        let n = axis + 1;
+
+        let ctype = get_string(header, &format!("CTYPE{}", n))?.to_uppercase();
+        let mut codes = ctype.splitn(2, '-');
+        let ctype = SpectralType::from_code(codes.next()?)?;
+
+        let algorithm = match codes.next().map(|code| code.trim_matches('-')).unwrap_or("") {
+            "" => Algorithm::Linear,
+            "LOG" => Algorithm::Log,
+            "TAB" => Algorithm::Table {
+                extname: get_string(header, &format!("PS{}_0", n))?,
+                extver: get_integer(header, &format!("PV{}_1", n)).unwrap_or(1),
+                coords: get_string(header, &format!("PS{}_1", n))?,
+                index: get_string(header, &format!("PS{}_2", n)),
+                lookup: None,
+            },
+            // X2P codes, P being implied by the spectral type
+            code => {
+                let mut letters = code.chars();
+                match (letters.next(), letters.next(), letters.next(), letters.next()) {
+                    (Some(x), Some('2'), Some(_), None) => Algorithm::NonLinear(Basis::from_letter(x)?),
+                    _ => return None,
+                }
+            }
+        };
+
+        // coordinates are in SI units by default
+        let unit_scale = match get_string(header, &format!("CUNIT{}", n)) {
+            Some(unit) => unit_scale(&unit)?,
+            None => 1.0,
+        };
+
+        let cdelt = get_float(header, &format!("CD{}_{}", n, n)).unwrap_or_else(|| {
+            get_float(header, &format!("CDELT{}", n)).unwrap_or(1.0) * get_float(header, &format!("PC{}_{}", n, n)).unwrap_or(1.0)
+        });
+
+        let rest_frequency = get_float(header, "RESTFRQ")
+            .or_else(|| get_float(header, "RESTFREQ"))
+            .or_else(|| get_float(header, "RESTWAV").map(|wavelength| C / wavelength))
+            .filter(|f| *f > 0.0);
+
+        // converting velocities from or to the basis needs the rest frequency
+        if let Algorithm::NonLinear(basis) = &algorithm {
+            if (ctype.needs_rest_frequency() || *basis == Basis::Velo) && rest_frequency.is_none() {
+                return None;
+            }
+        }
+
+        Some(Self {
+            ctype,
+            algorithm,
+            crval: get_float(header, &format!("CRVAL{}", n)).unwrap_or(0.0),
+            cdelt,
+            crpix: get_float(header, &format!("CRPIX{}", n)).unwrap_or(0.0),
+            unit_scale,
+            rest_frequency,
+            frame: frames::spectral_frame(header),
+            observation: Observation::from_header(header),
+        })
+    }
+
+    /// Types the coordinates can be given in
+    ///
+    /// Frequencies and wavelengths are always available, velocities and
+    /// redshifts need a rest frequency.
+    pub fn types(&self) -> Vec<SpectralType> {
+        SpectralType::all()
+            .iter()
+            .copied()
+            .filter(|t| {
+                *t == self.ctype
+                    || self.rest_frequency.is_some()
+                    || (!t.needs_rest_frequency() && !self.ctype.needs_rest_frequency())
+            })
+            .collect()
+    }
+
+    /// Read the coordinates of a -TAB axis from the binary tables of the file
+    ///
+    /// Nothing is done for the other algorithms. Fails if the table or its
+    /// columns are not found.
+    pub fn read_table(&mut self, tables: &[Table]) -> bool {
+        let Algorithm::Table { extname, extver, coords, index, lookup } = &mut self.algorithm else {
+            return true;
+        };
+
+        let table = tables
+            .iter()
+            .find(|table| table.extname().as_deref() == Some(extname.as_str()) && table.extver() == *extver);
+        let Some(table) = table else {
+            return false;
+        };
+
+        let Some(values) = table.column(coords).filter(|values| !values.is_empty()) else {
+            return false;
+        };
+        let positions = (1..=values.len()).map(|k| k as f64).collect::<Vec<_>>();
+        // the coordinate array is indexed by its 1-based positions by default
+        let index = match index {
+            Some(name) => match table.column(name) {
+                Some(index) if index.len() == values.len() => index,
+                _ => return false,
+            },
+            None => positions.clone(),
+        };
+
+        *lookup = Some(Lookup {
+            index,
+            positions,
+            coords: values,
+        });
+        true
+    }
+
+    /// Coordinate in SI units, in the type of the axis, of a position along the axis
+    ///
+    /// The center of the first channel is at 0.5, i.e. at FITS pixel 1. The
+    /// coordinates of the channel centers are exact for all the algorithms, the
+    /// tabulated ones being interpolated between the entries of the table.
+    fn value(&self, position: f64) -> Option<f64> {
+        let offset = self.cdelt * (position + 0.5 - self.crpix);
+
+        match &self.algorithm {
+            Algorithm::Linear => Some((self.crval + offset) * self.unit_scale),
+            Algorithm::Log => Some(self.crval * (offset / self.crval).exp() * self.unit_scale),
+            Algorithm::NonLinear(basis) => {
+                let rest_frequency = self.rest_frequency.unwrap_or(0.0);
+                let to_basis = |s: f64| basis.at_frequency(self.ctype.to_frequency(s, rest_frequency), rest_frequency);
+
+                // the increment is converted with the derivative of the basis variable at the reference point
+                let (s_ref, ds) = (self.crval * self.unit_scale, self.cdelt * self.unit_scale);
+                let h = ds.abs() * 1e-3;
+                let dx_ds = (to_basis(s_ref + h) - to_basis(s_ref - h)) / (2.0 * h);
+
+                let x = to_basis(s_ref) + dx_ds * offset * self.unit_scale;
+                Some(self.ctype.at_frequency(basis.to_frequency(x, rest_frequency), rest_frequency))
+            }
+            Algorithm::Table { lookup, .. } => {
+                let Lookup { index, positions, coords } = lookup.as_ref()?;
+                let upsilon = interpolate(index, positions, self.crval + offset);
+                Some(interpolate(positions, coords, upsilon) * self.unit_scale)
+            }
+        }
+    }
+
+    /// Frames the coordinates can be given in
+    ///
+    /// Converting them to another frame needs the frame of the axis and the
+    /// pointing of the observation, plus its epoch for the frames moving with the Earth.
+    pub fn frames(&self) -> Vec<SpectralFrame> {
+        SpectralFrame::all()
+            .iter()
+            .copied()
+            .filter(|frame| self.doppler(Some(*frame)).is_some())
+            .collect()
+    }
+
+    /// Ratio of the frequencies measured in a frame to the ones measured in the frame of the axis
+    fn doppler(&self, frame: Option<SpectralFrame>) -> Option<f64> {
+        match (self.frame, frame) {
+            (_, None) => Some(1.0),
+            (Some(from), Some(to)) if from == to => Some(1.0),
+            (Some(from), Some(to)) => self.observation.as_ref()?.doppler(from, to),
+            (None, Some(_)) => None,
+        }
+    }
+
+    /// Coordinate in SI units of a position along the axis
+    ///
+    /// The coordinate is given in `frame`, or in the frame of the axis if `None`.
+    /// It is `None` if a rest frequency is needed for the conversion and is unknown,
+    /// or if the frame cannot be converted to.
+    pub fn coordinate(&self, position: f64, to: SpectralType, frame: Option<SpectralFrame>) -> Option<f64> {
+        let value = self.value(position)?;
+        let doppler = self.doppler(frame)?;
+        if to == self.ctype && doppler == 1.0 {
+            return Some(value);
+        }
+
+        let needs_rest_frequency = to.needs_rest_frequency() || self.ctype.needs_rest_frequency();
+        let rest_frequency = match self.rest_frequency {
+            Some(f) => f,
+            None if needs_rest_frequency => return None,
+            None => 0.0,
+        };
+
+        let frequency = self.ctype.to_frequency(value, rest_frequency) * doppler;
+        Some(to.at_frequency(frequency, rest_frequency))
+    }
+
+    /// Coordinate of a position in the display unit of a type, see [SpectralType::display_unit]
+    pub fn display(&self, position: f64, to: SpectralType, frame: Option<SpectralFrame>) -> Option<f64> {
+        let (_, scale) = to.display_unit();
+        self.coordinate(position, to, frame).map(|v| v / scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{bintable, close, extension_header, header};
+
+    /// Rest frequency of the HI line, in Hz
+    const HI: f64 = 1.420_405_752e9;
+
+    #[test]
+    fn convert_frequencies_to_velocities() {
+        let header = header(&[
+            ("CTYPE3", "'FREQ'"),
+            ("CRVAL3", "1.420405752E9"),
+            ("CDELT3", "1.0E5"),
+            ("CRPIX3", "1.0"),
+            ("CUNIT3", "'Hz'"),
+            ("RESTFRQ", "1.420405752E9"),
+        ]);
+        let axis = SpectralAxis::from_header(&header, 2).unwrap();
+        assert_eq!((axis.ctype, axis.rest_frequency), (SpectralType::Freq, Some(HI)));
+        assert_eq!(axis.types(), SpectralType::all());
+
+        // the center of the first channel is at the reference pixel
+        assert_eq!(axis.coordinate(0.5, SpectralType::Vrad, None), Some(0.0));
+        let frequency = HI + 1e5;
+        let expected = [
+            (SpectralType::Freq, frequency),
+            (SpectralType::Wave, C / frequency),
+            (SpectralType::Vrad, C * (1.0 - frequency / HI)),
+            (SpectralType::Vopt, C * (HI / frequency - 1.0)),
+            (SpectralType::Zopt, HI / frequency - 1.0),
+        ];
+        for (to, value) in expected {
+            let v = axis.coordinate(1.5, to, None).unwrap();
+            assert!(close(v, value, 1e-9), "{}: {} instead of {}", to.code(), v, value);
+        }
+        // in km/s
+        assert!((axis.display(1.5, SpectralType::Vrad, None).unwrap() + 21.106_114).abs() < 1e-6);
+    }
+
+    #[test]
+    fn need_a_rest_frequency_for_velocities() {
+        let wave = header(&[("CTYPE3", "'WAVE'"), ("CRVAL3", "500.0"), ("CDELT3", "0.1"), ("CRPIX3", "1.0"), ("CUNIT3", "'nm'")]);
+        let axis = SpectralAxis::from_header(&wave, 2).unwrap();
+
+        assert_eq!(axis.types(), [SpectralType::Freq, SpectralType::Wave]);
+        assert_eq!(axis.coordinate(0.5, SpectralType::Vrad, None), None);
+        assert!(close(axis.coordinate(10.5, SpectralType::Wave, None).unwrap(), 501e-9, 1e-9));
+        assert!(close(axis.coordinate(0.5, SpectralType::Freq, None).unwrap(), C / 500e-9, 1e-9));
+
+        // neither the axes without spectral CTYPE nor the unknown units are handled
+        assert_eq!(SpectralAxis::from_header(&wave, 0), None);
+        let parsecs = header(&[("CTYPE3", "'WAVE'"), ("CUNIT3", "'parsec'")]);
+        assert_eq!(SpectralAxis::from_header(&parsecs, 2), None);
+    }
+
+    #[test]
+    fn sample_logarithmic_and_non_linear_axes() {
+        let log = header(&[("CTYPE3", "'WAVE-LOG'"), ("CRVAL3", "1.0E-6"), ("CDELT3", "1.0E-8"), ("CRPIX3", "1.0")]);
+        let axis = SpectralAxis::from_header(&log, 2).unwrap();
+        assert_eq!(axis.coordinate(0.5, SpectralType::Wave, None), Some(1e-6));
+        // e times the reference wavelength a hundred channels further
+        assert!(close(axis.coordinate(100.5, SpectralType::Wave, None).unwrap(), 1e-6 * std::f64::consts::E, 1e-9));
+
+        // optical velocities sampled regularly in frequency
+        let f2w = header(&[
+            ("CTYPE3", "'VOPT-F2W'"),
+            ("CRVAL3", "1.0E6"),
+            ("CDELT3", "1.0E3"),
+            ("CRPIX3", "1.0"),
+            ("RESTFRQ", "1.420405752E9"),
+        ]);
+        let axis = SpectralAxis::from_header(&f2w, 2).unwrap();
+        assert!(close(axis.coordinate(0.5, SpectralType::Vopt, None).unwrap(), 1e6, 1e-9));
+        let frequency = |position| axis.coordinate(position, SpectralType::Freq, None).unwrap();
+        let (step, next_step) = (frequency(1.5) - frequency(0.5), frequency(2.5) - frequency(1.5));
+        assert!((step - next_step).abs() < 1e-6 * step.abs(), "{} {}", step, next_step);
+
+        // velocities cannot be converted from frequencies without rest frequency
+        let no_rest_frequency = header(&[("CTYPE3", "'VOPT-F2W'"), ("CRVAL3", "1.0E6")]);
+        assert_eq!(SpectralAxis::from_header(&no_rest_frequency, 2), None);
+    }
+
+    #[test]
+    fn interpolate_tabulated_axes() {
+        let cards = bintable(&[("'COORDS'", "'3D'")], 1, &[("EXTNAME", "'WCS-TAB'")]);
+        let data = [1.0e9_f64, 1.1e9, 1.3e9].iter().flat_map(|f| f.to_be_bytes()).collect::<Vec<_>>();
+        let primary = header(&[
+            ("CTYPE3", "'FREQ-TAB'"),
+            ("CRVAL3", "0.0"),
+            ("CDELT3", "1.0"),
+            ("CRPIX3", "0.0"),
+            ("PS3_0", "'WCS-TAB'"),
+            ("PS3_1", "'COORDS'"),
+        ]);
+        let table_header = extension_header(&cards);
+
+        let mut axis = SpectralAxis::from_header(&primary, 2).unwrap();
+        assert_eq!(axis.coordinate(0.5, SpectralType::Freq, None), None);
+        assert!(!axis.read_table(&[]));
+        assert!(axis.read_table(&[Table::new(&table_header, &data)]));
+
+        // the coordinates are tabulated at the channel centers
+        assert_eq!(axis.coordinate(0.5, SpectralType::Freq, None), Some(1.0e9));
+        assert_eq!(axis.coordinate(2.5, SpectralType::Freq, None), Some(1.3e9));
+        assert!(close(axis.coordinate(2.0, SpectralType::Freq, None).unwrap(), 1.2e9, 1e-9));
+        // and extrapolated beyond the ends
+        assert!(close(axis.coordinate(3.5, SpectralType::Freq, None).unwrap(), 1.5e9, 1e-9));
+    }
+}
@@ -0,0 +1,285 @@
+use std::ops::Range;
+
+/// Number of bins of the histogram of a cube
+const NUM_BINS: usize = 1 << 16;
+
+/// Histogram whose range grows with the values added
+///
+/// The range is doubled (merging the bins by pairs) each time a value falls
+/// outside of it so that the values can be streamed in a single pass
+/// without knowing their bounds beforehand.
+///
+/// The initial range is given by the first two distinct values added so that
+/// it only has to grow by the ratio between their gap and the whole range.
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    start: f64,
+    // width of a bin, 0 until two distinct values are added
+    width: f64,
+    // allocated once the range is known
+    bins: Vec<u64>,
+    // value added while the range is not known, with its count
+    first: Option<(f64, u64)>,
+}
+
+impl Histogram {
+    /// Range covered by the bins
+    pub fn range(&self) -> Range<f64> {
+        self.start..(self.start + self.width * NUM_BINS as f64)
+    }
+
+    /// Counts of the values in `num_bins` bins regularly spanning `range`
+    ///
+    /// Used to display the histogram, the fine bins are assigned by their center.
+    pub fn rebin(&self, range: Range<f64>, num_bins: usize) -> Vec<u64> {
+        let mut counts = vec![0; num_bins];
+        if range.end <= range.start {
+            return counts;
+        }
+
+        let scale = num_bins as f64 / (range.end - range.start);
+        for (center, count) in self.first.iter().copied().chain(self.centers()) {
+            let j = ((center - range.start) * scale).floor();
+
+            if j >= 0.0 && j < num_bins as f64 {
+                counts[j as usize] += count;
+            }
+        }
+
+        counts
+    }
+
+    /// Centers of the non empty bins with their counts
+    fn centers(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        self.bins
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c > 0)
+            .map(move |(i, c)| (self.start + (i as f64 + 0.5) * self.width, *c))
+    }
+
+    fn add(&mut self, v: f64, count: u64) {
+        if self.width == 0.0 {
+            match self.first {
+                None => {
+                    self.first = Some((v, count));
+                    return;
+                }
+                Some((first, n)) if first == v => {
+                    self.first = Some((first, n + count));
+                    return;
+                }
+                Some((first, n)) => {
+                    // the two values span the middle half of the range
+                    let (low, high) = (first.min(v), first.max(v));
+                    self.width = (2.0 * (high - low) / NUM_BINS as f64).max(f64::MIN_POSITIVE);
+                    self.start = low - 0.5 * (high - low);
+                    self.bins = vec![0; NUM_BINS];
+                    self.first = None;
+                    self.add(first, n);
+                }
+            }
+        }
+
+        while v < self.start {
+            self.grow_left();
+        }
+        while v >= self.range().end {
+            self.grow_right();
+        }
+
+        let idx = ((v - self.start) / self.width) as usize;
+        self.bins[idx.min(NUM_BINS - 1)] += count;
+    }
+
+    /// Add the values of another histogram
+    ///
+    /// The values of each of its bins are assumed to be at the bin center.
+    fn merge(&mut self, other: &Histogram) {
+        for (center, count) in other.first.iter().copied().chain(other.centers()) {
+            self.add(center, count);
+        }
+    }
+
+    /// Double the range towards the higher values
+    fn grow_right(&mut self) {
+        for i in 0..(NUM_BINS / 2) {
+            self.bins[i] = self.bins[2 * i] + self.bins[2 * i + 1];
+        }
+        self.bins[(NUM_BINS / 2)..].iter_mut().for_each(|b| *b = 0);
+        self.width *= 2.0;
+    }
+
+    /// Double the range towards the lower values
+    fn grow_left(&mut self) {
+        for i in (0..(NUM_BINS / 2)).rev() {
+            self.bins[NUM_BINS / 2 + i] = self.bins[2 * i] + self.bins[2 * i + 1];
+        }
+        self.bins[..(NUM_BINS / 2)].iter_mut().for_each(|b| *b = 0);
+        self.start -= self.width * NUM_BINS as f64;
+        self.width *= 2.0;
+    }
+}
+
+/// Statistics of the valid values of a cube computed in a single pass
+#[derive(Debug, Clone, Default)]
+pub struct Statistics {
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    // sum of the squared deviations to the mean (Welford's algorithm)
+    m2: f64,
+    pub histogram: Histogram,
+}
+
+impl Statistics {
+    /// Add a value, non finite ones are ignored
+    pub fn add(&mut self, v: f64) {
+        if !v.is_finite() {
+            return;
+        }
+
+        if self.count == 0 {
+            self.min = v;
+            self.max = v;
+        } else {
+            self.min = self.min.min(v);
+            self.max = self.max.max(v);
+        }
+
+        self.count += 1;
+        let delta = v - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (v - self.mean);
+
+        self.histogram.add(v, 1);
+    }
+
+    /// Add the values another statistics have been computed on
+    pub fn merge(&mut self, other: &Statistics) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = other.clone();
+            return;
+        }
+
+        // parallel variant of Welford's algorithm (Chan et al.)
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.count as f64 / count as f64;
+        self.m2 += other.m2 + delta * delta * (self.count as f64 * other.count as f64 / count as f64);
+        self.count = count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+
+        self.histogram.merge(&other.histogram);
+    }
+
+    pub fn std(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            (self.m2 / self.count as f64).sqrt()
+        }
+    }
+
+    /// Root mean square of the values
+    pub fn rms(&self) -> f64 {
+        (self.std() * self.std() + self.mean * self.mean).sqrt()
+    }
+
+    /// Value at a percentile, between 0 and 100, interpolated in its bin
+    pub fn percentile(&self, percent: f32) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+
+        let rank = (percent.clamp(0.0, 100.0) as f64 / 100.0) * self.count as f64;
+        let Histogram { start, width, bins, .. } = &self.histogram;
+
+        let mut cumulated = 0;
+        for (i, count) in bins.iter().enumerate() {
+            if *count > 0 && (cumulated + count) as f64 >= rank {
+                let fraction = (rank - cumulated as f64) / *count as f64;
+                let v = start + (i as f64 + fraction) * width;
+
+                return v.clamp(self.min, self.max);
+            }
+            cumulated += count;
+        }
+
+        self.max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statistics(values: impl Iterator<Item = f64>) -> Statistics {
+        let mut stats = Statistics::default();
+        values.for_each(|v| stats.add(v));
+        stats
+    }
+
+    /// Values spread in [-500, 1501[ in a scrambled order
+    fn values() -> Vec<f64> {
+        (0..20000).map(|i| ((i * 7919) % 20011) as f64 * 0.1 - 500.0).collect()
+    }
+
+    #[test]
+    fn match_the_percentiles_of_the_sorted_values() {
+        let values = values();
+        let stats = statistics(values.iter().copied());
+
+        let mut sorted = values.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        assert_eq!((stats.count, stats.min, stats.max), (20000, sorted[0], sorted[19999]));
+
+        for percent in [0.5, 1.0, 10.0, 50.0, 90.0, 99.0, 99.5] {
+            let expected = sorted[(percent as f64 / 100.0 * 20000.0) as usize];
+            let v = stats.percentile(percent);
+            assert!((v - expected).abs() < 0.2, "{}%: {} instead of {}", percent, v, expected);
+        }
+        assert_eq!((stats.percentile(0.0), stats.percentile(100.0)), (stats.min, stats.max));
+
+        let mean = values.iter().sum::<f64>() / 20000.0;
+        let std = (values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / 20000.0).sqrt();
+        assert!((stats.mean - mean).abs() < 1e-9 && (stats.std() - std).abs() < 1e-9);
+    }
+
+    #[test]
+    fn merge_the_statistics_of_chunks() {
+        let values = values();
+        let whole = statistics(values.iter().copied());
+
+        let merged = values.chunks(3000).map(|chunk| statistics(chunk.iter().copied())).fold(
+            Statistics::default(),
+            |mut stats, other| {
+                stats.merge(&other);
+                stats
+            },
+        );
+        assert_eq!((merged.count, merged.min, merged.max), (whole.count, whole.min, whole.max));
+        assert!((merged.mean - whole.mean).abs() < 1e-9 && (merged.std() - whole.std()).abs() < 1e-9);
+        for percent in [1.0, 50.0, 99.0] {
+            assert!((merged.percentile(percent) - whole.percentile(percent)).abs() < 0.2);
+        }
+
+        let counts = merged.histogram.rebin(-500.0..1501.0, 4);
+        assert_eq!(counts.iter().sum::<u64>(), 20000);
+        assert!(counts.iter().all(|c| (4900..5100).contains(c)), "{:?}", counts);
+    }
+
+    #[test]
+    fn count_constant_values() {
+        let stats = statistics([3.0; 10].iter().copied().chain([f64::NAN, f64::INFINITY]));
+
+        assert_eq!((stats.count, stats.min, stats.max, stats.std()), (10, 3.0, 3.0, 0.0));
+        assert_eq!(stats.percentile(50.0), 3.0);
+        assert_eq!(stats.histogram.rebin(0.0..4.0, 4), [0, 0, 0, 10]);
+    }
+}
@@ -0,0 +1,170 @@
+use std::ops::Range;
+
+use crate::stats::Histogram;
+
+/// Number of entries of the histogram equalization texture
+pub const STRETCH_SIZE: usize = 1024;
+
+/// Function applied to the values normalized between the cuts before coloring them
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Stretch {
+    #[default]
+    Linear,
+    /// log(a x + 1) / log(a + 1), `base` being a
+    Log { base: f32 },
+    Sqrt,
+    /// x^exponent
+    Power { exponent: f32 },
+    /// asinh(x / a) / asinh(1 / a), linear below the softening a and logarithmic above
+    Asinh { softening: f32 },
+    /// Fraction of the values between the cuts below x, given by the histogram of the cube
+    HistEq,
+}
+
+impl Stretch {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stretch::Linear => "Linear",
+            Stretch::Log { .. } => "Log",
+            Stretch::Sqrt => "Square root",
+            Stretch::Power { .. } => "Power",
+            Stretch::Asinh { .. } => "Asinh",
+            Stretch::HistEq => "Histogram equalization",
+        }
+    }
+
+    /// Stretches with their default parameters, listed in the options panel
+    pub fn all() -> [Stretch; 6] {
+        [
+            Stretch::Linear,
+            Stretch::Log { base: 1000.0 },
+            Stretch::Sqrt,
+            Stretch::Power { exponent: 2.0 },
+            Stretch::Asinh { softening: 0.1 },
+            Stretch::HistEq,
+        ]
+    }
+
+    /// Tell whether the parameter of the stretch, if any, is finite and positive
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            Stretch::Log { base: parameter } | Stretch::Power { exponent: parameter } | Stretch::Asinh { softening: parameter } => {
+                parameter.is_finite() && parameter > 0.0
+            }
+            Stretch::Linear | Stretch::Sqrt | Stretch::HistEq => true,
+        }
+    }
+
+    /// Value of the stretch uniform of the volumetric shader, the kind then its parameter
+    pub fn uniform(&self) -> [f32; 4] {
+        let (kind, parameter) = match *self {
+            Stretch::Linear => (0.0, 0.0),
+            Stretch::Log { base } => (1.0, base),
+            Stretch::Sqrt => (2.0, 0.0),
+            Stretch::Power { exponent } => (3.0, exponent),
+            Stretch::Asinh { softening } => (4.0, softening),
+            Stretch::HistEq => (5.0, 0.0),
+        };
+
+        [kind, parameter, 0.0, 0.0]
+    }
+
+    /// Text representation stored with the view, e.g. `asinh 0.1`
+    pub fn to_text(self) -> String {
+        match self {
+            Stretch::Linear => "linear".to_owned(),
+            Stretch::Log { base } => format!("log {}", base),
+            Stretch::Sqrt => "sqrt".to_owned(),
+            Stretch::Power { exponent } => format!("power {}", exponent),
+            Stretch::Asinh { softening } => format!("asinh {}", softening),
+            Stretch::HistEq => "histeq".to_owned(),
+        }
+    }
+
+    /// Parse the text representation given by [Stretch::to_text]
+    pub fn from_text(text: &str) -> Option<Self> {
+        let mut words = text.split_whitespace();
+        let kind = words.next()?;
+        let parameter = words.next().map(|p| p.parse::<f32>().ok());
+        if words.next().is_some() {
+            return None;
+        }
+
+        let stretch = match (kind, parameter) {
+            ("linear", None) => Stretch::Linear,
+            ("log", Some(base)) => Stretch::Log { base: base? },
+            ("sqrt", None) => Stretch::Sqrt,
+            ("power", Some(exponent)) => Stretch::Power { exponent: exponent? },
+            ("asinh", Some(softening)) => Stretch::Asinh { softening: softening? },
+            ("histeq", None) => Stretch::HistEq,
+            _ => return None,
+        };
+
+        Some(stretch).filter(Stretch::is_valid)
+    }
+}
+
+/// Entries of the histogram equalization texture
+///
+/// The entry i is the fraction of the values between the cuts that are lower
+/// than the value normalized to i / (STRETCH_SIZE - 1).
+pub fn equalization(histogram: &Histogram, cuts: Range<f64>) -> Vec<f32> {
+    let counts = histogram.rebin(cuts, STRETCH_SIZE - 1);
+    let total = counts.iter().sum::<u64>();
+    if total == 0 {
+        // linear when there is no value between the cuts
+        return (0..STRETCH_SIZE).map(|i| i as f32 / (STRETCH_SIZE - 1) as f32).collect();
+    }
+
+    std::iter::once(0.0)
+        .chain(counts.iter().scan(0, |cumulated, count| {
+            *cumulated += count;
+            Some(*cumulated as f32 / total as f32)
+        }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::Statistics;
+
+    #[test]
+    fn round_trip_the_stretch_parameters() {
+        for stretch in Stretch::all() {
+            assert_eq!(Stretch::from_text(&stretch.to_text()), Some(stretch), "{}", stretch.to_text());
+        }
+        assert_eq!(Stretch::Log { base: 1000.0 }.to_text(), "log 1000");
+        assert_eq!(Stretch::from_text("  asinh   0.05 "), Some(Stretch::Asinh { softening: 0.05 }));
+        assert_eq!(Stretch::Power { exponent: 0.5 }.uniform(), [3.0, 0.5, 0.0, 0.0]);
+
+        for text in ["", "log", "log 0", "power -2", "power inf", "asinh NaN", "sqrt 2", "log 10 2", "gamma 2"] {
+            assert_eq!(Stretch::from_text(text), None, "{}", text);
+        }
+        assert!(Stretch::all().iter().all(Stretch::is_valid));
+        assert!(!Stretch::Power { exponent: f32::NAN }.is_valid() && !Stretch::Log { base: -1.0 }.is_valid());
+    }
+
+    #[test]
+    fn equalize_the_histogram() {
+        // as many values in the lowest and in the highest tenth of the cuts
+        let mut stats = Statistics::default();
+        for i in 0..1000 {
+            let v = (i % 500) as f64 * 0.0002;
+            stats.add(if i < 500 { v } else { 0.9 + v });
+        }
+
+        let entries = equalization(&stats.histogram, 0.0..1.0);
+        assert_eq!(entries.len(), STRETCH_SIZE);
+        assert_eq!((entries[0], entries[STRETCH_SIZE - 1]), (0.0, 1.0));
+        // the middle of the cuts, without any value, gets half of the colors
+        let at = |x: f64| entries[(x * (STRETCH_SIZE - 1) as f64).round() as usize];
+        assert!((at(0.12) - 0.5).abs() < 1e-6 && (at(0.88) - 0.5).abs() < 1e-6);
+        assert!((at(0.05) - 0.25).abs() < 0.01, "{}", at(0.05));
+        assert!(entries.windows(2).all(|w| w[0] <= w[1]));
+
+        // linear when no value is between the cuts
+        let linear = equalization(&stats.histogram, 2.0..3.0);
+        assert!(linear.iter().enumerate().all(|(i, e)| *e == i as f32 / (STRETCH_SIZE - 1) as f32));
+    }
+}
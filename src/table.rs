@@ -0,0 +1,226 @@
+use std::convert::TryInto;
+
+use fitsrs::hdu::header::ValueMap;
+
+use crate::{get_float, get_integer, get_string};
+
+/// Binary table extension read from the bytes of its data unit
+///
+/// Only used for the small tables describing a cube, e.g. the lookup table
+/// of a -TAB axis or the beams of a spectral cube, whose numeric columns are
+/// read in double precision, and the tiles of the images that fitsrs does not
+/// decompress, stored as variable-length arrays.
+pub struct Table<'a> {
+    header: &'a ValueMap,
+    data: &'a [u8],
+}
+
+/// Repeat count, type code and number of bytes of a TFORMn field
+fn field_type(tform: &str) -> Option<(usize, char, usize)> {
+    let tform = tform.trim();
+    let code_idx = tform.find(|c: char| c.is_ascii_alphabetic())?;
+    let repeat: usize = if code_idx == 0 { 1 } else { tform[..code_idx].parse().ok()? };
+    let code = tform[code_idx..].chars().next()?.to_ascii_uppercase();
+
+    let num_bytes = match code {
+        'L' | 'B' | 'A' => 1,
+        'I' => 2,
+        'J' | 'E' => 4,
+        'K' | 'D' | 'C' | 'P' => 8,
+        'M' | 'Q' => 16,
+        // bits are packed in bytes
+        'X' => return Some((repeat, code, repeat.div_ceil(8))),
+        _ => return None,
+    };
+
+    Some((repeat, code, repeat.checked_mul(num_bytes)?))
+}
+
+impl<'a> Table<'a> {
+    pub fn new(header: &'a ValueMap, data: &'a [u8]) -> Self {
+        Self { header, data }
+    }
+
+    /// EXTNAME of the table
+    pub fn extname(&self) -> Option<String> {
+        get_string(self.header, "EXTNAME")
+    }
+
+    /// EXTVER of the table, 1 by default
+    pub fn extver(&self) -> i64 {
+        get_integer(self.header, "EXTVER").unwrap_or(1)
+    }
+
+    /// Unit of a column given by TUNITn
+    pub fn unit(&self, name: &str) -> Option<String> {
+        let num_fields = get_integer(self.header, "TFIELDS")? as usize;
+        (1..=num_fields)
+            .find(|n| {
+                get_string(self.header, &format!("TTYPE{}", n))
+                    .map(|t| t.eq_ignore_ascii_case(name.trim()))
+                    .unwrap_or(false)
+            })
+            .and_then(|n| get_string(self.header, &format!("TUNIT{}", n)))
+    }
+
+    /// Number, repeat count, type code, number of bytes and offset in a row of a field
+    fn field(&self, name: &str) -> Option<(usize, usize, char, usize, usize)> {
+        let num_fields = get_integer(self.header, "TFIELDS")? as usize;
+
+        let mut offset = 0;
+        for n in 1..=num_fields {
+            let (repeat, code, num_bytes) = field_type(&get_string(self.header, &format!("TFORM{}", n))?)?;
+            let ttype = get_string(self.header, &format!("TTYPE{}", n));
+
+            if ttype.map(|t| t.eq_ignore_ascii_case(name.trim())).unwrap_or(false) {
+                return Some((n, repeat, code, num_bytes, offset));
+            }
+
+            offset = offset.checked_add(num_bytes)?;
+        }
+
+        None
+    }
+
+    /// Number of bytes of a row and number of rows
+    ///
+    /// `None` if the rows announced by NAXIS1 and NAXIS2 do not fit in the data unit.
+    fn rows(&self) -> Option<(usize, usize)> {
+        let row_num_bytes: usize = get_integer(self.header, "NAXIS1")?.try_into().ok()?;
+        let num_rows: usize = get_integer(self.header, "NAXIS2")?.try_into().ok()?;
+
+        (row_num_bytes.checked_mul(num_rows)? <= self.data.len()).then_some((row_num_bytes, num_rows))
+    }
+
+    /// Values of a numeric column, the elements of all the rows being concatenated
+    ///
+    /// `None` if the column is not found, is not numeric, does not fit in the rows
+    /// or the data unit is truncated. TSCALn and TZEROn are applied.
+    pub fn column(&self, name: &str) -> Option<Vec<f64>> {
+        let (row_num_bytes, num_rows) = self.rows()?;
+        let (n, repeat, code, num_bytes, offset) = self.field(name)?;
+        if offset + num_bytes > row_num_bytes {
+            return None;
+        }
+
+        let element_num_bytes = match code {
+            'B' => 1,
+            'I' => 2,
+            'J' | 'E' => 4,
+            'K' | 'D' => 8,
+            _ => return None,
+        };
+        let tscal = get_float(self.header, &format!("TSCAL{}", n)).unwrap_or(1.0);
+        let tzero = get_float(self.header, &format!("TZERO{}", n)).unwrap_or(0.0);
+
+        let mut values = Vec::with_capacity(num_rows * repeat);
+        for row in 0..num_rows {
+            let start = row * row_num_bytes + offset;
+            let field = self.data.get(start..(start + num_bytes))?;
+            for bytes in field.chunks_exact(element_num_bytes) {
+                let value = match code {
+                    'B' => bytes[0] as f64,
+                    'I' => i16::from_be_bytes(bytes.try_into().ok()?) as f64,
+                    'J' => i32::from_be_bytes(bytes.try_into().ok()?) as f64,
+                    'K' => i64::from_be_bytes(bytes.try_into().ok()?) as f64,
+                    'E' => f32::from_be_bytes(bytes.try_into().ok()?) as f64,
+                    'D' => f64::from_be_bytes(bytes.try_into().ok()?),
+                    _ => return None,
+                };
+                values.push(tzero + tscal * value);
+            }
+        }
+
+        Some(values)
+    }
+
+    /// Bytes of the variable-length arrays of a P or Q column, one per row
+    ///
+    /// The arrays are stored in the heap following the rows, from THEAP.
+    /// `None` if the column is not found or an array is out of the heap.
+    pub fn arrays(&self, name: &str) -> Option<Vec<&'a [u8]>> {
+        let (row_num_bytes, num_rows) = self.rows()?;
+        let (n, _, code, num_bytes, offset) = self.field(name)?;
+        if offset + num_bytes > row_num_bytes {
+            return None;
+        }
+        let tform = get_string(self.header, &format!("TFORM{}", n))?;
+        // type of the elements of the arrays, e.g. B in 1PB(149)
+        let element = tform.trim().to_ascii_uppercase().split(code).nth(1).and_then(|t| t.chars().next())?;
+        let (_, _, element_num_bytes) = field_type(&element.to_string())?;
+
+        let heap_start = get_integer(self.header, "THEAP").map(|t| t as usize).unwrap_or(row_num_bytes * num_rows);
+        let heap = self.data.get(heap_start..)?;
+        (0..num_rows)
+            .map(|row| {
+                let start = row * row_num_bytes + offset;
+                let (len, offset) = match code {
+                    'P' => {
+                        let descriptor = self.data.get(start..(start + 8))?;
+                        let len = u32::from_be_bytes(descriptor[..4].try_into().ok()?) as u64;
+                        (len, u32::from_be_bytes(descriptor[4..].try_into().ok()?) as u64)
+                    }
+                    'Q' => {
+                        let descriptor = self.data.get(start..(start + 16))?;
+                        let len = u64::from_be_bytes(descriptor[..8].try_into().ok()?);
+                        (len, u64::from_be_bytes(descriptor[8..].try_into().ok()?))
+                    }
+                    _ => return None,
+                };
+
+                let end = len.checked_mul(element_num_bytes as u64)?.checked_add(offset)?;
+                heap.get(offset.try_into().ok()?..end.try_into().ok()?)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{bintable, extension_header};
+
+    /// Table of two rows with a scaled 16 bits column and a pair of floats per row
+    fn table() -> (ValueMap, Vec<u8>) {
+        let cards = bintable(
+            &[("'CHANNEL'", "'I'"), ("'WIDTH'", "'2E'")],
+            2,
+            &[("TSCAL1", "2.0"), ("TZERO1", "1.0"), ("TUNIT2", "'arcsec'"), ("EXTNAME", "'SIZES'")],
+        );
+        let mut data = vec![];
+        for (channel, widths) in [(3_i16, [0.5_f32, 1.5]), (-4, [2.0, 4.0])] {
+            data.extend_from_slice(&channel.to_be_bytes());
+            widths.iter().for_each(|w| data.extend_from_slice(&w.to_be_bytes()));
+        }
+
+        (extension_header(&cards), data)
+    }
+
+    #[test]
+    fn read_numeric_columns() {
+        let (header, data) = table();
+        let table = Table::new(&header, &data);
+
+        assert_eq!((table.extname().as_deref(), table.extver()), (Some("SIZES"), 1));
+        assert_eq!(table.column("CHANNEL"), Some(vec![7.0, -7.0]));
+        assert_eq!(table.column("width"), Some(vec![0.5, 1.5, 2.0, 4.0]));
+        assert_eq!(table.unit("WIDTH").as_deref(), Some("arcsec"));
+        assert_eq!(table.column("FLUX"), None);
+
+        // the second row is cut
+        assert_eq!(Table::new(&header, &data[..15]).column("WIDTH"), None);
+
+        // the rows announced do not fit in the data unit
+        let cards = bintable(&[("'CHANNEL'", "'I'"), ("'WIDTH'", "'2E'")], 3, &[]);
+        assert_eq!(Table::new(&extension_header(&cards), &data).column("WIDTH"), None);
+    }
+
+    #[test]
+    fn size_the_fields() {
+        assert_eq!(field_type("1PB(32)"), Some((1, 'P', 8)));
+        assert_eq!(field_type("16A"), Some((16, 'A', 16)));
+        assert_eq!(field_type("12X"), Some((12, 'X', 2)));
+        assert_eq!(field_type("3d"), Some((3, 'D', 24)));
+        assert_eq!(field_type("2Z"), None);
+    }
+}
@@ -0,0 +1,135 @@
+//! FITS files and headers built by the tests
+
+use fitsrs::hdu::header::ValueMap;
+use fitsrs::{Fits, HDU};
+use std::io::Cursor;
+
+pub const BLOCK_SIZE: usize = 2880;
+
+/// Keyword and value of a header card
+pub type Card<'a> = (&'a str, &'a str);
+
+/// FITS file made of HDUs given by their header cards and their data unit
+///
+/// The values of the cards are written as in the header, e.g. strings are quoted.
+pub fn fits_file(hdus: &[(&[Card], Vec<u8>)]) -> Vec<u8> {
+    let mut bytes = vec![];
+    for (cards, data) in hdus {
+        for (keyword, value) in cards.iter().chain(std::iter::once(&("END", ""))) {
+            let card = if value.is_empty() {
+                format!("{:<80}", keyword)
+            } else {
+                format!("{:<8}= {:>20}{:50}", keyword, value, "")
+            };
+            assert_eq!(card.len(), 80, "the value of {} is too long", keyword);
+            bytes.extend_from_slice(card.as_bytes());
+        }
+        bytes.resize(bytes.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, b' ');
+
+        bytes.extend_from_slice(data);
+        bytes.resize(bytes.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+    }
+
+    bytes
+}
+
+/// Headers of the HDUs of a FITS file
+pub fn headers(file: &[u8]) -> Vec<ValueMap> {
+    let mut fits = Fits::from_reader(Cursor::new(file));
+
+    let mut headers = vec![];
+    while let Some(Ok(hdu)) = fits.next() {
+        let header = match &hdu {
+            HDU::Primary(hdu) | HDU::XImage(hdu) => ValueMap::clone(hdu.get_header()),
+            HDU::XBinaryTable(hdu) => ValueMap::clone(hdu.get_header()),
+            HDU::XASCIITable(hdu) => ValueMap::clone(hdu.get_header()),
+        };
+        headers.push(header);
+    }
+
+    headers
+}
+
+/// Header of an empty primary HDU with some more cards
+pub fn header(cards: &[Card]) -> ValueMap {
+    let primary = [("SIMPLE", "T"), ("BITPIX", "8"), ("NAXIS", "0")];
+    let cards = primary.iter().chain(cards).copied().collect::<Vec<_>>();
+
+    headers(&fits_file(&[(&cards, vec![])])).remove(0)
+}
+
+/// Header of an extension, e.g. a binary table, following an empty primary HDU
+pub fn extension_header<K: AsRef<str>, V: AsRef<str>>(cards: &[(K, V)]) -> ValueMap {
+    let primary: &[Card] = &[("SIMPLE", "T"), ("BITPIX", "8"), ("NAXIS", "0")];
+    let cards = cards.iter().map(|(k, v)| (k.as_ref(), v.as_ref())).collect::<Vec<_>>();
+
+    headers(&fits_file(&[(primary, vec![]), (&cards, vec![])])).remove(1)
+}
+
+/// Cards of a binary table extension of `num_rows` rows followed by some more cards
+///
+/// The columns are given by their TTYPEn and TFORMn values, NAXIS1 being the
+/// sum of the sizes of the fields and the heap being empty. The mandatory
+/// cards found in `cards`, e.g. PCOUNT for a heap, replace the computed ones.
+pub fn bintable(columns: &[Card], num_rows: usize, cards: &[Card]) -> Vec<(String, String)> {
+    let row_num_bytes = columns
+        .iter()
+        .map(|(_, tform)| {
+            let tform = tform.trim_matches(|c| c == '\'' || c == ' ');
+            let code_idx = tform.find(|c: char| c.is_ascii_alphabetic()).unwrap();
+            let repeat = if code_idx == 0 { 1 } else { tform[..code_idx].parse().unwrap() };
+            let num_bytes = match &tform[code_idx..(code_idx + 1)] {
+                "L" | "B" | "A" => 1,
+                "I" => 2,
+                "J" | "E" => 4,
+                "K" | "D" | "C" | "P" => 8,
+                "M" | "Q" => 16,
+                code => panic!("unknown TFORM code {}", code),
+            };
+            repeat * num_bytes
+        })
+        .sum::<usize>();
+
+    let mut table = [
+        ("XTENSION", "'BINTABLE'".to_owned()),
+        ("BITPIX", "8".to_owned()),
+        ("NAXIS", "2".to_owned()),
+        ("NAXIS1", row_num_bytes.to_string()),
+        ("NAXIS2", num_rows.to_string()),
+        ("PCOUNT", "0".to_owned()),
+        ("GCOUNT", "1".to_owned()),
+        ("TFIELDS", columns.len().to_string()),
+    ]
+    .iter()
+    .map(|(keyword, value)| {
+        let value = cards.iter().find(|(k, _)| k == keyword).map_or(value.as_str(), |(_, v)| v);
+        (keyword.to_string(), value.to_owned())
+    })
+    .collect::<Vec<_>>();
+    for (n, (ttype, tform)) in columns.iter().enumerate() {
+        table.push((format!("TTYPE{}", n + 1), ttype.to_string()));
+        table.push((format!("TFORM{}", n + 1), tform.to_string()));
+    }
+    let more = cards.iter().filter(|(keyword, _)| !table.iter().any(|(k, _)| k == keyword));
+    let more = more.map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<_>>();
+    table.extend(more);
+
+    table
+}
+
+/// WCS of a 100x100 image with some more cards
+pub fn wcs(cards: &[Card]) -> fitsrs::WCS {
+    let image = [("SIMPLE", "T"), ("BITPIX", "8"), ("NAXIS", "2"), ("NAXIS1", "100"), ("NAXIS2", "100")];
+    let cards = image.iter().chain(cards).copied().collect::<Vec<_>>();
+    let file = fits_file(&[(&cards, vec![0; 100 * 100])]);
+
+    match Fits::from_reader(Cursor::new(&file[..])).next() {
+        Some(Ok(HDU::Primary(hdu))) => hdu.wcs().unwrap(),
+        _ => panic!("the image cannot be parsed"),
+    }
+}
+
+/// Tell whether a value is equal to the expected one up to a relative tolerance
+pub fn close(value: f64, expected: f64, tolerance: f64) -> bool {
+    (value - expected).abs() <= tolerance * expected.abs()
+}
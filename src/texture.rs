@@ -6,6 +6,8 @@ pub struct Texture {
 }
 use byte_slice_cast::*;
 
+use crate::error::Error;
+
 pub trait TextureFormat: ToByteSlice {
     const WGPU_FORMAT: wgpu::TextureFormat;
 }
@@ -13,19 +15,24 @@ pub trait TextureFormat: ToByteSlice {
 impl TextureFormat for f32 {
     const WGPU_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
 }
+impl TextureFormat for u8 {
+    const WGPU_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+}
+impl TextureFormat for [f32; 4] {
+    const WGPU_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+}
 impl TextureFormat for [u8; 4] {
     const WGPU_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
 }
 impl Texture {
     fn from_bytes_rgba(
         device: &wgpu::Device,
-        queue: &wgpu::Queue,
         format: wgpu::TextureFormat,
-        rgba: Option<&[u8]>,
         dimensions: (u32, u32, u32),
         num_bytes_per_pixel: usize,
+        mip_level_count: u32,
         label: &str,
-    ) -> Result<Self, &'static str> {
+    ) -> Result<Self, Error> {
         let dimension = wgpu::TextureDimension::D3;
 
         let limits = device.limits();
@@ -42,7 +49,7 @@ impl Texture {
                     height: dimensions.1,
                     depth_or_array_layers: dimensions.2,
                 },
-                mip_level_count: 1,
+                mip_level_count,
                 sample_count: 1,
                 dimension,
                 format,
@@ -64,20 +71,18 @@ impl Texture {
                 ..Default::default()
             });
 
-            let texture = Self {
+            Ok(Self {
                 texture,
                 view,
                 sampler,
                 num_bytes_per_pixel,
-            };
-
-            if let Some(rgba) = rgba {
-                texture.write_data(queue, (0, 0, 0), rgba, dimensions);
-            }
-
-            Ok(texture)
+            })
         } else {
-            Err("Error: too big cube")
+            Err(Error::TextureTooBig {
+                label: label.to_owned(),
+                dim: dimensions,
+                limit: limits.max_texture_dimension_3d,
+            })
         }
     }
 
@@ -87,6 +92,17 @@ impl Texture {
         origin: (u32, u32, u32),
         data: &[u8],
         dimensions: (u32, u32, u32),
+    ) {
+        self.write_mip_data(queue, 0, origin, data, dimensions);
+    }
+
+    pub fn write_mip_data(
+        &self,
+        queue: &wgpu::Queue,
+        mip_level: u32,
+        origin: (u32, u32, u32),
+        data: &[u8],
+        dimensions: (u32, u32, u32),
     ) {
         let size = wgpu::Extent3d {
             width: dimensions.0,
@@ -96,7 +112,7 @@ impl Texture {
         queue.write_texture(
             wgpu::TexelCopyTextureInfo {
                 texture: &self.texture,
-                mip_level: 0,
+                mip_level,
                 origin: wgpu::Origin3d {
                     x: origin.0,
                     y: origin.1,
@@ -121,18 +137,58 @@ impl Texture {
         dimensions: (u32, u32, u32),
         num_bytes_per_pixel: usize,
         label: &str,
-    ) -> Result<Self, &'static str> {
+    ) -> Result<Self, Error> {
         // check the dimensions w.r.t to the limits
 
-        Self::from_bytes_rgba(
+        let texture = Self::from_bytes_rgba(
             device,
-            queue,
             T::WGPU_FORMAT,
-            bytes,
             dimensions,
             num_bytes_per_pixel,
+            1,
             label,
-        )
+        )?;
+
+        if let Some(bytes) = bytes {
+            texture.write_data(queue, (0, 0, 0), bytes, dimensions);
+        }
+
+        Ok(texture)
+    }
+
+    /// Texture with mip levels
+    ///
+    /// `levels` gives the bytes of the coarse levels, the level `i + 1` having
+    /// the dimensions of the full resolution one divided by 2^(i + 1). The full
+    /// resolution level is only allocated, it is filled with [Texture::write_data].
+    pub fn from_mips<T: TextureFormat>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        levels: &[&[u8]],
+        dimensions: (u32, u32, u32),
+        num_bytes_per_pixel: usize,
+        label: &str,
+    ) -> Result<Self, Error> {
+        let texture = Self::from_bytes_rgba(
+            device,
+            T::WGPU_FORMAT,
+            dimensions,
+            num_bytes_per_pixel,
+            levels.len() as u32 + 1,
+            label,
+        )?;
+
+        for (level, bytes) in (1..).zip(levels) {
+            let dim = (
+                (dimensions.0 >> level).max(1),
+                (dimensions.1 >> level).max(1),
+                (dimensions.2 >> level).max(1),
+            );
+
+            texture.write_mip_data(queue, level, (0, 0, 0), bytes, dim);
+        }
+
+        Ok(texture)
     }
 
     // rgba images
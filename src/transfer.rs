@@ -0,0 +1,317 @@
+/// Number of entries of the transfer function texture
+pub const TRANSFER_SIZE: usize = 256;
+
+/// Transfer function of the direct volume rendering
+///
+/// Maps the values normalized between the cuts to a color and an opacity,
+/// both linearly interpolated between their control points. The opacity is
+/// the one of a slab of 1/128 of the cube crossed by a ray.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferFunction {
+    /// Opacity control points (position, opacity)
+    pub opacity: Vec<[f32; 2]>,
+    /// Color stops (position, rgb)
+    pub colors: Vec<(f32, [f32; 3])>,
+}
+
+impl Default for TransferFunction {
+    fn default() -> Self {
+        Self {
+            opacity: vec![[0.0, 0.0], [0.2, 0.0], [1.0, 0.8]],
+            // same colors as the maximum intensity projection
+            colors: vec![
+                (0.0, [0.0, 0.0, 0.5]),
+                (0.125, [0.0, 0.0, 1.0]),
+                (0.375, [0.0, 1.0, 1.0]),
+                (0.625, [1.0, 1.0, 0.0]),
+                (0.875, [1.0, 0.0, 0.0]),
+                (1.0, [0.5, 0.0, 0.0]),
+            ],
+        }
+    }
+}
+
+/// Linear interpolation of control points, constant beyond the first and last ones
+fn interpolate<T: Copy>(points: &[(f32, T)], x: f32, mix: impl Fn(T, T, f32) -> T) -> Option<T> {
+    let points = sorted(points);
+    let first = points.first()?;
+    let last = points.last()?;
+    if x <= first.0 {
+        return Some(first.1);
+    }
+
+    Some(
+        points
+            .windows(2)
+            .find(|w| x <= w[1].0)
+            .map(|w| {
+                let t = if w[1].0 > w[0].0 { (x - w[0].0) / (w[1].0 - w[0].0) } else { 1.0 };
+                mix(w[0].1, w[1].1, t)
+            })
+            .unwrap_or(last.1),
+    )
+}
+
+/// Control points sorted by position
+///
+/// The points are kept in the order they have been added so that they keep
+/// their handle in the editor while being dragged past each other.
+fn sorted<T: Copy>(points: &[(f32, T)]) -> Vec<(f32, T)> {
+    let mut points = points.to_vec();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    points
+}
+
+impl TransferFunction {
+    fn opacity_points(&self) -> Vec<(f32, f32)> {
+        sorted(&self.opacity.iter().map(|p| (p[0], p[1])).collect::<Vec<_>>())
+    }
+
+    /// Opacity at a position between 0 and 1
+    pub fn opacity_at(&self, x: f32) -> f32 {
+        interpolate(&self.opacity_points(), x, |a, b, t| a + (b - a) * t).unwrap_or(0.0)
+    }
+
+    /// Color at a position between 0 and 1
+    pub fn color_at(&self, x: f32) -> [f32; 3] {
+        interpolate(&self.colors, x, |a, b, t| [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t)).unwrap_or([1.0; 3])
+    }
+
+    /// Entries of the transfer function texture, rgba
+    pub fn lut(&self) -> Vec<[f32; 4]> {
+        (0..TRANSFER_SIZE)
+            .map(|i| {
+                let x = i as f32 / (TRANSFER_SIZE - 1) as f32;
+                let [r, g, b] = self.color_at(x);
+                [r, g, b, self.opacity_at(x)]
+            })
+            .collect()
+    }
+
+    /// Text representation stored with the view, e.g. `0:0 0.5:0.1 1:0.8` for the opacity
+    /// and `0:#000080 1:#800000` for the colors
+    pub fn to_text(&self) -> (String, String) {
+        let opacity = self
+            .opacity_points()
+            .iter()
+            .map(|(x, a)| format!("{}:{}", x, a))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let colors = sorted(&self.colors)
+            .iter()
+            .map(|(x, rgb)| {
+                let [r, g, b] = rgb.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+                format!("{}:#{:02x}{:02x}{:02x}", x, r, g, b)
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        (opacity, colors)
+    }
+
+    /// Parse the text representation given by [TransferFunction::to_text]
+    pub fn from_text(opacity: &str, colors: &str) -> Option<Self> {
+        fn points(text: &str) -> Option<Vec<(&str, &str)>> {
+            text.split_whitespace()
+                .map(|point| point.split_once(':'))
+                .collect::<Option<Vec<_>>>()
+                .filter(|points| !points.is_empty())
+        }
+        let position = |x: &str| x.parse::<f32>().ok().filter(|x| (0.0..=1.0).contains(x));
+
+        let opacity = points(opacity)?
+            .into_iter()
+            .map(|(x, a)| Some([position(x)?, position(a)?]))
+            .collect::<Option<Vec<_>>>()?;
+        let colors = points(colors)?
+            .into_iter()
+            .map(|(x, hex)| {
+                let hex = hex.strip_prefix('#')?;
+                let channel = |i: usize| Some(u8::from_str_radix(hex.get(i..(i + 2))?, 16).ok()? as f32 / 255.0);
+                if hex.len() != 6 {
+                    return None;
+                }
+
+                Some((position(x)?, [channel(0)?, channel(2)?, channel(4)?]))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self { opacity, colors })
+    }
+}
+
+/// Height of the opacity editor, in points
+const EDITOR_HEIGHT: f32 = 100.0;
+/// Height of the color bar under the opacity editor, in points
+const COLOR_BAR_HEIGHT: f32 = 12.0;
+/// Radius of the control points, in points
+const HANDLE_RADIUS: f32 = 4.0;
+
+/// Editor of a transfer function drawn over the histogram of the values between the cuts
+///
+/// The opacity control points are dragged, added by double-clicking and removed
+/// by right-clicking them. The color stops are edited below. Returns true if
+/// the transfer function has been changed.
+pub fn editor(ui: &mut egui::Ui, transfer: &mut TransferFunction, histogram: &[u64]) -> bool {
+    let mut changed = false;
+
+    let (rect, response) = ui.allocate_exact_size(egui::vec2(ui.available_width(), EDITOR_HEIGHT), egui::Sense::click());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+
+    // histogram in log scale so that the tails of the distribution stay visible
+    let max_count = histogram.iter().copied().max().unwrap_or(0).max(1) as f32;
+    let column_width = rect.width() / histogram.len().max(1) as f32;
+    for (i, count) in histogram.iter().enumerate() {
+        let h = (*count as f32).ln_1p() / max_count.ln_1p() * rect.height();
+        let x = rect.left() + (i as f32) * column_width;
+
+        painter.rect_filled(
+            egui::Rect::from_min_max(egui::pos2(x, rect.bottom() - h), egui::pos2(x + column_width, rect.bottom())),
+            0.0,
+            ui.visuals().widgets.inactive.bg_fill,
+        );
+    }
+
+    let to_screen = |[x, a]: [f32; 2]| egui::pos2(rect.left() + x * rect.width(), rect.bottom() - a * rect.height());
+    let from_screen = |p: egui::Pos2| {
+        [
+            ((p.x - rect.left()) / rect.width()).clamp(0.0, 1.0),
+            ((rect.bottom() - p.y) / rect.height()).clamp(0.0, 1.0),
+        ]
+    };
+
+    let mut removed = None;
+    for (i, point) in transfer.opacity.iter_mut().enumerate() {
+        let center = to_screen(*point);
+        let handle = ui.interact(
+            egui::Rect::from_center_size(center, egui::vec2(4.0 * HANDLE_RADIUS, 4.0 * HANDLE_RADIUS)),
+            response.id.with(i),
+            egui::Sense::click_and_drag(),
+        );
+
+        if handle.dragged() {
+            if let Some(pos) = handle.interact_pointer_pos() {
+                *point = from_screen(pos);
+                changed = true;
+            }
+        }
+        if handle.secondary_clicked() {
+            removed = Some(i);
+        }
+    }
+    // an opacity is defined as long as one control point remains
+    if let Some(i) = removed.filter(|_| transfer.opacity.len() > 1) {
+        transfer.opacity.remove(i);
+        changed = true;
+    }
+    if response.double_clicked() {
+        if let Some(pos) = response.interact_pointer_pos() {
+            transfer.opacity.push(from_screen(pos));
+            changed = true;
+        }
+    }
+
+    let stroke = egui::Stroke::new(1.5, ui.visuals().selection.bg_fill);
+    let mut line = transfer.opacity_points().iter().map(|(x, a)| to_screen([*x, *a])).collect::<Vec<_>>();
+    // the opacity is constant beyond the first and last control points
+    if let (Some(first), Some(last)) = (line.first().copied(), line.last().copied()) {
+        line.insert(0, egui::pos2(rect.left(), first.y));
+        line.push(egui::pos2(rect.right(), last.y));
+    }
+    painter.add(egui::Shape::line(line, stroke));
+    for point in &transfer.opacity {
+        painter.circle(to_screen(*point), HANDLE_RADIUS, ui.visuals().strong_text_color(), stroke);
+    }
+
+    // colors of the transfer function
+    let (bar, _) = ui.allocate_exact_size(egui::vec2(rect.width(), COLOR_BAR_HEIGHT), egui::Sense::hover());
+    let num_columns = bar.width().max(1.0) as usize;
+    for i in 0..num_columns {
+        let [r, g, b] = transfer.color_at(i as f32 / num_columns as f32);
+        let x = bar.left() + i as f32;
+        ui.painter().rect_filled(
+            egui::Rect::from_min_max(egui::pos2(x, bar.top()), egui::pos2(x + 1.0, bar.bottom())),
+            0.0,
+            egui::Rgba::from_rgb(r, g, b),
+        );
+    }
+
+    let mut removed = None;
+    let num_stops = transfer.colors.len();
+    for (i, (x, rgb)) in transfer.colors.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            changed |= ui.color_edit_button_rgb(rgb).changed();
+            changed |= ui.add(egui::Slider::new(x, 0.0..=1.0).text("stop")).changed();
+            if num_stops > 1 && ui.small_button("x").on_hover_text("Remove the color stop").clicked() {
+                removed = Some(i);
+            }
+        });
+    }
+    if let Some(i) = removed {
+        transfer.colors.remove(i);
+        changed = true;
+    }
+    ui.horizontal(|ui| {
+        if ui.button("Add color stop").clicked() {
+            transfer.colors.push((0.5, transfer.color_at(0.5)));
+            changed = true;
+        }
+        if ui.button("Reset").clicked() {
+            *transfer = TransferFunction::default();
+            changed = true;
+        }
+    });
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolate_the_control_points() {
+        let transfer = TransferFunction::default();
+        assert_eq!(transfer.opacity_at(0.1), 0.0);
+        assert!((transfer.opacity_at(0.6) - 0.4).abs() < 1e-6);
+        assert_eq!(transfer.opacity_at(1.5), 0.8);
+        assert_eq!(transfer.color_at(0.25), [0.0, 0.5, 1.0]);
+
+        let lut = transfer.lut();
+        assert_eq!(lut.len(), TRANSFER_SIZE);
+        assert_eq!((lut[0], lut[TRANSFER_SIZE - 1]), ([0.0, 0.0, 0.5, 0.0], [0.5, 0.0, 0.0, 0.8]));
+
+        // the points are sorted, whatever the order they have been added
+        let dragged = TransferFunction {
+            opacity: vec![[1.0, 1.0], [0.0, 0.5]],
+            colors: vec![(0.5, [1.0; 3])],
+        };
+        assert_eq!(dragged.opacity_at(0.5), 0.75);
+        assert_eq!(dragged.color_at(0.0), [1.0; 3]);
+    }
+
+    #[test]
+    fn round_trip_the_opacity_and_color_stops() {
+        let (opacity, colors) = TransferFunction::default().to_text();
+        assert_eq!(opacity, "0:0 0.2:0 1:0.8");
+        assert_eq!(colors, "0:#000080 0.125:#0000ff 0.375:#00ffff 0.625:#ffff00 0.875:#ff0000 1:#800000");
+
+        let parsed = TransferFunction::from_text(&opacity, &colors).unwrap();
+        assert_eq!(parsed.opacity, TransferFunction::default().opacity);
+        assert_eq!(parsed.colors[1], (0.125, [0.0, 0.0, 1.0]));
+        assert_eq!(parsed.to_text(), (opacity, colors));
+
+        for (opacity, colors) in [
+            ("", "0:#000000"),
+            ("0:0", ""),
+            ("0:1.5", "0:#000000"),
+            ("0.5", "0:#000000"),
+            ("0:0", "0:#0000"),
+            ("0:0", "0:000000"),
+            ("0:0", "-1:#000000"),
+        ] {
+            assert_eq!(TransferFunction::from_text(opacity, colors), None, "{} {}", opacity, colors);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use crate::error::Error;
+use crate::stretch::Stretch;
+use crate::transfer::TransferFunction;
+use crate::volumetric::RenderMode;
+
+/// Settings of the view that can be stored and restored
+///
+/// The view is written as `key = value` lines, so that it can be copied and
+/// stored alongside the cube it has been set up for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct View {
+    /// Longitude and latitude of the camera around the cube, in radians
+    pub camera: (f64, f64),
+    pub perspective: bool,
+    pub mode: RenderMode,
+    /// Cuts in physical values
+    pub cuts: (f32, f32),
+    pub stretch: Stretch,
+    pub isosurface: f32,
+    pub transfer: TransferFunction,
+    /// Name of the colormap of the maximum intensity projection
+    pub colormap: String,
+    pub reverse_colormap: bool,
+}
+
+impl View {
+    pub fn to_text(&self) -> String {
+        let (opacity, colors) = self.transfer.to_text();
+
+        [
+            format!("camera = {} {}", self.camera.0, self.camera.1),
+            format!("perspective = {}", self.perspective),
+            format!("mode = {}", self.mode.keyword()),
+            format!("cuts = {} {}", self.cuts.0, self.cuts.1),
+            format!("stretch = {}", self.stretch.to_text()),
+            format!("isosurface = {}", self.isosurface),
+            format!("opacity = {}", opacity),
+            format!("colors = {}", colors),
+            format!("colormap = {}", self.colormap),
+            format!("reverse_colormap = {}", self.reverse_colormap),
+        ]
+        .join("\n")
+    }
+
+    /// Parse a view written by [View::to_text]
+    ///
+    /// The keys missing from the text are taken from `current`.
+    pub fn from_text(text: &str, current: &View) -> Result<Self, Error> {
+        let invalid = |key: &str| Error::InvalidView {
+            reason: format!("cannot parse the value of {}", key),
+        };
+        let pair = |value: &str| {
+            let mut values = value.split_whitespace().map(|v| v.parse::<f64>().ok());
+            match (values.next(), values.next(), values.next()) {
+                (Some(Some(a)), Some(Some(b)), None) => Some((a, b)),
+                _ => None,
+            }
+        };
+
+        let mut view = current.clone();
+        let (mut opacity, mut colors) = current.transfer.to_text();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let (key, value) = line.split_once('=').ok_or_else(|| Error::InvalidView {
+                reason: format!("expected key = value, got {}", line),
+            })?;
+            let (key, value) = (key.trim(), value.trim());
+
+            match key {
+                "camera" => view.camera = pair(value).ok_or_else(|| invalid(key))?,
+                "perspective" => view.perspective = value.parse().map_err(|_| invalid(key))?,
+                "mode" => view.mode = RenderMode::from_keyword(value).ok_or_else(|| invalid(key))?,
+                "cuts" => {
+                    let (low, high) = pair(value).ok_or_else(|| invalid(key))?;
+                    let (low, high) = (low as f32, high as f32);
+                    if !(low.is_finite() && high.is_finite() && low < high) {
+                        return Err(Error::InvalidView {
+                            reason: format!("the cuts {} are not finite and increasing", value),
+                        });
+                    }
+                    view.cuts = (low, high);
+                }
+                "stretch" => view.stretch = Stretch::from_text(value).ok_or_else(|| invalid(key))?,
+                "isosurface" => view.isosurface = value.parse().map_err(|_| invalid(key))?,
+                "opacity" => opacity = value.to_owned(),
+                "colors" => colors = value.to_owned(),
+                "colormap" => view.colormap = value.to_owned(),
+                "reverse_colormap" => view.reverse_colormap = value.parse().map_err(|_| invalid(key))?,
+                _ => {
+                    return Err(Error::InvalidView {
+                        reason: format!("unknown key {}", key),
+                    })
+                }
+            }
+        }
+        view.transfer = TransferFunction::from_text(&opacity, &colors).ok_or_else(|| invalid("the transfer function"))?;
+
+        Ok(view)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view() -> View {
+        View {
+            camera: (0.5, -0.25),
+            perspective: true,
+            mode: RenderMode::Dvr,
+            cuts: (-1.5, 3.0),
+            stretch: Stretch::Asinh { softening: 0.1 },
+            isosurface: 0.75,
+            transfer: TransferFunction::from_text("0:0 0.5:0.25 1:1", "0:#000000 1:#ffffff").unwrap(),
+            colormap: "viridis".to_owned(),
+            reverse_colormap: true,
+        }
+    }
+
+    #[test]
+    fn round_trip_the_view_settings() {
+        let text = view().to_text();
+        assert_eq!(text.lines().nth(3), Some("cuts = -1.5 3"));
+        assert_eq!(text.lines().nth(6), Some("opacity = 0:0 0.5:0.25 1:1"));
+
+        let other = View {
+            mode: RenderMode::Mip,
+            colormap: "jet".to_owned(),
+            transfer: TransferFunction::default(),
+            ..view()
+        };
+        assert_eq!(View::from_text(&text, &other).unwrap(), view());
+
+        // the missing keys are kept
+        let cut = View::from_text("\n  cuts = 0 1  \n", &view()).unwrap();
+        assert_eq!(cut, View { cuts: (0.0, 1.0), ..view() });
+    }
+
+    #[test]
+    fn reject_invalid_views() {
+        for text in [
+            "cuts = 2 1",
+            "cuts = 1 1",
+            "cuts = NaN 1",
+            "cuts = 0 1e40",
+            "cuts = 0",
+            "mode = mip intensity",
+            "stretch = log -1",
+            "opacity = 0:2",
+            "zoom = 2",
+            "camera",
+        ] {
+            assert!(matches!(View::from_text(text, &view()), Err(Error::InvalidView { .. })), "{}", text);
+        }
+    }
+}
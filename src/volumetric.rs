@@ -4,6 +4,55 @@ use crate::Mat4;
 use crate::Texture;
 use crate::VertexNDC;
 use crate::Vec4;
+use crate::colormap::COLORMAP_SIZE;
+use crate::stretch::STRETCH_SIZE;
+use crate::transfer::TRANSFER_SIZE;
+
+/// Way the rays cast through the cube are turned into colors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum RenderMode {
+    /// Maximum intensity projection
+    Mip,
+    /// Direct volume rendering compositing the colors and opacities of a transfer function
+    Dvr,
+    Isosurface,
+}
+
+impl RenderMode {
+    pub(crate) fn all() -> [RenderMode; 3] {
+        [RenderMode::Mip, RenderMode::Dvr, RenderMode::Isosurface]
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            RenderMode::Mip => "Maximum Intensity Projection",
+            RenderMode::Dvr => "Direct Volume Rendering",
+            RenderMode::Isosurface => "Isosurface",
+        }
+    }
+
+    /// Keyword of the mode in a stored view
+    pub(crate) fn keyword(&self) -> &'static str {
+        match self {
+            RenderMode::Mip => "mip",
+            RenderMode::Dvr => "dvr",
+            RenderMode::Isosurface => "isosurface",
+        }
+    }
+
+    pub(crate) fn from_keyword(keyword: &str) -> Option<Self> {
+        RenderMode::all().iter().copied().find(|mode| mode.keyword() == keyword)
+    }
+
+    /// Value of the render mode uniform of the volumetric shader
+    pub(crate) fn uniform(&self) -> f32 {
+        match self {
+            RenderMode::Dvr => 1.0,
+            _ => 0.0,
+        }
+    }
+}
+
 pub(crate) struct VolumetricRenderer {
     volumetric_rendering_pipeline: wgpu::RenderPipeline,
     isosurface_rendering_pipeline: wgpu::RenderPipeline,
@@ -12,6 +61,12 @@ pub(crate) struct VolumetricRenderer {
 
     texture_bind_group_layout: wgpu::BindGroupLayout,
     diffuse_bind_group: wgpu::BindGroup,
+    // transfer function of the direct volume rendering
+    transfer: Texture,
+    // colormap of the maximum intensity projection
+    colormap: Texture,
+    // histogram equalization stretch
+    stretch: Texture,
 }
 
 use std::collections::HashMap;
@@ -166,12 +221,145 @@ impl VolumetricRenderer {
                     },
                     count: None,
                 },
+                // validity mask of the voxels (NaN and BLANK)
+                wgpu::BindGroupLayoutEntry {
+                    binding: 12,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                // blank color uniform
+                wgpu::BindGroupLayoutEntry {
+                    binding: 13,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<Vec4<f32>>() as wgpu::BufferAddress,
+                        ),
+                    },
+                    count: None,
+                },
+                // brick indirection table
+                wgpu::BindGroupLayoutEntry {
+                    binding: 14,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                // bricking uniform
+                wgpu::BindGroupLayoutEntry {
+                    binding: 15,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<Vec4<f32>>() as wgpu::BufferAddress,
+                        ),
+                    },
+                    count: None,
+                },
+                // level of detail uniform
+                wgpu::BindGroupLayoutEntry {
+                    binding: 16,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<Vec4<f32>>() as wgpu::BufferAddress,
+                        ),
+                    },
+                    count: None,
+                },
+                // transfer function
+                wgpu::BindGroupLayoutEntry {
+                    binding: 17,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                // render mode uniform
+                wgpu::BindGroupLayoutEntry {
+                    binding: 18,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<Vec4<f32>>() as wgpu::BufferAddress,
+                        ),
+                    },
+                    count: None,
+                },
+                // colormap
+                wgpu::BindGroupLayoutEntry {
+                    binding: 19,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                // stretch uniform
+                wgpu::BindGroupLayoutEntry {
+                    binding: 20,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<Vec4<f32>>() as wgpu::BufferAddress,
+                        ),
+                    },
+                    count: None,
+                },
+                // histogram equalization
+                wgpu::BindGroupLayoutEntry {
+                    binding: 21,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
             ],
             label: Some("texture_bind_group_layout"),
         });
 
         let cube =
             Texture::from_raw_bytes::<f32>(&device, &queue, None, (1, 1, 1), 4, "cube").unwrap();
+        let mask =
+            Texture::from_raw_bytes::<u8>(device, queue, Some(&[0]), (1, 1, 1), 1, "mask").unwrap();
+        let bricks =
+            Texture::from_raw_bytes::<[f32; 4]>(device, queue, None, (1, 1, 1), 16, "brick table").unwrap();
+        // filled by the transfer function set by the state
+        let transfer =
+            Texture::from_raw_bytes::<[f32; 4]>(device, queue, None, (TRANSFER_SIZE as u32, 1, 1), 16, "transfer function").unwrap();
+        // filled by the colormap selected by the state
+        let colormap =
+            Texture::from_raw_bytes::<[u8; 4]>(device, queue, None, (COLORMAP_SIZE as u32, 1, 1), 4, "colormap").unwrap();
+        // filled when the histogram equalization is selected
+        let stretch =
+            Texture::from_raw_bytes::<f32>(device, queue, None, (STRETCH_SIZE as u32, 1, 1), 4, "stretch").unwrap();
 
         let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &texture_bind_group_layout,
@@ -266,6 +454,66 @@ impl VolumetricRenderer {
                         size: wgpu::BufferSize::new(32),
                     }),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: wgpu::BindingResource::TextureView(&mask.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 13,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &buffers["blank_color"],
+                        offset: 0,
+                        size: wgpu::BufferSize::new(16),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 14,
+                    resource: wgpu::BindingResource::TextureView(&bricks.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 15,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &buffers["bricking"],
+                        offset: 0,
+                        size: wgpu::BufferSize::new(16),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 16,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &buffers["lod"],
+                        offset: 0,
+                        size: wgpu::BufferSize::new(16),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 17,
+                    resource: wgpu::BindingResource::TextureView(&transfer.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 18,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &buffers["render_mode"],
+                        offset: 0,
+                        size: wgpu::BufferSize::new(16),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 19,
+                    resource: wgpu::BindingResource::TextureView(&colormap.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 20,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &buffers["stretch"],
+                        offset: 0,
+                        size: wgpu::BufferSize::new(16),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 21,
+                    resource: wgpu::BindingResource::TextureView(&stretch.view),
+                },
             ],
             label: Some("diffuse_bind_group"),
         });
@@ -426,10 +674,39 @@ impl VolumetricRenderer {
             index_buffer,
             diffuse_bind_group,
             texture_bind_group_layout,
+            transfer,
+            colormap,
+            stretch,
         }
     }
 
-    pub(crate) fn set_volume(&mut self, device: &wgpu::Device, buffers: &HashMap<&'static str, wgpu::Buffer>, volume: Texture) {
+    /// Write the entries of the transfer function, see [crate::transfer::TransferFunction::lut]
+    pub(crate) fn write_transfer(&self, queue: &wgpu::Queue, lut: &[[f32; 4]]) {
+        self.transfer.write_data(queue, (0, 0, 0), bytemuck::cast_slice(lut), (TRANSFER_SIZE as u32, 1, 1));
+    }
+
+    /// Write the entries of the colormap, see [crate::colormap::Colormap::lut]
+    pub(crate) fn write_colormap(&self, queue: &wgpu::Queue, lut: &[[u8; 4]]) {
+        self.colormap.write_data(queue, (0, 0, 0), bytemuck::cast_slice(lut), (COLORMAP_SIZE as u32, 1, 1));
+    }
+
+    /// Write the entries of the histogram equalization, see [crate::stretch::equalization]
+    pub(crate) fn write_stretch(&self, queue: &wgpu::Queue, lut: &[f32]) {
+        self.stretch.write_data(queue, (0, 0, 0), bytemuck::cast_slice(lut), (STRETCH_SIZE as u32, 1, 1));
+    }
+
+    /// Bind a new volume to render
+    ///
+    /// For bricked cubes, `volume` and `mask` are the brick atlases and `bricks` their indirection table.
+    /// Otherwise `bricks` is ignored.
+    pub(crate) fn set_volume(
+        &mut self,
+        device: &wgpu::Device,
+        buffers: &HashMap<&'static str, wgpu::Buffer>,
+        volume: &Texture,
+        mask: &Texture,
+        bricks: &Texture,
+    ) {
         self.diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &self.texture_bind_group_layout,
             entries: &[
@@ -523,12 +800,72 @@ impl VolumetricRenderer {
                         size: wgpu::BufferSize::new(32),
                     }),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: wgpu::BindingResource::TextureView(&mask.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 13,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &buffers["blank_color"],
+                        offset: 0,
+                        size: wgpu::BufferSize::new(16),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 14,
+                    resource: wgpu::BindingResource::TextureView(&bricks.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 15,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &buffers["bricking"],
+                        offset: 0,
+                        size: wgpu::BufferSize::new(16),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 16,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &buffers["lod"],
+                        offset: 0,
+                        size: wgpu::BufferSize::new(16),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 17,
+                    resource: wgpu::BindingResource::TextureView(&self.transfer.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 18,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &buffers["render_mode"],
+                        offset: 0,
+                        size: wgpu::BufferSize::new(16),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 19,
+                    resource: wgpu::BindingResource::TextureView(&self.colormap.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 20,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &buffers["stretch"],
+                        offset: 0,
+                        size: wgpu::BufferSize::new(16),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 21,
+                    resource: wgpu::BindingResource::TextureView(&self.stretch.view),
+                },
             ],
             label: Some("diffuse_bind_group"),
         });
     }
 
-    pub(crate) fn render_frame(&self, encoder: &mut wgpu::CommandEncoder, window_surface_view: &TextureView, show_isosurface: bool) {
+    pub(crate) fn render_frame(&self, encoder: &mut wgpu::CommandEncoder, window_surface_view: &TextureView, mode: RenderMode) {
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
@@ -551,7 +888,7 @@ impl VolumetricRenderer {
             //multiview_mask: None,
         });
 
-        if show_isosurface {
+        if mode == RenderMode::Isosurface {
             render_pass.set_pipeline(&self.isosurface_rendering_pipeline);
         } else {
             render_pass.set_pipeline(&self.volumetric_rendering_pipeline);